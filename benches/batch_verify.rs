@@ -0,0 +1,48 @@
+use ark_ec::ProjectiveCurve;
+use ark_ff::UniformRand;
+use barnett_smart_card_protocol::BarnettSmartProtocol;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use example_api::deck::batch::{self, ProofClaim};
+use proof_essentials::homomorphic_encryption::el_gamal;
+use rand::thread_rng;
+
+type Curve = starknet_curve::Projective;
+type Scalar = starknet_curve::Fr;
+type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
+type Parameters = <CardProtocol as BarnettSmartProtocol>::Parameters;
+
+// reveal tokens of one player for a whole deck, what open_cards checks per player
+fn reveal_claims(parameters: &Parameters, count: usize) -> Vec<ProofClaim> {
+    let rng = &mut thread_rng();
+    let (public_key, private_key) = CardProtocol::player_keygen(rng, parameters).unwrap();
+    (0..count).map(|_| {
+        let card = el_gamal::Plaintext(Curve::rand(rng).into_affine());
+        let (masked_card, _) = CardProtocol::mask(rng, parameters, &public_key, &card, &Scalar::rand(rng)).unwrap();
+        let (reveal_token, proof) = CardProtocol::compute_reveal_token(rng, parameters, &private_key, &public_key, &masked_card).unwrap();
+        ProofClaim::Reveal { public_key, reveal_token, masked_card, proof }
+    }).collect()
+}
+
+// one by one is how open_cards checked tokens before batching
+fn verify_one_by_one(parameters: &Parameters, claims: &[ProofClaim]) -> bool {
+    claims.iter().all(|claim| match claim {
+        ProofClaim::Reveal { public_key, reveal_token, masked_card, proof } =>
+            CardProtocol::verify_reveal(parameters, public_key, reveal_token, masked_card, proof).is_ok(),
+        ProofClaim::Mask { joint_key, card, masked_card, proof } =>
+            CardProtocol::verify_mask(parameters, joint_key, card, masked_card, proof).is_ok(),
+    })
+}
+
+fn reveal_proofs(c: &mut Criterion) {
+    let parameters = CardProtocol::setup(&mut thread_rng(), 2, 26).unwrap();
+    let mut group = c.benchmark_group("reveal proofs");
+    for count in [16, 52, 208] {
+        let claims = reveal_claims(&parameters, count);
+        group.bench_with_input(BenchmarkId::new("one by one", count), &claims, |b, claims| b.iter(|| verify_one_by_one(&parameters, claims)));
+        group.bench_with_input(BenchmarkId::new("batched", count), &claims, |b, claims| b.iter(|| batch::verify_claims(&parameters, claims)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, reveal_proofs);
+criterion_main!(benches);
@@ -0,0 +1,56 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+use example_api::deck::transcript::{self, GameTranscript};
+
+// Re-runs every check of a game transcript offline and prints a verdict per step. The log an
+// agent exports for the game is accepted too and rebuilt into a transcript first.
+// Exits with 1 if any step fails and 2 if the transcript can't be read.
+//
+//     deck-verify transcript.json
+//     deck-verify - < transcript.json
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: deck-verify <transcript.json | ->");
+            process::exit(2)
+        }
+    };
+    let json = if path == "-" {
+        let mut json = String::new();
+        io::stdin().read_to_string(&mut json).map(|_| json)
+    } else {
+        fs::read_to_string(&path)
+    };
+    let json = match json {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", path, e);
+            process::exit(2)
+        }
+    };
+    let game_transcript: GameTranscript = match transcript::from_json(&json) {
+        Ok(game_transcript) => game_transcript,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(2)
+        }
+    };
+
+    let report = transcript::verify(&game_transcript);
+    for step in report.iter() {
+        let verdict = if step.passed { "PASS" } else { "FAIL" };
+        match &step.detail {
+            Some(detail) => println!("{} {}: {}", verdict, step.step, detail),
+            None => println!("{} {}", verdict, step.step),
+        }
+    }
+    let failed = report.iter().filter(|step| !step.passed).count();
+    if failed > 0 {
+        println!("game {}: {} of {} checks failed", game_transcript.game_id, failed, report.len());
+        process::exit(1)
+    }
+    println!("game {}: all {} checks passed", game_transcript.game_id, report.len());
+}
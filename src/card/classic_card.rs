@@ -27,6 +27,7 @@ pub enum Value {
     Queen,
     King,
     Ace,
+    Joker,
 }
 
 impl Value {
@@ -57,6 +58,34 @@ impl ClassicPlayingCard {
     pub fn new(value: Value, suite: Suite) -> Self {
         Self { value, suite }
     }
+
+    // black joker is carried as a spade, red joker as a heart
+    pub fn jokers() -> [Self; 2] {
+        [Self::new(Value::Joker, Suite::Spade), Self::new(Value::Joker, Suite::Heart)]
+    }
+
+    // card faces of the supported deck sizes, in the order they are encoded:
+    // 32 (Skat/Piquet, 7..A), 36 (6..A), 52 (standard), 54 (standard + 2 jokers), 104 (double deck)
+    pub fn deck_of(num_of_cards: usize) -> Option<Vec<Self>> {
+        let lowest = match num_of_cards {
+            32 => Value::Seven,
+            36 => Value::Six,
+            52 | 54 | 104 => Value::Two,
+            _ => return None,
+        };
+        let mut cards = Vec::with_capacity(num_of_cards);
+        for value in Value::VALUES.iter().copied().filter(|v| *v >= lowest) {
+            for suite in Suite::VALUES.iter().copied() {
+                cards.push(Self::new(value, suite));
+            }
+        }
+        match num_of_cards {
+            54 => cards.extend_from_slice(&Self::jokers()),
+            104 => cards.extend_from_within(..),
+            _ => {}
+        }
+        Some(cards)
+    }
 }
 
 impl std::fmt::Debug for ClassicPlayingCard {
@@ -82,6 +111,7 @@ impl std::fmt::Debug for ClassicPlayingCard {
             Value::Queen => "Q",
             Value::King => "K",
             Value::Ace => "A",
+            Value::Joker => "Joker",
         };
 
         write!(f, "{}{}", val, suite)
@@ -0,0 +1,53 @@
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ff::PrimeField;
+use proof_essentials::homomorphic_encryption::el_gamal;
+use crate::deck::batch;
+use crate::keys::secret::SecretScalar;
+
+type Curve = starknet_curve::Projective;
+type Affine = starknet_curve::Affine;
+type Card = barnett_smart_card_protocol::discrete_log_cards::Card<Curve>;
+type MaskedCard = barnett_smart_card_protocol::discrete_log_cards::MaskedCard<Curve>;
+
+// a disclosed key is only worth something if it is the discrete log of the key set up with
+pub fn key_matches(generator: Affine, public_key: &Affine, private_key: &SecretScalar) -> bool {
+    generator.mul(private_key.expose().into_repr()).into_affine() == *public_key
+}
+
+// with every player's key the auditor computes all reveal tokens itself, so a
+// position decrypts without trusting any token handed out during the hand
+pub fn decrypt(private_keys: &[SecretScalar], masked_card: &MaskedCard) -> Card {
+    let reveal_tokens: Vec<_> = private_keys.iter()
+        .map(|private_key| el_gamal::Plaintext(masked_card.0.mul(private_key.expose().into_repr()).into_affine()))
+        .collect();
+    batch::unmask_verified(&reveal_tokens, masked_card)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use ark_ff::UniformRand;
+    use barnett_smart_card_protocol::BarnettSmartProtocol;
+    use rand::thread_rng;
+
+    type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
+    type Scalar = starknet_curve::Fr;
+
+    #[test]
+    fn test_all_disclosed_keys_decrypt_the_deck() {
+        let rng = &mut thread_rng();
+        let parameters = CardProtocol::setup(rng, 2, 26).unwrap();
+        let generator = parameters.enc_parameters.generator;
+        let (pk1, sk1) = CardProtocol::player_keygen(rng, &parameters).unwrap();
+        let (pk2, sk2) = CardProtocol::player_keygen(rng, &parameters).unwrap();
+        let keys = [SecretScalar::new(sk1), SecretScalar::new(sk2)];
+        assert!(key_matches(generator, &pk1, &keys[0]));
+        assert!(!key_matches(generator, &pk2, &keys[0]));
+
+        let joint_key = (pk1.into_projective() + pk2.into_projective()).into_affine();
+        let card = el_gamal::Plaintext(Curve::rand(rng).into_affine());
+        let (masked_card, _proof) = CardProtocol::mask(rng, &parameters, &joint_key, &card, &Scalar::rand(rng)).unwrap();
+        assert_eq!(decrypt(&keys, &masked_card), card);
+        assert_ne!(decrypt(&keys[..1], &masked_card), card);
+    }
+}
@@ -0,0 +1,190 @@
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ff::{PrimeField, UniformRand, Zero};
+use ark_serialize::CanonicalSerialize;
+use barnett_smart_card_protocol::BarnettSmartProtocol;
+use merlin::Transcript;
+use proof_essentials::homomorphic_encryption::el_gamal;
+use rand::thread_rng;
+use rayon::prelude::*;
+use crate::deck::errors::DeckCustomError;
+
+type Curve = starknet_curve::Projective;
+type Affine = starknet_curve::Affine;
+type Scalar = starknet_curve::Fr;
+type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
+type Parameters = <CardProtocol as BarnettSmartProtocol>::Parameters;
+type PublicKey = <CardProtocol as BarnettSmartProtocol>::PlayerPublicKey;
+type Card = barnett_smart_card_protocol::discrete_log_cards::Card<Curve>;
+type MaskedCard = barnett_smart_card_protocol::discrete_log_cards::MaskedCard<Curve>;
+type RevealToken = barnett_smart_card_protocol::discrete_log_cards::RevealToken<Curve>;
+type DLEqualityProof = proof_essentials::zkp::proofs::chaum_pedersen_dl_equality::proof::Proof<Curve>;
+
+// proofs verified together in one random linear combination, chunks run on separate threads
+const CHUNK_SIZE: usize = 16;
+
+// A Chaum-Pedersen claim log_g(x) == log_h(y) together with its proof.
+#[derive(Clone)]
+pub enum ProofClaim {
+    Reveal {
+        public_key: PublicKey,
+        reveal_token: RevealToken,
+        masked_card: MaskedCard,
+        proof: DLEqualityProof,
+    },
+    Mask {
+        joint_key: PublicKey,
+        card: Card,
+        masked_card: MaskedCard,
+        proof: DLEqualityProof,
+    },
+}
+
+impl ProofClaim {
+    // verify on its own through the protocol, this is the reference result
+    fn verify(&self, parameters: &Parameters) -> bool {
+        match self {
+            ProofClaim::Reveal { public_key, reveal_token, masked_card, proof } =>
+                CardProtocol::verify_reveal(parameters, public_key, reveal_token, masked_card, proof).is_ok(),
+            ProofClaim::Mask { joint_key, card, masked_card, proof } =>
+                CardProtocol::verify_mask(parameters, joint_key, card, masked_card, proof).is_ok(),
+        }
+    }
+
+    // (g, h, x, y) of the discrete log equality statement
+    fn statement(&self, parameters: &Parameters) -> (Affine, Affine, Affine, Affine) {
+        let generator = parameters.enc_parameters.generator;
+        match self {
+            ProofClaim::Reveal { public_key, reveal_token, masked_card, .. } =>
+                (generator, masked_card.0, *public_key, reveal_token.0),
+            ProofClaim::Mask { joint_key, card, masked_card, .. } =>
+                (generator, *joint_key, masked_card.0, (masked_card.1.into_projective() - card.0.into_projective()).into_affine()),
+        }
+    }
+
+    fn proof(&self) -> &DLEqualityProof {
+        match self {
+            ProofClaim::Reveal { proof, .. } | ProofClaim::Mask { proof, .. } => proof,
+        }
+    }
+}
+
+// Verdict per claim, in input order.
+//
+// Each chunk is first checked with a single random linear combination of all its
+// verification equations, g*r_i == a_i + x_i*c_i and h*r_i == b_i + y_i*c_i, weighted
+// by random scalars. A forged proof only passes with negligible probability. If a
+// chunk fails, its claims are verified one by one to find the offenders.
+pub fn verify_claims(parameters: &Parameters, claims: &[ProofClaim]) -> Vec<bool> {
+    claims
+        .par_chunks(CHUNK_SIZE)
+        .flat_map_iter(|chunk| {
+            if chunk.len() > 1 && verify_linear_combination(parameters, chunk) {
+                vec![true; chunk.len()]
+            } else {
+                chunk.iter().map(|claim| claim.verify(parameters)).collect()
+            }
+        })
+        .collect()
+}
+
+fn verify_linear_combination(parameters: &Parameters, claims: &[ProofClaim]) -> bool {
+    let rng = &mut thread_rng();
+    let mut sum = Curve::zero();
+    for claim in claims {
+        let (g, h, x, y) = claim.statement(parameters);
+        let proof = claim.proof();
+        let c = match challenge(g, h, x, y, proof) {
+            Ok(c) => c,
+            Err(_e) => return false,
+        };
+        let r = proof.r.into_repr();
+        let first = g.mul(r) - proof.a - x.mul(c.into_repr());
+        let second = h.mul(r) - proof.b - y.mul(c.into_repr());
+        sum += first.mul(Scalar::rand(rng).into_repr());
+        sum += second.mul(Scalar::rand(rng).into_repr());
+    }
+    sum.is_zero()
+}
+
+// Fiat-Shamir challenge of the Chaum-Pedersen proof, derived from the same transcript the prover used
+fn challenge(g: Affine, h: Affine, x: Affine, y: Affine, proof: &DLEqualityProof) -> Result<Scalar, DeckCustomError> {
+    let mut transcript = Transcript::new(b"chaum_pedersen");
+    for (label, point) in [(b"g", g), (b"h", h), (b"x", x), (b"y", y)] {
+        transcript.append_message(label, &serialize_point(&point)?);
+    }
+    transcript.append_message(b"a", &serialize_point(&proof.a.into_affine())?);
+    transcript.append_message(b"b", &serialize_point(&proof.b.into_affine())?);
+
+    let mut buf = [0u8; 64];
+    transcript.challenge_bytes(b"c", &mut buf);
+    Ok(Scalar::from_le_bytes_mod_order(&buf))
+}
+
+fn serialize_point(point: &Affine) -> Result<Vec<u8>, DeckCustomError> {
+    let mut bytes = Vec::new();
+    match point.serialize(&mut bytes) {
+        Ok(()) => Ok(bytes),
+        Err(e) => Err(DeckCustomError::SerializationError(e.to_string())),
+    }
+}
+
+// c2 - sum(tokens), only for tokens already accepted by verify_claims
+pub fn unmask_verified(reveal_tokens: &[RevealToken], masked_card: &MaskedCard) -> Card {
+    let mut aggregate_token = Curve::zero();
+    for reveal_token in reveal_tokens {
+        aggregate_token += reveal_token.0.into_projective();
+    }
+    el_gamal::Plaintext((masked_card.1.into_projective() - aggregate_token).into_affine())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    // reveal and masking claims alternating, all of them valid
+    fn valid_claims(count: usize) -> (Parameters, Vec<ProofClaim>) {
+        let rng = &mut thread_rng();
+        let parameters = CardProtocol::setup(rng, 2, 16).unwrap();
+        let (public_key, private_key) = CardProtocol::player_keygen(rng, &parameters).unwrap();
+        let mut claims = Vec::with_capacity(count);
+        for index in 0..count {
+            let card = el_gamal::Plaintext(Curve::rand(rng).into_affine());
+            let (masked_card, mask_proof) = CardProtocol::mask(rng, &parameters, &public_key, &card, &Scalar::rand(rng)).unwrap();
+            if index % 2 == 0 {
+                let (reveal_token, proof) = CardProtocol::compute_reveal_token(rng, &parameters, &private_key, &public_key, &masked_card).unwrap();
+                claims.push(ProofClaim::Reveal { public_key, reveal_token, masked_card, proof });
+            } else {
+                claims.push(ProofClaim::Mask { joint_key: public_key, card, masked_card, proof: mask_proof });
+            }
+        }
+        (parameters, claims)
+    }
+
+    #[test]
+    fn test_valid_chunk_passes_the_combined_check() {
+        let (parameters, claims) = valid_claims(CHUNK_SIZE);
+        assert!(claims.iter().all(|claim| claim.verify(&parameters)));
+        // no fallback, the challenges match the ones the prover derived
+        assert!(verify_linear_combination(&parameters, &claims));
+        assert_eq!(verify_claims(&parameters, &claims), vec![true; CHUNK_SIZE]);
+    }
+
+    #[test]
+    fn test_forged_proof_is_singled_out() {
+        let (parameters, mut claims) = valid_claims(2 * CHUNK_SIZE + 3);
+        let forged = CHUNK_SIZE + 4;
+        // a valid proof, but of another reveal token
+        let other_proof = claims[forged + 2].proof().clone();
+        if let ProofClaim::Reveal { proof, .. } = &mut claims[forged] {
+            *proof = other_proof;
+        }
+        assert!(verify_linear_combination(&parameters, &claims[..CHUNK_SIZE]));
+        assert!(!verify_linear_combination(&parameters, &claims[CHUNK_SIZE..2 * CHUNK_SIZE]));
+
+        let verdicts = verify_claims(&parameters, &claims);
+        assert_eq!(verdicts.len(), claims.len());
+        for (index, valid) in verdicts.into_iter().enumerate() {
+            assert_eq!(valid, index != forged, "verdict of claim {}", index);
+        }
+    }
+}
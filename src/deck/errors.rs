@@ -1,5 +1,6 @@
 use core::fmt;
 use std::error::Error;
+use crate::user::errors::CustomError;
 
 #[derive(Debug)]
 #[derive(PartialEq)]
@@ -13,6 +14,23 @@ pub enum DeckCustomError {
     SerializationError(String),
     InvalidSeed,
     InvalidRevealToken,
+    InvalidGeometry(String),
+    InvalidDeckSize(String),
+    GameNotFound,
+    CardNotInDeck,
+    GameMismatch(String),
+    DuplicatePlayer(String),
+    DuplicatePublicKey(String),
+    IdentityPublicKey(String),
+    OwnKeyMissing(String),
+    InvalidShuffleStep{ index: usize, game_user_id: String, reason: String },
+    OutOfOrder(String),
+    StateMismatch(String),
+    RevealNotAllowed(String),
+    KeyEncryption(String),
+    SeedAgreement(String),
+    InvalidKeyShare(String),
+    AuditFailed(String),
 }
 
 impl fmt::Display for DeckCustomError {
@@ -27,6 +45,23 @@ impl fmt::Display for DeckCustomError {
             DeckCustomError::InvalidSeed => write!(f, "invalid seed"),
             DeckCustomError::InvalidCard => write!(f, "invalid card"),
             DeckCustomError::InvalidRevealToken=> write!(f, "invald token"),
+            DeckCustomError::InvalidGeometry(msg) => write!(f, "invalid deck geometry: {}", msg),
+            DeckCustomError::InvalidDeckSize(msg) => write!(f, "invalid deck size: {}", msg),
+            DeckCustomError::GameNotFound => write!(f, "Game not found"),
+            DeckCustomError::CardNotInDeck => write!(f, "unmasked card is not part of the game deck"),
+            DeckCustomError::GameMismatch(game_user_id) => write!(f, "player {} belongs to another game", game_user_id),
+            DeckCustomError::DuplicatePlayer(game_user_id) => write!(f, "player {} appears more than once", game_user_id),
+            DeckCustomError::DuplicatePublicKey(game_user_id) => write!(f, "public key of player {} is already used by another player", game_user_id),
+            DeckCustomError::IdentityPublicKey(game_user_id) => write!(f, "player {} submitted the identity point as public key", game_user_id),
+            DeckCustomError::OwnKeyMissing(game_user_id) => write!(f, "own player {} is missing from the aggregate key", game_user_id),
+            DeckCustomError::InvalidShuffleStep{ index, game_user_id, reason } => write!(f, "shuffle step {} by player {} is invalid: {}", index, game_user_id, reason),
+            DeckCustomError::OutOfOrder(msg) => write!(f, "operation out of order: {}", msg),
+            DeckCustomError::StateMismatch(msg) => write!(f, "request does not match the game state: {}", msg),
+            DeckCustomError::RevealNotAllowed(msg) => write!(f, "reveal token refused: {}", msg),
+            DeckCustomError::KeyEncryption(msg) => write!(f, "key encryption error: {}", msg),
+            DeckCustomError::SeedAgreement(msg) => write!(f, "seed agreement failed: {}", msg),
+            DeckCustomError::InvalidKeyShare(msg) => write!(f, "invalid key share: {}", msg),
+            DeckCustomError::AuditFailed(msg) => write!(f, "audit failed: {}", msg),
         }
     }
 }
@@ -37,4 +72,14 @@ impl From<mongodb::error::Error> for DeckCustomError {
     }
 }
 
+impl From<CustomError> for DeckCustomError {
+    fn from(err: CustomError) -> Self {
+        match err {
+            CustomError::UserNotFound => DeckCustomError::UserNotFound,
+            CustomError::UserAlreadyExists => DeckCustomError::StateMismatch(String::from("player is already set up")),
+            err => DeckCustomError::GenericError(err.to_string()),
+        }
+    }
+}
+
 impl Error for DeckCustomError {}
\ No newline at end of file
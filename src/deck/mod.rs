@@ -2,4 +2,10 @@ pub mod routes;
 pub mod service;
 pub mod errors;
 pub mod repository;
+pub mod params_cache;
+pub mod batch;
+pub mod sweeper;
+pub mod seed_agreement;
+pub mod audit;
+pub mod transcript;
 mod models;
\ No newline at end of file
@@ -11,10 +11,12 @@ use proof_essentials::zkp::proofs::{chaum_pedersen_dl_equality, schnorr_identifi
 use serde::{Serialize, Deserialize};
 use starknet_curve::StarkwareParameters;
 use crate::card::classic_card::ClassicPlayingCard;
+use crate::game::models::transcript::TranscriptEntry;
 
 type Curve = starknet_curve::Projective;
 type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
 // type Card = barnett_smart_card_protocol::discrete_log_cards::Card<Curve>;
+type PublicKey = GroupAffine<StarkwareParameters>;
 
 type ProofKeyOwnership = schnorr_identification::proof::Proof<Curve>;
 type RemaskingProof = chaum_pedersen_dl_equality::proof::Proof<Curve>;
@@ -22,8 +24,9 @@ type RevealProof = chaum_pedersen_dl_equality::proof::Proof<Curve>;
 use crate::deck::errors::DeckCustomError;
 use crate::deck::models::deck::Deck;
 use crate::game_user::models::game_user::GameUser;
+use crate::keys::vss::KeyDealing;
 use crate::serialize::proof::{IdentityProof, PedersenProof};
-use crate::serialize::serialize::{decode_masked_card, decode_masking_proof, decode_revel_proof, decode_revel_token, encode_masked_card, encode_masking_proof, encode_revel_token};
+use crate::serialize::serialize::{decode_deck_public_key, decode_masked_card, decode_masking_proof, decode_revel_proof, decode_revel_token, encode_masked_card, encode_masking_proof, encode_revel_proof, encode_revel_token};
 
 type MaskedCard = barnett_smart_card_protocol::discrete_log_cards::MaskedCard<Curve>;
 type RevealToken = barnett_smart_card_protocol::discrete_log_cards::RevealToken<Curve>;
@@ -35,8 +38,10 @@ type Parameters = <DLCards<ark_ec::short_weierstrass_jacobian::GroupProjective<S
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InitialDeckRequest {
-    // pub m:usize,
-    // pub n:usize,
+    pub m:usize,
+    pub n:usize,
+    // recompute the deck of an existing seed instead of drawing a new one
+    pub seed_hex: Option<String>,
 }
 
 // #[derive(Debug, Serialize, Deserialize)]
@@ -76,6 +81,8 @@ pub struct InitialCard{
 pub struct InitialDeckResponse {
     pub cards: Vec<InitialCard>,
     pub seed_hex: String,
+    pub m:usize,
+    pub n:usize,
 }
 
 
@@ -86,8 +93,12 @@ pub struct SetUpDeckRequest{
     pub game_id: String,
     pub game_user_id: String, // user identity among this round
     pub seed_hex: String,
-    // pub m:usize,
-    // pub n:usize,
+    pub m:usize,
+    pub n:usize,
+    // split the key into Feldman shares for the other players, any threshold of them
+    // can stand in for this player if its agent drops out
+    #[serde(default)]
+    pub share_threshold: Option<usize>,
 }
 #[derive(Debug,Clone, Serialize, Deserialize)]
 pub struct Proof{
@@ -111,7 +122,7 @@ pub struct SetUpDeckResponse{
     pub user_key_proof:Proof,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Player{
     pub game_id:String,
     pub game_user_id:String,
@@ -121,6 +132,7 @@ pub struct Player{
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ComputeAggregateKeyRequest {
+    pub game_id: String,
     pub players: Vec<Player>,
     pub seed_hex: String,
 }
@@ -129,6 +141,9 @@ pub struct ComputeAggregateKeyRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ComputeAggregateKeyResponse{
     pub joined_key: String, // if accept other player's proof, return the joined key of all player
+    // key shares of our players that opted in at setup, to be passed to the other agents
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_dealings: Vec<KeyDealing>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -224,6 +239,8 @@ pub struct GenerateDeckResponse{
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ShuffleRequest{
+    pub game_id: String,
+    pub game_user_id: String, // shuffler, a local player of the game
     pub seed_hex: String,
     pub joined_key: String,
     pub cards: Vec<String>,
@@ -236,6 +253,8 @@ pub struct ShuffleResponse{
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VerifyShuffleRequest{
+    pub game_id: String,
+    pub game_user_id: String, // shuffler
     pub joined_key: String,
     pub seed_hex: String,
     pub proof: String,
@@ -247,10 +266,64 @@ pub struct VerifyShuffleResponse{
 
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShuffleStepDTO{
+    pub game_user_id: String, // shuffler
+    pub shuffled_cards: Vec<String>,
+    pub proof: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyShuffleChainRequest{
+    pub game_id: String,
+    pub joined_key: String,
+    pub seed_hex: String,
+    pub initial_cards: Vec<String>, // masked deck before the first shuffle
+    pub steps: Vec<ShuffleStepDTO>,
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyShuffleChainResponse{
+    pub final_cards: Vec<String>,
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemaskRequest{
+    pub game_id: String,
+    pub seed_hex: String,
+    pub joined_key: String,
+    pub cards: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize,Clone)]
+pub struct RemaskedCardDTO {
+    pub origin_card: String,
+    pub masked_card: String,
+    pub proof: PedersenProofDTO,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemaskResponse{
+    pub cards: Vec<RemaskedCardDTO>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyRemaskRequest{
+    pub game_id: String,
+    pub seed_hex: String,
+    pub joined_key: String,
+    pub cards: Vec<RemaskedCardDTO>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyRemaskResponse{
+
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RevealCardsRequest{
     pub game_user_id:String,
+    pub seed_hex: String,
     pub shuffled_deck: ShuffledDeck,
 }
 #[derive(Debug, Serialize, Deserialize)]
@@ -265,12 +338,284 @@ pub struct RevealTokenRequest {
     pub seed_hex: String,
     pub reveal_cards: Vec<String>,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize,Clone)]
 pub struct RevealTokenDTO{
     pub token: String,
     pub proof: PedersenProofDTO,
     pub public_key: String,
 }
+
+impl RevealTokenDTO {
+    pub fn into_reveal_token(self)-> Result<(RevealToken, RevealProof, PublicKey),DeckCustomError>{
+        let reveal_token = decode_revel_token(self.token)?;
+        let proof = PedersenProof{
+            a: self.proof.a,
+            b: self.proof.b,
+            r: self.proof.r,
+        }.to_curve()?;
+        let public_key = decode_deck_public_key(self.public_key)?;
+        Ok((reveal_token, proof, public_key))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevealTokenCheckDTO{
+    pub masked_card: String,
+    pub reveal_token: RevealTokenDTO,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyRevealTokensRequest{
+    pub game_id: String,
+    pub seed_hex: String,
+    pub tokens: Vec<RevealTokenCheckDTO>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevealTokenVerdictDTO{
+    pub masked_card: String,
+    pub public_key: String, // the player who issued the token
+    pub valid: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyRevealTokensResponse{
+    pub results: Vec<RevealTokenVerdictDTO>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CardAssignmentDTO{
+    pub position: usize, // index in the final shuffled deck
+    pub game_user_id: Option<String>, // none for a board card
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DealRequest{
+    pub game_id: String,
+    pub game_user_id: String, // our player recording the deal
+    pub cards: Vec<CardAssignmentDTO>,
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DealResponse{
+    pub dealt: usize, // positions dealt so far
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShowdownResponse{
+
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeedCommitRequest{
+    pub game_id: String,
+    pub game_user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedCommitmentDTO{
+    pub game_user_id: String,
+    pub commitment: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeedRevealRequest{
+    pub game_id: String,
+    pub game_user_id: String,
+    // commitments of every participant, including our own
+    pub commitments: Vec<SeedCommitmentDTO>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedRevealDTO{
+    pub game_user_id: String,
+    pub commitment: String,
+    pub contribution: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeedCombineRequest{
+    pub game_id: String,
+    pub reveals: Vec<SeedRevealDTO>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeedCombineResponse{
+    pub game_id: String,
+    pub seed_hex: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyKeySharesRequest{
+    pub game_id: String,
+    pub seed_hex: String,
+    // our local player the shares are encrypted to
+    pub game_user_id: String,
+    pub dealings: Vec<KeyDealing>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyShareVerdictDTO{
+    pub dealer: String,
+    pub valid: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyKeySharesResponse{
+    pub results: Vec<KeyShareVerdictDTO>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartialRevealTokenDTO{
+    pub card: String,
+    // index of the share the token was computed with
+    pub index: u64,
+    pub token: String,
+    pub proof: PedersenProofDTO,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartialRevealTokensRequest{
+    pub game_id: String,
+    pub seed_hex: String,
+    // our local player holding a share
+    pub game_user_id: String,
+    // the player that dropped out
+    pub dealer_game_user_id: String,
+    pub cards: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartialRevealTokensResponse{
+    pub dealer_game_user_id: String,
+    pub partial_tokens: Vec<PartialRevealTokenDTO>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRecoveryDTO{
+    pub dealer_game_user_id: String,
+    // at least threshold partial tokens per card, from distinct share holders
+    pub partial_tokens: Vec<PartialRevealTokenDTO>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoverRevealTokensRequest{
+    pub game_id: String,
+    pub seed_hex: String,
+    pub recovery: KeyRecoveryDTO,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoverRevealTokensResponse{
+    pub dealer_game_user_id: String,
+    pub public_key: String,
+    // reveal token of the dealer keyed by masked card
+    pub tokens: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FinishGameResponse{
+    pub game_id: String,
+    // local players whose keys are kept until disclose_key or the sweep
+    pub held_keys: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisclosedKeyDTO{
+    pub game_user_id: String,
+    pub public_key: String,
+    pub private_key: String,
+    // key ownership proof under the setup context, it verifies like the one from setup
+    pub user_key_proof: Proof,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiscloseKeyResponse{
+    pub game_id: String,
+    pub keys: Vec<DisclosedKeyDTO>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevealedCardClaimDTO{
+    pub position: usize,
+    pub card: ClassicPlayingCard,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditGameRequest{
+    // disclosed keys of every player of the joint key
+    pub keys: Vec<DisclosedKeyDTO>,
+    // cards the table opened during the hand, by deck position
+    #[serde(default)]
+    pub revealed_cards: Vec<RevealedCardClaimDTO>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyAuditDTO{
+    pub game_user_id: String,
+    pub valid: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PositionAuditDTO{
+    pub position: usize,
+    pub masked_card: String,
+    // what the disclosed keys decrypt the position to
+    pub card: Option<ClassicPlayingCard>,
+    // what the table revealed for it, if anything
+    pub revealed: Option<ClassicPlayingCard>,
+    pub valid: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditGameResponse{
+    pub game_id: String,
+    pub all_valid: bool,
+    pub keys: Vec<KeyAuditDTO>,
+    // empty unless every player's key checks out
+    pub positions: Vec<PositionAuditDTO>,
+}
+
+// versioned export of the messages this agent handled for a game, head is the hash of the last entry
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GameTranscriptLogResponse{
+    pub version: u32,
+    pub game_id: String,
+    pub head: String,
+    pub entries: Vec<TranscriptEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MaskCheckDTO{
+    pub card: String, // initial card
+    pub masked_card: MaskedCardAndProofDTO,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MaskVerdictDTO{
+    pub masked_card: String,
+    pub valid: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchVerifyRequest{
+    pub game_id: String,
+    pub seed_hex: String,
+    pub joined_key: Option<String>, // required when masks are checked
+    pub reveal_tokens: Vec<RevealTokenCheckDTO>,
+    pub masks: Vec<MaskCheckDTO>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchVerifyResponse{
+    pub all_valid: bool,
+    pub reveal_tokens: Vec<RevealTokenVerdictDTO>,
+    pub masks: Vec<MaskVerdictDTO>,
+}
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RevealTokenResponse {
     pub token_map: HashMap<String, RevealTokenDTO>,
@@ -280,7 +625,13 @@ pub struct RevealTokenResponse {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OpenCardsRequest{
+    pub game_id: String,
+    pub seed_hex: String,
     pub shuffled_deck: ShuffledDeck,
+    pub reveal_tokens: HashMap<String, Vec<RevealTokenDTO>>, // every player's token, keyed by masked card
+    // partial tokens standing in for players that dropped out
+    #[serde(default)]
+    pub recoveries: Vec<KeyRecoveryDTO>,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OpenCardsResponse{
@@ -310,7 +661,7 @@ impl RevealedDeck {
 
             let revel_token_hex = encode_revel_token(deck_and_proof.1)?;
 
-            let proof_hex = encode_masking_proof(deck_and_proof.2)?;
+            let proof_hex = encode_revel_proof(deck_and_proof.2)?;
 
             cards.push(RevealedCardAndProofDTO {
                 masked_card: MaskedCardDTO{
@@ -352,12 +703,13 @@ pub struct PeekCardsRequest {
 
 #[derive(Debug, Serialize, Deserialize,Clone)]
 pub struct PeekCardsResponse {
-    pub card_map: HashMap<String,String>
+    pub card_map: HashMap<String,ClassicPlayingCard>
 }
 
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MaskRequest{
+    pub game_id: String,
     pub seed_hex: String,
     pub joined_key: String,
     pub cards: Vec<String>,
@@ -375,6 +727,20 @@ pub struct MaskResponse{
 }
 
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyMaskRequest{
+    pub game_id: String,
+    pub seed_hex: String,
+    pub joined_key: String,
+    pub cards: Vec<String>, // initial cards, in the order they were masked
+    pub masked_cards: Vec<MaskedCardAndProofDTO>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyMaskResponse{
+
+}
+
 #[derive(Debug, Serialize, Deserialize,Clone)]
 pub struct MaskDeck {
     pub cards: Vec<MaskedCardAndProofDTO>,
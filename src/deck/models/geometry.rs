@@ -0,0 +1,60 @@
+use serde::{Serialize, Deserialize};
+use crate::card::classic_card::ClassicPlayingCard;
+use crate::deck::errors::DeckCustomError;
+
+// (m, n) as passed to CardProtocol::setup, a game is played with m * n cards
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DeckGeometry {
+    pub m: usize,
+    pub n: usize,
+}
+
+impl DeckGeometry {
+    pub const CLASSIC: DeckGeometry = DeckGeometry { m: 2, n: 26 };
+
+    pub fn new(m: usize, n: usize) -> Result<Self, DeckCustomError> {
+        let geometry = DeckGeometry { m, n };
+        if m == 0 || n == 0 {
+            return Err(DeckCustomError::InvalidGeometry(format!("m and n must be positive, got {}x{}", m, n)));
+        }
+        if ClassicPlayingCard::deck_of(geometry.num_of_cards()).is_none() {
+            return Err(DeckCustomError::InvalidGeometry(format!("unsupported deck of {} cards", geometry.num_of_cards())));
+        }
+        Ok(geometry)
+    }
+
+    pub fn num_of_cards(&self) -> usize {
+        self.m * self.n
+    }
+
+    pub fn check_deck_len(&self, len: usize) -> Result<(), DeckCustomError> {
+        if len != self.num_of_cards() {
+            return Err(DeckCustomError::InvalidDeckSize(format!("expected {} cards, got {}", self.num_of_cards(), len)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_supported_geometries() {
+        for (m, n) in [(2, 16), (4, 9), (2, 26), (2, 27), (4, 26)] {
+            let geometry = DeckGeometry::new(m, n).unwrap();
+            let cards = ClassicPlayingCard::deck_of(geometry.num_of_cards()).unwrap();
+            assert_eq!(cards.len(), geometry.num_of_cards());
+        }
+    }
+
+    #[test]
+    fn test_unsupported_geometry() {
+        assert!(matches!(DeckGeometry::new(3, 7), Err(DeckCustomError::InvalidGeometry(_))));
+        assert!(matches!(DeckGeometry::new(0, 52), Err(DeckCustomError::InvalidGeometry(_))));
+        assert_eq!(
+            DeckGeometry::CLASSIC.check_deck_len(51),
+            Err(DeckCustomError::InvalidDeckSize(String::from("expected 52 cards, got 51")))
+        );
+    }
+}
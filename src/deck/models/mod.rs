@@ -1,2 +1,3 @@
 pub mod deck_case;
-pub mod deck;
\ No newline at end of file
+pub mod deck;
+pub mod geometry;
\ No newline at end of file
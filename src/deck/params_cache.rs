@@ -0,0 +1,155 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use barnett_smart_card_protocol::BarnettSmartProtocol;
+use hex::FromHex;
+use log::debug;
+use rand_chacha::ChaCha20Rng;
+use rand_core::SeedableRng;
+use serde::{Serialize, Deserialize};
+use crate::deck::errors::DeckCustomError;
+use crate::deck::models::geometry::DeckGeometry;
+
+type Curve = starknet_curve::Projective;
+type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
+type Parameters = <CardProtocol as BarnettSmartProtocol>::Parameters;
+
+pub const DEFAULT_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    seed: [u8; 32],
+    geometry: DeckGeometry,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParametersCacheStats {
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct Entries {
+    map: HashMap<CacheKey, Arc<Parameters>>,
+    // least recently used key first
+    order: VecDeque<CacheKey>,
+}
+
+// CardProtocol::setup regenerates the Pedersen commitment key from the seed, which is
+// by far the most expensive part of a request. The parameters are a pure function of
+// (seed, m, n), so they are shared across requests in a bounded LRU.
+pub struct ParametersCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ParametersCache {
+    pub fn new(capacity: usize) -> Self {
+        ParametersCache {
+            capacity: capacity.max(1),
+            entries: Mutex::new(Entries { map: HashMap::new(), order: VecDeque::new() }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get_or_setup(&self, seed_hex: &str, geometry: &DeckGeometry) -> Result<Arc<Parameters>, DeckCustomError> {
+        let key = CacheKey { seed: decode_seed(seed_hex)?, geometry: *geometry };
+
+        if let Some(parameters) = self.lookup(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            debug!("parameters cache hit for {}x{}", geometry.m, geometry.n);
+            return Ok(parameters);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        debug!("parameters cache miss for {}x{}", geometry.m, geometry.n);
+
+        // setup runs outside the lock, two concurrent misses on the same key compute
+        // identical parameters and the second insert is a no-op
+        let mut restored_rng = ChaCha20Rng::from_seed(key.seed);
+        let parameters = match CardProtocol::setup(&mut restored_rng, geometry.m, geometry.n){
+            Ok(p) => Arc::new(p),
+            Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
+        };
+        self.insert(key, parameters.clone());
+        Ok(parameters)
+    }
+
+    pub fn stats(&self) -> ParametersCacheStats {
+        ParametersCacheStats {
+            capacity: self.capacity,
+            entries: self.entries.lock().unwrap().map.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<Arc<Parameters>> {
+        let mut entries = self.entries.lock().unwrap();
+        let parameters = entries.map.get(key)?.clone();
+        if let Some(position) = entries.order.iter().position(|k| k == key) {
+            entries.order.remove(position);
+        }
+        entries.order.push_back(key.clone());
+        Some(parameters)
+    }
+
+    fn insert(&self, key: CacheKey, parameters: Arc<Parameters>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.map.contains_key(&key) {
+            return;
+        }
+        while entries.map.len() >= self.capacity {
+            match entries.order.pop_front() {
+                Some(evicted) => {
+                    entries.map.remove(&evicted);
+                }
+                None => break,
+            }
+        }
+        entries.order.push_back(key.clone());
+        entries.map.insert(key, parameters);
+    }
+}
+
+fn decode_seed(seed_hex: &str) -> Result<[u8; 32], DeckCustomError> {
+    let seed = match Vec::from_hex(seed_hex){
+        Ok(seed) => seed,
+        Err(_e) => return Err(DeckCustomError::InvalidSeed)
+    };
+    match seed.try_into() {
+        Ok(seed) => Ok(seed),
+        Err(_e) => Err(DeckCustomError::InvalidSeed)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    const SEED_HEX: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_cache_hits_and_eviction() {
+        let cache = ParametersCache::new(1);
+        let first = cache.get_or_setup(SEED_HEX, &DeckGeometry::CLASSIC).unwrap();
+        let second = cache.get_or_setup(SEED_HEX, &DeckGeometry::CLASSIC).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        cache.get_or_setup(SEED_HEX, &DeckGeometry::new(4, 9).unwrap()).unwrap();
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 1));
+
+        let third = cache.get_or_setup(SEED_HEX, &DeckGeometry::CLASSIC).unwrap();
+        assert!(!Arc::ptr_eq(&first, &third));
+    }
+
+    #[test]
+    fn test_invalid_seed() {
+        let cache = ParametersCache::new(DEFAULT_CAPACITY);
+        assert!(matches!(cache.get_or_setup("abcd", &DeckGeometry::CLASSIC), Err(DeckCustomError::InvalidSeed)));
+    }
+}
@@ -1,19 +1,22 @@
+use std::sync::Arc;
 use barnett_smart_card_protocol::Reveal;
 use rocket::response::status;
 use rocket::{ State, http::Status};
 use rocket::futures::stream::Peek;
 use rocket::serde::json::Json;
 
-use crate::deck::models::deck_case::deck::{ComputeAggregateKeyRequest, ComputeAggregateKeyResponse, InitialDeckRequest, InitialDeckResponse, MaskRequest, MaskResponse, PeekCardsRequest, PeekCardsResponse, RevealTokenRequest, RevealTokenResponse, SetUpDeckRequest, SetUpDeckResponse, ShuffleRequest, ShuffleResponse, VerifyShuffleRequest, VerifyShuffleResponse};
+use crate::deck::models::deck_case::deck::{ComputeAggregateKeyRequest, ComputeAggregateKeyResponse, InitialDeckRequest, InitialDeckResponse, MaskRequest, MaskResponse, PeekCardsRequest, PeekCardsResponse, RevealTokenRequest, RevealTokenResponse, SetUpDeckRequest, SetUpDeckResponse, ShuffleRequest, ShuffleResponse, VerifyShuffleRequest, VerifyShuffleResponse, VerifyMaskRequest, VerifyMaskResponse, VerifyRevealTokensRequest, VerifyRevealTokensResponse, RevealCardsRequest, RevealCardsResponse, OpenCardsRequest, OpenCardsResponse, RemaskRequest, RemaskResponse, VerifyRemaskRequest, VerifyRemaskResponse, BatchVerifyRequest, BatchVerifyResponse, VerifyShuffleChainRequest, VerifyShuffleChainResponse, DealRequest, DealResponse, ShowdownResponse, FinishGameResponse, SeedCommitRequest, SeedCommitmentDTO, SeedRevealRequest, SeedRevealDTO, SeedCombineRequest, SeedCombineResponse, VerifyKeySharesRequest, VerifyKeySharesResponse, PartialRevealTokensRequest, PartialRevealTokensResponse, RecoverRevealTokensRequest, RecoverRevealTokensResponse, DiscloseKeyResponse, AuditGameRequest, AuditGameResponse, GameTranscriptLogResponse};
 use crate::user::service::UserServiceTrait;
 use crate::core::api_response::ErrorResponse;
 use crate::deck::errors::DeckCustomError;
+use crate::deck::models::geometry::DeckGeometry;
+use crate::deck::params_cache::ParametersCacheStats;
 use crate::deck::service::{ DeckServiceTrait};
 
 
 
 #[post("/deck/setup", data = "<setup>")]
-pub async fn setup(deck_service: &State<Box<dyn DeckServiceTrait>>, setup: Json<SetUpDeckRequest>) -> Result<status::Custom<Json<SetUpDeckResponse>>, status::Custom<Json<ErrorResponse>>> {
+pub async fn setup(deck_service: &State<Arc<dyn DeckServiceTrait>>, setup: Json<SetUpDeckRequest>) -> Result<status::Custom<Json<SetUpDeckResponse>>, status::Custom<Json<ErrorResponse>>> {
     let new_setup = SetUpDeckRequest {
         ..setup.into_inner()
     };
@@ -28,6 +31,10 @@ pub async fn setup(deck_service: &State<Box<dyn DeckServiceTrait>>, setup: Json<
                 DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
                 DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
                 DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) | DeckCustomError::SeedAgreement(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::InvalidKeyShare(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
                 _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
             }
         }
@@ -38,9 +45,14 @@ pub async fn setup(deck_service: &State<Box<dyn DeckServiceTrait>>, setup: Json<
     })))
 }
 
-#[get("/deck/initialize")]
-pub async fn initialize(deck_service: &State<Box<dyn DeckServiceTrait>>, ) -> Result<status::Custom<Json<InitialDeckResponse>>, status::Custom<Json<ErrorResponse>>> {
-    let initial_result = deck_service.initial_deck(InitialDeckRequest{}).await;
+#[get("/deck/initialize?<m>&<n>")]
+pub async fn initialize(deck_service: &State<Arc<dyn DeckServiceTrait>>, m: Option<usize>, n: Option<usize>) -> Result<status::Custom<Json<InitialDeckResponse>>, status::Custom<Json<ErrorResponse>>> {
+    // defaults to the classic 52 card deck
+    let initial_result = deck_service.initial_deck(InitialDeckRequest{
+        m: m.unwrap_or(DeckGeometry::CLASSIC.m),
+        n: n.unwrap_or(DeckGeometry::CLASSIC.n),
+        seed_hex: None,
+    }).await;
     let initialize_response = match initial_result {
         Ok(response) => response,
         Err(err) => {
@@ -49,6 +61,8 @@ pub async fn initialize(deck_service: &State<Box<dyn DeckServiceTrait>>, ) -> Re
                 DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
                 DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
                 DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
                 _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
             }
         }
@@ -59,8 +73,36 @@ pub async fn initialize(deck_service: &State<Box<dyn DeckServiceTrait>>, ) -> Re
     })))
 }
 
+// recompute the initial deck of an agreed seed, every agent must get the same cards
+#[post("/deck/initialize", data = "<initial_deck_req>")]
+pub async fn initialize_with_seed(deck_service: &State<Arc<dyn DeckServiceTrait>>, initial_deck_req: Json<InitialDeckRequest>) -> Result<status::Custom<Json<InitialDeckResponse>>, status::Custom<Json<ErrorResponse>>> {
+    let initial_deck_request = InitialDeckRequest {
+        ..initial_deck_req.into_inner()
+    };
+    let initial_result = deck_service.initial_deck(initial_deck_request).await;
+    let initialize_response = match initial_result {
+        Ok(response) => response,
+        Err(err) => {
+            match err {
+                DeckCustomError::GenericError(msg) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: msg }))),
+                DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
+                DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
+                DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidSeed => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    };
+
+    Ok(status::Custom(Status::Ok, Json(InitialDeckResponse {
+        ..initialize_response
+    })))
+}
+
 #[post("/deck/compute_aggregate_key", data = "<compute_aggregate>")]
-pub async fn compute_aggregate_key(deck_service: &State<Box<dyn DeckServiceTrait>>,compute_aggregate: Json<ComputeAggregateKeyRequest> ) -> Result<status::Custom<Json<ComputeAggregateKeyResponse>>, status::Custom<Json<ErrorResponse>>> {
+pub async fn compute_aggregate_key(deck_service: &State<Arc<dyn DeckServiceTrait>>,compute_aggregate: Json<ComputeAggregateKeyRequest> ) -> Result<status::Custom<Json<ComputeAggregateKeyResponse>>, status::Custom<Json<ErrorResponse>>> {
     let new_compute_agg = ComputeAggregateKeyRequest {
         ..compute_aggregate.into_inner()
     };
@@ -73,6 +115,12 @@ pub async fn compute_aggregate_key(deck_service: &State<Box<dyn DeckServiceTrait
                 DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
                 DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
                 DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameMismatch(_) | DeckCustomError::InvalidSeed => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::DuplicatePlayer(_) | DeckCustomError::DuplicatePublicKey(_) | DeckCustomError::IdentityPublicKey(_) | DeckCustomError::OwnKeyMissing(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::InvalidKeyShare(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
                 _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
             }
         }
@@ -85,7 +133,7 @@ pub async fn compute_aggregate_key(deck_service: &State<Box<dyn DeckServiceTrait
 
 
 #[post("/deck/mask", data = "<mask_req>")]
-pub async fn mask(deck_service: &State<Box<dyn DeckServiceTrait>>,mask_req: Json<MaskRequest> ) -> Result<status::Custom<Json<MaskResponse>>, status::Custom<Json<ErrorResponse>>> {
+pub async fn mask(deck_service: &State<Arc<dyn DeckServiceTrait>>,mask_req: Json<MaskRequest> ) -> Result<status::Custom<Json<MaskResponse>>, status::Custom<Json<ErrorResponse>>> {
     let mask_request = MaskRequest {
         ..mask_req.into_inner()
     };
@@ -98,6 +146,9 @@ pub async fn mask(deck_service: &State<Box<dyn DeckServiceTrait>>,mask_req: Json
                 DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
                 DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
                 DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
                 _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
             }
         }
@@ -109,7 +160,7 @@ pub async fn mask(deck_service: &State<Box<dyn DeckServiceTrait>>,mask_req: Json
 }
 
 #[post("/deck/shuffle", data = "<shuffle_req>")]
-pub async fn shuffle(deck_service: &State<Box<dyn DeckServiceTrait>>,shuffle_req: Json<ShuffleRequest> ) -> Result<status::Custom<Json<ShuffleResponse>>, status::Custom<Json<ErrorResponse>>> {
+pub async fn shuffle(deck_service: &State<Arc<dyn DeckServiceTrait>>,shuffle_req: Json<ShuffleRequest> ) -> Result<status::Custom<Json<ShuffleResponse>>, status::Custom<Json<ErrorResponse>>> {
     let shuffle_request = ShuffleRequest {
         ..shuffle_req.into_inner()
     };
@@ -122,6 +173,10 @@ pub async fn shuffle(deck_service: &State<Box<dyn DeckServiceTrait>>,shuffle_req
                 DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
                 DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
                 DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameMismatch(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound | DeckCustomError::UserNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
                 _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
             }
         }
@@ -133,7 +188,7 @@ pub async fn shuffle(deck_service: &State<Box<dyn DeckServiceTrait>>,shuffle_req
 }
 
 #[post("/deck/verify_shuffle", data = "<verify_shuffle_req>")]
-pub async fn verify_shuffle(deck_service: &State<Box<dyn DeckServiceTrait>>,verify_shuffle_req: Json<VerifyShuffleRequest> ) -> Result<status::Custom<Json<VerifyShuffleResponse>>, status::Custom<Json<ErrorResponse>>> {
+pub async fn verify_shuffle(deck_service: &State<Arc<dyn DeckServiceTrait>>,verify_shuffle_req: Json<VerifyShuffleRequest> ) -> Result<status::Custom<Json<VerifyShuffleResponse>>, status::Custom<Json<ErrorResponse>>> {
     let verify_shuffle_request = VerifyShuffleRequest {
         ..verify_shuffle_req.into_inner()
     };
@@ -146,6 +201,9 @@ pub async fn verify_shuffle(deck_service: &State<Box<dyn DeckServiceTrait>>,veri
                 DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
                 DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
                 DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
                 _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
             }
         }
@@ -157,7 +215,7 @@ pub async fn verify_shuffle(deck_service: &State<Box<dyn DeckServiceTrait>>,veri
 }
 
 #[post("/deck/reveal_token", data = "<revel_token_req>")]
-pub async fn reveal_token(deck_service: &State<Box<dyn DeckServiceTrait>>,revel_token_req: Json<RevealTokenRequest> ) -> Result<status::Custom<Json<RevealTokenResponse>>, status::Custom<Json<ErrorResponse>>> {
+pub async fn reveal_token(deck_service: &State<Arc<dyn DeckServiceTrait>>,revel_token_req: Json<RevealTokenRequest> ) -> Result<status::Custom<Json<RevealTokenResponse>>, status::Custom<Json<ErrorResponse>>> {
     let reveal_token_request = RevealTokenRequest {
         ..revel_token_req.into_inner()
     };
@@ -170,6 +228,10 @@ pub async fn reveal_token(deck_service: &State<Box<dyn DeckServiceTrait>>,revel_
                 DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
                 DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
                 DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::RevealNotAllowed(_) => return Err(status::Custom(Status::Forbidden, Json(ErrorResponse { message: err.to_string() }))),
                 _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
             }
         }
@@ -181,7 +243,7 @@ pub async fn reveal_token(deck_service: &State<Box<dyn DeckServiceTrait>>,revel_
 }
 
 #[post("/deck/peek_cards", data = "<peek_cards_req>")]
-pub async fn peek_cards(deck_service: &State<Box<dyn DeckServiceTrait>>,peek_cards_req: Json<PeekCardsRequest> ) -> Result<status::Custom<Json<PeekCardsResponse>>, status::Custom<Json<ErrorResponse>>> {
+pub async fn peek_cards(deck_service: &State<Arc<dyn DeckServiceTrait>>,peek_cards_req: Json<PeekCardsRequest> ) -> Result<status::Custom<Json<PeekCardsResponse>>, status::Custom<Json<ErrorResponse>>> {
     let peek_cards_request = PeekCardsRequest {
         ..peek_cards_req.into_inner()
     };
@@ -194,6 +256,11 @@ pub async fn peek_cards(deck_service: &State<Box<dyn DeckServiceTrait>>,peek_car
                 DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
                 DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
                 DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::CardNotInDeck => return Err(status::Custom(Status::UnprocessableEntity, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::RevealNotAllowed(_) => return Err(status::Custom(Status::Forbidden, Json(ErrorResponse { message: err.to_string() }))),
                 _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
             }
         }
@@ -204,6 +271,411 @@ pub async fn peek_cards(deck_service: &State<Box<dyn DeckServiceTrait>>,peek_car
     })))
 }
 
+#[post("/deck/verify_mask", data = "<verify_mask_req>")]
+pub async fn verify_mask(deck_service: &State<Arc<dyn DeckServiceTrait>>,verify_mask_req: Json<VerifyMaskRequest> ) -> Result<status::Custom<Json<VerifyMaskResponse>>, status::Custom<Json<ErrorResponse>>> {
+    let verify_mask_request = VerifyMaskRequest {
+        ..verify_mask_req.into_inner()
+    };
+    let verify_mask_response = deck_service.verify_mask(verify_mask_request).await;
+    let verify_mask_response = match verify_mask_response {
+        Ok(response) => response,
+        Err(err) => {
+            match err {
+                DeckCustomError::GenericError(msg) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: msg }))),
+                DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
+                DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
+                DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    };
+
+    Ok(status::Custom(Status::Ok, Json(VerifyMaskResponse {
+        ..verify_mask_response
+    })))
+}
+
+#[post("/deck/verify_reveal_tokens", data = "<verify_reveal_tokens_req>")]
+pub async fn verify_reveal_tokens(deck_service: &State<Arc<dyn DeckServiceTrait>>,verify_reveal_tokens_req: Json<VerifyRevealTokensRequest> ) -> Result<status::Custom<Json<VerifyRevealTokensResponse>>, status::Custom<Json<ErrorResponse>>> {
+    let verify_reveal_tokens_request = VerifyRevealTokensRequest {
+        ..verify_reveal_tokens_req.into_inner()
+    };
+    let verify_reveal_tokens_response = deck_service.verify_reveal_tokens(verify_reveal_tokens_request).await;
+    let verify_reveal_tokens_response = match verify_reveal_tokens_response {
+        Ok(response) => response,
+        Err(err) => {
+            match err {
+                DeckCustomError::GenericError(msg) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: msg }))),
+                DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
+                DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
+                DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    };
+
+    Ok(status::Custom(Status::Ok, Json(VerifyRevealTokensResponse {
+        ..verify_reveal_tokens_response
+    })))
+}
+
+#[post("/deck/reveal_cards", data = "<reveal_cards_req>")]
+pub async fn reveal_cards(deck_service: &State<Arc<dyn DeckServiceTrait>>,reveal_cards_req: Json<RevealCardsRequest> ) -> Result<status::Custom<Json<RevealCardsResponse>>, status::Custom<Json<ErrorResponse>>> {
+    let reveal_cards_request = RevealCardsRequest {
+        ..reveal_cards_req.into_inner()
+    };
+    let reveal_cards_response = deck_service.reveal_cards(reveal_cards_request).await;
+    let reveal_cards_response = match reveal_cards_response {
+        Ok(response) => response,
+        Err(err) => {
+            match err {
+                DeckCustomError::GenericError(msg) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: msg }))),
+                DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
+                DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
+                DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::RevealNotAllowed(_) => return Err(status::Custom(Status::Forbidden, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::InvalidRevealToken | DeckCustomError::InvalidCard => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::UserNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    };
+
+    Ok(status::Custom(Status::Ok, Json(RevealCardsResponse {
+        ..reveal_cards_response
+    })))
+}
+
+#[post("/deck/open_cards", data = "<open_cards_req>")]
+pub async fn open_cards(deck_service: &State<Arc<dyn DeckServiceTrait>>,open_cards_req: Json<OpenCardsRequest> ) -> Result<status::Custom<Json<OpenCardsResponse>>, status::Custom<Json<ErrorResponse>>> {
+    let open_cards_request = OpenCardsRequest {
+        ..open_cards_req.into_inner()
+    };
+    let open_cards_response = deck_service.open_cards(open_cards_request).await;
+    let open_cards_response = match open_cards_response {
+        Ok(response) => response,
+        Err(err) => {
+            match err {
+                DeckCustomError::GenericError(msg) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: msg }))),
+                DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
+                DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
+                DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::InvalidRevealToken | DeckCustomError::InvalidCard => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::InvalidKeyShare(_) | DeckCustomError::DuplicatePublicKey(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::RevealNotAllowed(_) => return Err(status::Custom(Status::Forbidden, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::CardNotInDeck => return Err(status::Custom(Status::UnprocessableEntity, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::UserNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    };
+
+    Ok(status::Custom(Status::Ok, Json(OpenCardsResponse {
+        ..open_cards_response
+    })))
+}
+
+#[post("/deck/remask", data = "<remask_req>")]
+pub async fn remask(deck_service: &State<Arc<dyn DeckServiceTrait>>,remask_req: Json<RemaskRequest> ) -> Result<status::Custom<Json<RemaskResponse>>, status::Custom<Json<ErrorResponse>>> {
+    let remask_request = RemaskRequest {
+        ..remask_req.into_inner()
+    };
+    let remask_response = deck_service.remask(remask_request).await;
+    let remask_response = match remask_response {
+        Ok(response) => response,
+        Err(err) => {
+            match err {
+                DeckCustomError::GenericError(msg) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: msg }))),
+                DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
+                DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
+                DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::InvalidCard => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    };
+
+    Ok(status::Custom(Status::Created, Json(RemaskResponse {
+        ..remask_response
+    })))
+}
+
+#[post("/deck/verify_remask", data = "<verify_remask_req>")]
+pub async fn verify_remask(deck_service: &State<Arc<dyn DeckServiceTrait>>,verify_remask_req: Json<VerifyRemaskRequest> ) -> Result<status::Custom<Json<VerifyRemaskResponse>>, status::Custom<Json<ErrorResponse>>> {
+    let verify_remask_request = VerifyRemaskRequest {
+        ..verify_remask_req.into_inner()
+    };
+    let verify_remask_response = deck_service.verify_remask(verify_remask_request).await;
+    let verify_remask_response = match verify_remask_response {
+        Ok(response) => response,
+        Err(err) => {
+            match err {
+                DeckCustomError::GenericError(msg) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: msg }))),
+                DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
+                DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
+                DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::InvalidCard => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    };
+
+    Ok(status::Custom(Status::Ok, Json(VerifyRemaskResponse {
+        ..verify_remask_response
+    })))
+}
+
+#[get("/deck/params_cache/stats")]
+pub async fn parameters_cache_stats(deck_service: &State<Arc<dyn DeckServiceTrait>>) -> Result<status::Custom<Json<ParametersCacheStats>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.parameters_cache_stats().await {
+        Ok(stats) => Ok(status::Custom(Status::Ok, Json(stats))),
+        Err(err) => Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+    }
+}
+
+#[post("/deck/batch_verify", data = "<batch_verify_req>")]
+pub async fn batch_verify(deck_service: &State<Arc<dyn DeckServiceTrait>>,batch_verify_req: Json<BatchVerifyRequest> ) -> Result<status::Custom<Json<BatchVerifyResponse>>, status::Custom<Json<ErrorResponse>>> {
+    let batch_verify_request = BatchVerifyRequest {
+        ..batch_verify_req.into_inner()
+    };
+    let batch_verify_response = deck_service.batch_verify(batch_verify_request).await;
+    let batch_verify_response = match batch_verify_response {
+        Ok(response) => response,
+        Err(err) => {
+            match err {
+                DeckCustomError::GenericError(msg) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: msg }))),
+                DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
+                DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
+                DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::InvalidSeed => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    };
+
+    Ok(status::Custom(Status::Ok, Json(BatchVerifyResponse {
+        ..batch_verify_response
+    })))
+}
+
+#[post("/deck/verify_shuffle_chain", data = "<verify_shuffle_chain_req>")]
+pub async fn verify_shuffle_chain(deck_service: &State<Arc<dyn DeckServiceTrait>>,verify_shuffle_chain_req: Json<VerifyShuffleChainRequest> ) -> Result<status::Custom<Json<VerifyShuffleChainResponse>>, status::Custom<Json<ErrorResponse>>> {
+    let verify_shuffle_chain_request = VerifyShuffleChainRequest {
+        ..verify_shuffle_chain_req.into_inner()
+    };
+    let verify_shuffle_chain_response = deck_service.verify_shuffle_chain(verify_shuffle_chain_request).await;
+    let verify_shuffle_chain_response = match verify_shuffle_chain_response {
+        Ok(response) => response,
+        Err(err) => {
+            match err {
+                DeckCustomError::GenericError(msg) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: msg }))),
+                DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
+                DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
+                DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::InvalidSeed | DeckCustomError::InvalidShuffleStep{ .. } => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    };
+
+    Ok(status::Custom(Status::Ok, Json(VerifyShuffleChainResponse {
+        ..verify_shuffle_chain_response
+    })))
+}
+
+#[post("/deck/deal", data = "<deal_req>")]
+pub async fn deal(deck_service: &State<Arc<dyn DeckServiceTrait>>,deal_req: Json<DealRequest> ) -> Result<status::Custom<Json<DealResponse>>, status::Custom<Json<ErrorResponse>>> {
+    let deal_request = DealRequest {
+        ..deal_req.into_inner()
+    };
+    let deal_response = deck_service.deal(deal_request).await;
+    let deal_response = match deal_response {
+        Ok(response) => response,
+        Err(err) => {
+            match err {
+                DeckCustomError::GenericError(msg) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: msg }))),
+                DeckCustomError::MissingFields(msg) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: format!("The following properties are required: {}", msg) }))),
+                DeckCustomError::InvalidPublicKey => return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid public key")}))),
+                DeckCustomError::InvalidProof=>return Err(status::Custom(Status::BadRequest,Json(ErrorResponse{message: format!("invalid proof")}))),
+                DeckCustomError::InvalidGeometry(_) | DeckCustomError::InvalidDeckSize(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => return Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::GameMismatch(_) => return Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::UserNotFound => return Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                _ => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    };
+
+    Ok(status::Custom(Status::Ok, Json(DealResponse {
+        ..deal_response
+    })))
+}
+
+#[post("/deck/game/<game_id>/showdown")]
+pub async fn showdown(deck_service: &State<Arc<dyn DeckServiceTrait>>, game_id: String) -> Result<status::Custom<Json<ShowdownResponse>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.showdown(game_id).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => {
+            match err {
+                DeckCustomError::GameNotFound => Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                _ => Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    }
+}
+
+fn seed_error(err: DeckCustomError) -> status::Custom<Json<ErrorResponse>> {
+    match err {
+        DeckCustomError::MissingFields(_) | DeckCustomError::DuplicatePlayer(_) => status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() })),
+        DeckCustomError::OutOfOrder(_) | DeckCustomError::SeedAgreement(_) => status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() })),
+        _ => status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() })),
+    }
+}
+
+#[post("/deck/seed/commit", data = "<seed_commit_req>")]
+pub async fn seed_commit(deck_service: &State<Arc<dyn DeckServiceTrait>>, seed_commit_req: Json<SeedCommitRequest>) -> Result<status::Custom<Json<SeedCommitmentDTO>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.seed_commit(seed_commit_req.into_inner()).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => Err(seed_error(err)),
+    }
+}
+
+#[post("/deck/seed/reveal", data = "<seed_reveal_req>")]
+pub async fn seed_reveal(deck_service: &State<Arc<dyn DeckServiceTrait>>, seed_reveal_req: Json<SeedRevealRequest>) -> Result<status::Custom<Json<SeedRevealDTO>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.seed_reveal(seed_reveal_req.into_inner()).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => Err(seed_error(err)),
+    }
+}
+
+// refuses with 409 if any reveal does not open its commitment
+#[post("/deck/seed/combine", data = "<seed_combine_req>")]
+pub async fn seed_combine(deck_service: &State<Arc<dyn DeckServiceTrait>>, seed_combine_req: Json<SeedCombineRequest>) -> Result<status::Custom<Json<SeedCombineResponse>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.seed_combine(seed_combine_req.into_inner()).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => Err(seed_error(err)),
+    }
+}
+
+fn key_share_error(err: DeckCustomError) -> status::Custom<Json<ErrorResponse>> {
+    match err {
+        DeckCustomError::InvalidKeyShare(_) | DeckCustomError::MissingFields(_) | DeckCustomError::GameMismatch(_) | DeckCustomError::InvalidCard | DeckCustomError::InvalidProof | DeckCustomError::SerializationError(_) => status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() })),
+        DeckCustomError::GameNotFound | DeckCustomError::UserNotFound => status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() })),
+        DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() })),
+        DeckCustomError::RevealNotAllowed(_) => status::Custom(Status::Forbidden, Json(ErrorResponse { message: err.to_string() })),
+        _ => status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() })),
+    }
+}
+
+// a verdict per dealing, only the valid ones can be used for recovery later
+#[post("/deck/key_shares/verify", data = "<verify_key_shares_req>")]
+pub async fn verify_key_shares(deck_service: &State<Arc<dyn DeckServiceTrait>>, verify_key_shares_req: Json<VerifyKeySharesRequest>) -> Result<status::Custom<Json<VerifyKeySharesResponse>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.verify_key_shares(verify_key_shares_req.into_inner()).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => Err(key_share_error(err)),
+    }
+}
+
+#[post("/deck/key_shares/partial_tokens", data = "<partial_tokens_req>")]
+pub async fn partial_reveal_tokens(deck_service: &State<Arc<dyn DeckServiceTrait>>, partial_tokens_req: Json<PartialRevealTokensRequest>) -> Result<status::Custom<Json<PartialRevealTokensResponse>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.partial_reveal_tokens(partial_tokens_req.into_inner()).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => Err(key_share_error(err)),
+    }
+}
+
+#[post("/deck/key_shares/recover", data = "<recover_req>")]
+pub async fn recover_reveal_tokens(deck_service: &State<Arc<dyn DeckServiceTrait>>, recover_req: Json<RecoverRevealTokensRequest>) -> Result<status::Custom<Json<RecoverRevealTokensResponse>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.recover_reveal_tokens(recover_req.into_inner()).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => Err(key_share_error(err)),
+    }
+}
+
+#[post("/deck/game/<game_id>/finish")]
+pub async fn finish_game(deck_service: &State<Arc<dyn DeckServiceTrait>>, game_id: String) -> Result<status::Custom<Json<FinishGameResponse>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.finish_game(game_id).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => {
+            match err {
+                DeckCustomError::GameNotFound => Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) => Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                _ => Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    }
+}
+
+// finishes the game first, no reveal token can be asked for once the keys are out
+#[post("/deck/game/<game_id>/disclose_key")]
+pub async fn disclose_key(deck_service: &State<Arc<dyn DeckServiceTrait>>, game_id: String) -> Result<status::Custom<Json<DiscloseKeyResponse>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.disclose_key(game_id).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => {
+            match err {
+                DeckCustomError::GameNotFound | DeckCustomError::UserNotFound => Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) => Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                _ => Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    }
+}
+
+#[post("/deck/game/<game_id>/audit", data = "<audit_req>")]
+pub async fn audit_game(deck_service: &State<Arc<dyn DeckServiceTrait>>, game_id: String, audit_req: Json<AuditGameRequest>) -> Result<status::Custom<Json<AuditGameResponse>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.audit_game(game_id, audit_req.into_inner()).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => {
+            match err {
+                DeckCustomError::GameNotFound => Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::OutOfOrder(_) | DeckCustomError::StateMismatch(_) => Err(status::Custom(Status::Conflict, Json(ErrorResponse { message: err.to_string() }))),
+                DeckCustomError::DuplicatePlayer(_) => Err(status::Custom(Status::BadRequest, Json(ErrorResponse { message: err.to_string() }))),
+                _ => Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    }
+}
+
+#[get("/deck/game/<game_id>/transcript")]
+pub async fn transcript(deck_service: &State<Arc<dyn DeckServiceTrait>>, game_id: String) -> Result<status::Custom<Json<GameTranscriptLogResponse>>, status::Custom<Json<ErrorResponse>>> {
+    match deck_service.transcript(game_id).await {
+        Ok(response) => Ok(status::Custom(Status::Ok, Json(response))),
+        Err(err) => {
+            match err {
+                DeckCustomError::GameNotFound => Err(status::Custom(Status::NotFound, Json(ErrorResponse { message: err.to_string() }))),
+                // a broken chain is the agent's own storage failing, not a bad request
+                _ => Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { message: err.to_string() }))),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod e2e_tests {
     use crate::serialize::serialize::{decode_proof, encode_proof};
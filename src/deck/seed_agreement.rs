@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use hex::FromHex;
+use rand::RngCore;
+use zeroize::Zeroizing;
+use crate::deck::errors::DeckCustomError;
+use crate::serialize::fields::hash_fields;
+
+const COMMITMENT_DOMAIN: &[u8] = b"deck_agent/seed_commitment/v1";
+const SEED_DOMAIN: &[u8] = b"deck_agent/seed/v1";
+const CONTRIBUTION_LEN: usize = 32;
+
+type Contribution = [u8; CONTRIBUTION_LEN];
+
+// the commitment is bound to the game and the participant, a copied commitment
+// can't be opened by anyone but its author
+pub fn commitment(game_id: &str, game_user_id: &str, contribution: &Contribution) -> String {
+    hex::encode(hash_fields(&[COMMITMENT_DOMAIN, game_id.as_bytes(), game_user_id.as_bytes(), contribution]))
+}
+
+// every reveal must open its commitment, the seed is the hash of all contributions
+// in game_user_id order so every agent computes the same one
+pub fn combine(game_id: &str, reveals: &BTreeMap<String, (String, String)>) -> Result<String, DeckCustomError> {
+    if reveals.is_empty() {
+        return Err(DeckCustomError::MissingFields(String::from("reveals")))
+    }
+    let mut fields: Vec<Vec<u8>> = vec![SEED_DOMAIN.to_vec(), game_id.as_bytes().to_vec()];
+    for (game_user_id, (expected, contribution_hex)) in reveals {
+        let contribution = match Contribution::from_hex(contribution_hex) {
+            Ok(contribution) => contribution,
+            Err(_e) => return Err(DeckCustomError::SeedAgreement(format!("contribution of {} is not {} hex encoded bytes", game_user_id, CONTRIBUTION_LEN))),
+        };
+        if !commitment(game_id, game_user_id, &contribution).eq_ignore_ascii_case(expected) {
+            return Err(DeckCustomError::SeedAgreement(format!("reveal of {} does not match its commitment", game_user_id)))
+        }
+        fields.push(game_user_id.as_bytes().to_vec());
+        fields.push(contribution.to_vec());
+    }
+    let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+    Ok(hex::encode(hash_fields(&fields)))
+}
+
+#[derive(Default)]
+struct SeedRound {
+    // our own contributions, one per local player
+    contributions: HashMap<String, Zeroizing<Contribution>>,
+    // commitments of every participant, fixed at our first reveal
+    commitments: Option<BTreeMap<String, String>>,
+    seed_hex: Option<String>,
+}
+
+// Commit-reveal agreement on seed_hex. An agent only reveals once the full set of
+// commitments is known, and only accepts a seed built from exactly that set.
+pub struct SeedAgreements {
+    rounds: Mutex<HashMap<String, SeedRound>>,
+}
+
+impl SeedAgreements {
+    pub fn new() -> Self {
+        SeedAgreements {
+            rounds: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn commit(&self, game_id: &str, game_user_id: &str) -> Result<String, DeckCustomError> {
+        let mut rounds = self.rounds.lock().unwrap();
+        let round = rounds.entry(game_id.to_string()).or_default();
+        if let Some(contribution) = round.contributions.get(game_user_id) {
+            return Ok(commitment(game_id, game_user_id, contribution))
+        }
+        if round.commitments.is_some() {
+            return Err(DeckCustomError::SeedAgreement(format!("commitments of game {} are already fixed", game_id)))
+        }
+        let mut contribution = Zeroizing::new([0u8; CONTRIBUTION_LEN]);
+        rand::thread_rng().fill_bytes(&mut *contribution);
+        let committed = commitment(game_id, game_user_id, &contribution);
+        round.contributions.insert(game_user_id.to_string(), contribution);
+        Ok(committed)
+    }
+
+    // returns our commitment and contribution, hex encoded
+    pub fn reveal(&self, game_id: &str, game_user_id: &str, commitments: BTreeMap<String, String>) -> Result<(String, String), DeckCustomError> {
+        let mut rounds = self.rounds.lock().unwrap();
+        let round = match rounds.get_mut(game_id) {
+            Some(round) => round,
+            None => return Err(DeckCustomError::OutOfOrder(format!("no seed commitment for game {}", game_id))),
+        };
+        let own = match round.contributions.get(game_user_id) {
+            Some(contribution) => commitment(game_id, game_user_id, contribution),
+            None => return Err(DeckCustomError::OutOfOrder(format!("{} has not committed to a seed contribution", game_user_id))),
+        };
+        let commitments: BTreeMap<String, String> = commitments.into_iter().map(|(id, c)| (id, c.to_lowercase())).collect();
+        if commitments.get(game_user_id) != Some(&own) {
+            return Err(DeckCustomError::SeedAgreement(format!("commitments do not contain the one of {}", game_user_id)))
+        }
+        match &round.commitments {
+            Some(fixed) if *fixed != commitments => return Err(DeckCustomError::SeedAgreement(
+                format!("commitments of game {} differ from the ones already revealed against", game_id))),
+            Some(_) => {}
+            None => round.commitments = Some(commitments),
+        }
+        let contribution = hex::encode(&**round.contributions.get(game_user_id).unwrap());
+        Ok((own, contribution))
+    }
+
+    pub fn combine(&self, game_id: &str, reveals: BTreeMap<String, (String, String)>) -> Result<String, DeckCustomError> {
+        let reveals: BTreeMap<String, (String, String)> = reveals.into_iter()
+            .map(|(id, (c, contribution))| (id, (c.to_lowercase(), contribution))).collect();
+        let mut rounds = self.rounds.lock().unwrap();
+        let round = rounds.entry(game_id.to_string()).or_default();
+        if let Some(fixed) = &round.commitments {
+            let revealed: BTreeMap<String, String> = reveals.iter().map(|(id, (c, _))| (id.clone(), c.clone())).collect();
+            if revealed != *fixed {
+                return Err(DeckCustomError::SeedAgreement(String::from("reveals do not cover exactly the committed participants")))
+            }
+        }
+        for (game_user_id, contribution) in round.contributions.iter() {
+            match reveals.get(game_user_id) {
+                Some((_, revealed)) if revealed.eq_ignore_ascii_case(&hex::encode(&**contribution)) => {}
+                _ => return Err(DeckCustomError::SeedAgreement(format!("own contribution of {} is missing or altered", game_user_id))),
+            }
+        }
+        let seed_hex = combine(game_id, &reveals)?;
+        round.seed_hex = Some(seed_hex.clone());
+        Ok(seed_hex)
+    }
+
+    pub fn agreed_seed(&self, game_id: &str) -> Option<String> {
+        self.rounds.lock().unwrap().get(game_id).and_then(|round| round.seed_hex.clone())
+    }
+
+    // once a round is open for a game, setup has to wait for its seed
+    pub fn started(&self, game_id: &str) -> bool {
+        self.rounds.lock().unwrap().contains_key(game_id)
+    }
+
+    pub fn forget(&self, game_id: &str) {
+        self.rounds.lock().unwrap().remove(game_id);
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_agents_agree_on_the_seed() {
+        let alice = SeedAgreements::new();
+        let bob = SeedAgreements::new();
+        let mut commitments = BTreeMap::new();
+        commitments.insert(String::from("alice"), alice.commit("game", "alice").unwrap());
+        commitments.insert(String::from("bob"), bob.commit("game", "bob").unwrap());
+
+        let mut reveals = BTreeMap::new();
+        let (c, contribution) = alice.reveal("game", "alice", commitments.clone()).unwrap();
+        reveals.insert(String::from("alice"), (c, contribution));
+        let (c, contribution) = bob.reveal("game", "bob", commitments.clone()).unwrap();
+        reveals.insert(String::from("bob"), (c, contribution));
+
+        let seed_hex = alice.combine("game", reveals.clone()).unwrap();
+        assert_eq!(bob.combine("game", reveals.clone()).unwrap(), seed_hex);
+        assert_eq!(alice.agreed_seed("game"), Some(seed_hex));
+        assert!(alice.commit("game", "carol").is_err());
+    }
+
+    #[test]
+    fn test_mismatching_reveal_is_refused() {
+        let alice = SeedAgreements::new();
+        let mut commitments = BTreeMap::new();
+        commitments.insert(String::from("alice"), alice.commit("game", "alice").unwrap());
+        commitments.insert(String::from("bob"), commitment("game", "bob", &[1u8; CONTRIBUTION_LEN]));
+        let (c, contribution) = alice.reveal("game", "alice", commitments.clone()).unwrap();
+
+        let mut reveals = BTreeMap::new();
+        reveals.insert(String::from("alice"), (c, contribution));
+        reveals.insert(String::from("bob"), (commitments["bob"].clone(), hex::encode([2u8; CONTRIBUTION_LEN])));
+        assert!(matches!(alice.combine("game", reveals.clone()), Err(DeckCustomError::SeedAgreement(_))));
+
+        // a participant left out of the reveals is just as fatal
+        reveals.remove("bob");
+        assert!(matches!(alice.combine("game", reveals), Err(DeckCustomError::SeedAgreement(_))));
+        assert_eq!(alice.agreed_seed("game"), None);
+    }
+}
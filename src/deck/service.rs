@@ -10,11 +10,11 @@ use rand_core::{CryptoRngCore, RngCore, SeedableRng};
 use rocket::data::ToByteUnit;
 use rocket::futures::TryFutureExt;
 use rocket::yansi::Paint;
-use crate::deck::models::deck_case::deck::{SetUpDeckResponse, MaskResponse, ComputeAggregateKeyResponse, GenerateDeckRequest, GenerateDeckResponse, InitialDeck, MaskedCardAndProofDTO as CardDTO, ShuffleRequest, ShuffleResponse, VerifyShuffleRequest, VerifyShuffleResponse, ShuffledDeck, RevealCardsRequest, RevealCardsResponse, OpenCardsRequest, OpenCardsResponse, RevealedDeck, PeekCardsRequest, PeekCardsResponse, RevealTokenRequest, RevealTokenResponse, InitialDeckRequest, InitialDeckResponse, InitialCard, Proof, MaskDeck, RevealTokenDTO, PedersenProofDTO};
+use crate::deck::models::deck_case::deck::{SetUpDeckResponse, MaskResponse, ComputeAggregateKeyResponse, GenerateDeckRequest, GenerateDeckResponse, InitialDeck, MaskedCardAndProofDTO as CardDTO, ShuffleRequest, ShuffleResponse, VerifyShuffleRequest, VerifyShuffleResponse, ShuffledDeck, RevealCardsRequest, RevealCardsResponse, OpenCardsRequest, OpenCardsResponse, RevealedDeck, PeekCardsRequest, PeekCardsResponse, RevealTokenRequest, RevealTokenResponse, InitialDeckRequest, InitialDeckResponse, InitialCard, Proof, MaskDeck, RevealTokenDTO, PedersenProofDTO, VerifyMaskRequest, VerifyMaskResponse, VerifyRevealTokensRequest, VerifyRevealTokensResponse, RevealTokenVerdictDTO, OpenedCards, RemaskRequest, RemaskResponse, RemaskedCardDTO, VerifyRemaskRequest, VerifyRemaskResponse, BatchVerifyRequest, BatchVerifyResponse, MaskVerdictDTO, VerifyShuffleChainRequest, VerifyShuffleChainResponse, DealRequest, DealResponse, ShowdownResponse, FinishGameResponse, SeedCommitRequest, SeedCommitmentDTO, SeedRevealRequest, SeedRevealDTO, SeedCombineRequest, SeedCombineResponse, VerifyKeySharesRequest, VerifyKeySharesResponse, KeyShareVerdictDTO, PartialRevealTokensRequest, PartialRevealTokensResponse, PartialRevealTokenDTO, KeyRecoveryDTO, RecoverRevealTokensRequest, RecoverRevealTokensResponse, DisclosedKeyDTO, DiscloseKeyResponse, AuditGameRequest, AuditGameResponse, KeyAuditDTO, PositionAuditDTO, GameTranscriptLogResponse};
 use ark_serialize::{CanonicalSerialize,CanonicalDeserialize};
 use asn1_der::typed::DerEncodable;
 use starknet_curve::{Affine, StarkwareParameters};
-use crate::serialize::serialize::{encode_public_key, decode_public_key, decode_deck_public_key, decode_masked_card, encode_masked_card, encode_masking_proof, decode_shuffle_proof, encode_shuffle_proof, encode_initial_card, decode_initial_card, encode_revel_token, encode_revel_proof, decode_revel_token, decode_revel_proof};
+use crate::serialize::serialize::{encode_public_key, decode_public_key, decode_deck_public_key, decode_masked_card, encode_masked_card, encode_masking_proof, decode_shuffle_proof, encode_shuffle_proof, encode_initial_card, decode_initial_card, encode_revel_token, encode_revel_proof, decode_revel_token, decode_revel_proof, encode_private_key, decode_private_key};
 
 use proof_essentials::homomorphic_encryption::{
     el_gamal, el_gamal::ElGamal, HomomorphicEncryptionScheme,
@@ -25,8 +25,15 @@ use rand::thread_rng;
 use crate::user::service::UserService;
 use ark_std::{rand::Rng, One};
 type Scalar = starknet_curve::Fr;
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::deck::params_cache::{self, ParametersCache, ParametersCacheStats};
+use crate::deck::seed_agreement::SeedAgreements;
+use crate::deck::batch::{self, ProofClaim};
+use crate::deck::audit;
+use crate::game::models::transcript::TRANSCRIPT_LOG_VERSION;
+use rayon::prelude::*;
 
 type Curve = starknet_curve::Projective;
 type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
@@ -44,13 +51,249 @@ use crate::game_user::repository::GameUserMemTrait;
 use hex::FromHex;
 type ZKProof = schnorr_identification::proof::Proof<Curve>;
 
+type Parameters = <CardProtocol as BarnettSmartProtocol>::Parameters;
+type PublicKey = <CardProtocol as BarnettSmartProtocol>::PlayerPublicKey;
+
 pub struct DeckService {
-    user_db: Mutex<HashMap<String, GameUser>>,
+    user_db: Arc<dyn GameUserMemTrait>,
+    // all private key operations, keys never leave it
+    key_store: Box<dyn KeyStore>,
+    // protocol state of each game_id, created at setup
+    game_db: Box<dyn GameDbTrait>,
+    // serializes read-modify-write of games within this agent
+    game_updates: rocket::tokio::sync::Mutex<()>,
+    // plaintext card to face mapping of each game_id, derived from the seed at setup
+    card_mappings: Mutex<HashMap<String, HashMap<Card, ClassicPlayingCard>>>,
+    params_cache: ParametersCache,
+    // commit-reveal rounds agreeing on seed_hex, before any game exists
+    seed_agreements: SeedAgreements,
 }
 
 impl DeckService {
-    pub fn new(user_db: Box<dyn GameUserMemTrait>) -> Self {
-        DeckService { user_db:Mutex::new(HashMap::new())}
+    pub fn new(user_db: Arc<dyn GameUserMemTrait>, game_db: Box<dyn GameDbTrait>, key_store: Box<dyn KeyStore>) -> Self {
+        DeckService {
+            user_db:user_db,
+            key_store:key_store,
+            game_db:game_db,
+            game_updates:rocket::tokio::sync::Mutex::new(()),
+            card_mappings:Mutex::new(HashMap::new()),
+            params_cache:ParametersCache::new(params_cache::DEFAULT_CAPACITY),
+            seed_agreements:SeedAgreements::new(),
+        }
+    }
+
+    fn parameters(&self, seed_hex: &str, geometry: &DeckGeometry) -> Result<Arc<Parameters>, DeckCustomError> {
+        self.params_cache.get_or_setup(seed_hex, geometry)
+    }
+
+    fn register_card_mapping(&self, game_id: &str, seed_hex: String, geometry: &DeckGeometry) -> Result<(), DeckCustomError> {
+        let mut card_mappings = self.card_mappings.lock().unwrap();
+        if !card_mappings.contains_key(game_id) {
+            let card_mapping = encode_cards(seed_hex, geometry)?.into_iter().collect();
+            card_mappings.insert(game_id.to_string(), card_mapping);
+        }
+        Ok(())
+    }
+
+    // the mapping is derived from the seed, so it is rebuilt after a restart
+    fn classic_card(&self, game: &Game, card: &Card) -> Result<ClassicPlayingCard, DeckCustomError> {
+        self.register_card_mapping(&game.game_id, game.seed_hex.clone(), &game.geometry)?;
+        let card_mappings = self.card_mappings.lock().unwrap();
+        let card_mapping = match card_mappings.get(&game.game_id) {
+            Some(card_mapping) => card_mapping,
+            None => return Err(DeckCustomError::GameNotFound),
+        };
+        match card_mapping.get(card) {
+            Some(classic_card) => Ok(*classic_card),
+            None => Err(DeckCustomError::CardNotInDeck),
+        }
+    }
+
+    async fn register_game(&self, game_id: &str, seed_hex: &str, seed_agreed: bool, geometry: DeckGeometry) -> Result<(), DeckCustomError> {
+        let _guard = self.game_updates.lock().await;
+        match self.game_db.get_by_id(game_id).await {
+            Ok(game) => game.check_setup(seed_hex, &geometry),
+            Err(DeckCustomError::GameNotFound) => {
+                let mut game = Game::new(game_id.to_string(), seed_hex.to_string(), geometry);
+                game.seed_agreed = seed_agreed;
+                self.game_db.save(game).await
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn game(&self, game_id: &str) -> Result<Game, DeckCustomError> {
+        self.game_db.get_by_id(game_id).await
+    }
+
+    // transitions re-check the phase under the lock, a concurrent call may have advanced it
+    async fn update_game<T: Send>(&self, game_id: &str, update: impl FnOnce(&mut Game) -> Result<T, DeckCustomError> + Send) -> Result<T, DeckCustomError> {
+        let _guard = self.game_updates.lock().await;
+        let mut game = self.game_db.get_by_id(game_id).await?;
+        let result = update(&mut game)?;
+        game.touch();
+        self.game_db.save(game).await?;
+        Ok(result)
+    }
+
+    async fn game_user(&self, game_user_id: &str) -> Result<Arc<GameUser>, DeckCustomError> {
+        Ok(self.user_db.get_by_id(game_user_id).await?)
+    }
+
+    async fn own_public_keys(&self, game_id: &str) -> Result<Vec<(String, PublicKey)>, DeckCustomError> {
+        Ok(self.user_db.get_by_game_id(game_id).await?
+            .into_iter()
+            .map(|game_user| (game_user.game_user_id.clone(), game_user.public_key))
+            .collect())
+    }
+
+    // our local players shuffle through this agent, a shuffle verified in their name would stand in for theirs
+    async fn local_player_ids(&self, game_id: &str) -> Result<HashSet<String>, DeckCustomError> {
+        Ok(self.user_db.get_by_game_id(game_id).await?
+            .into_iter()
+            .map(|game_user| game_user.game_user_id.clone())
+            .collect())
+    }
+
+    // requests still carry seed_hex, it must be the seed the game was set up with
+    fn game_parameters(&self, game: &Game, seed_hex: &str) -> Result<Arc<Parameters>, DeckCustomError> {
+        game.check_seed(seed_hex)?;
+        self.parameters(&game.seed_hex, &game.geometry)
+    }
+
+    // proves the key of a player that was just set up and adds the player to the game
+    async fn join_game(&self, set_up: SetUpDeckRequest, input: serde_json::Value, params: &Parameters, pk: PublicKey, game_user_info: &[u8]) -> Result<SetUpDeckResponse, DeckCustomError> {
+        let proof = self.key_store.prove_key_ownership(params, &set_up.game_user_id, game_user_info).await?;
+        let share_threshold = set_up.share_threshold;
+
+        let pub_key = match encode_public_key(pk){
+            Ok(p) => p,
+            Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Failed to serialize pk")))
+        };
+        let proof_third = IdentityProof::new(proof);
+        let response = SetUpDeckResponse{
+            user_id:set_up.user_id,
+            game_id:set_up.game_id,
+            game_user_id: set_up.game_user_id,
+            user_public_key:  pub_key,
+            user_key_proof: Proof{
+                commit: proof_third.commit,
+                opening:proof_third.opening,
+            },
+        };
+        // only calls that succeeded are recorded, with the request as received and the response as sent
+        let output = transcript_value(&response)?;
+        self.update_game(&response.game_id, |game| {
+            if let Some(threshold) = share_threshold {
+                game.share_key(response.game_user_id.clone(), threshold)?;
+            }
+            game.record("setup", input, output);
+            Ok(())
+        }).await?;
+        Ok(response)
+    }
+
+    // deal the keys of our players that opted in at setup, a repeated call hands out the same dealings,
+    // the caller stores new ones with the joint key
+    async fn deal_key_shares(&self, game: &Game, parameters: &Parameters, player_keys: &HashMap<String, PublicKey>) -> Result<Vec<KeyDealing>, DeckCustomError> {
+        let mut dealings = Vec::with_capacity(game.share_thresholds.len());
+        for (dealer, threshold) in game.share_thresholds.iter() {
+            if let Some(dealing) = game.key_dealings.get(dealer) {
+                dealings.push(dealing.clone());
+                continue
+            }
+            let mut recipients = Vec::new();
+            for (game_user_id, index) in game.share_recipients(dealer)? {
+                let public_key = match player_keys.get(&game_user_id) {
+                    Some(public_key) => *public_key,
+                    None => return Err(DeckCustomError::StateMismatch(format!("player {} is not part of the joint key", game_user_id))),
+                };
+                recipients.push(ShareRecipient{ game_user_id, index, public_key });
+            }
+            dealings.push(self.key_store.deal_key_shares(parameters, dealer, &game.game_id, *threshold, &recipients).await?);
+        }
+        Ok(dealings)
+    }
+
+    // a disclosed key must belong to a player of the joint key and come with an ownership
+    // proof under that player's setup context
+    fn check_disclosed_key(&self, game: &Game, parameters: &Parameters, disclosed: &DisclosedKeyDTO) -> Result<SecretScalar, DeckCustomError> {
+        let public_key = decode_deck_public_key(game.public_key(&disclosed.game_user_id)?.clone())?;
+        if decode_deck_public_key(disclosed.public_key.clone())? != public_key {
+            return Err(DeckCustomError::AuditFailed(format!("{} disclosed another public key than the one in the joint key", disclosed.game_user_id)))
+        }
+        let private_key = decode_private_key(disclosed.private_key.clone())?;
+        if !audit::key_matches(parameters.enc_parameters.generator, &public_key, &private_key) {
+            return Err(DeckCustomError::AuditFailed(format!("disclosed key of {} does not match its public key", disclosed.game_user_id)))
+        }
+        let context = key_ownership_context(&game.game_id, &disclosed.game_user_id, &game.geometry, &game.seed_hex);
+        let key_proof = IdentityProof{
+            commit: disclosed.user_key_proof.commit.clone(),
+            opening: disclosed.user_key_proof.opening.clone(),
+        }.to_curve()?;
+        if let Err(_e) = CardProtocol::verify_key_ownership(parameters, &public_key, &context, &key_proof) {
+            return Err(DeckCustomError::InvalidProof)
+        }
+        Ok(private_key)
+    }
+
+    // the dealing must commit to the dealer's key and our share must open to a point on it
+    async fn open_key_share(&self, game: &Game, parameters: &Parameters, game_user_id: &str, dealing: &KeyDealing) -> Result<(u64, SecretScalar), DeckCustomError> {
+        let dealer_key = decode_deck_public_key(game.public_key(&dealing.dealer)?.clone())?;
+        vss::check_dealing(dealing, &dealer_key, &game.share_recipients(&dealing.dealer)?)?;
+        let ephemeral_key = vss::ephemeral_key(vss::find_share(dealing, game_user_id)?)?;
+        let shared_point = self.key_store.diffie_hellman(parameters, game_user_id, &ephemeral_key).await?;
+        let (index, share) = vss::open_share(dealing, game_user_id, &shared_point, &game.game_id)?;
+        if !vss::verify_share(parameters.enc_parameters.generator, dealing, index, &share)? {
+            return Err(DeckCustomError::InvalidKeyShare(format!("share of {} does not match the commitments of {}", game_user_id, dealing.dealer)))
+        }
+        Ok((index, share))
+    }
+
+    // every partial token is checked against the public key of its share, then the tokens of
+    // each dealer and card are interpolated into the token the dealer's own key would give
+    async fn recover_tokens(&self, game: &Game, parameters: &Arc<Parameters>, recoveries: Vec<KeyRecoveryDTO>) -> Result<HashMap<String, Vec<(PublicKey, RevealToken)>>, DeckCustomError> {
+        if recoveries.is_empty() {
+            return Ok(HashMap::new())
+        }
+        let mut claims = Vec::new();
+        let mut partials: BTreeMap<(String, String), Vec<(u64, RevealToken)>> = BTreeMap::new();
+        for recovery in recoveries {
+            let dealing = game.key_dealing(&recovery.dealer_game_user_id)?;
+            for partial in recovery.partial_tokens {
+                if !dealing.shares.iter().any(|share| share.index == partial.index) {
+                    return Err(DeckCustomError::InvalidKeyShare(format!("dealing of {} has no share at index {}", dealing.dealer, partial.index)))
+                }
+                let masked_card = decode_masked_card(partial.card.clone())?;
+                // the shares stand in for the dealer, so the dealer's reveal rules apply
+                game.check_reveal(&dealing.dealer, &encode_masked_card(masked_card.clone())?)?;
+                let reveal_token = decode_revel_token(partial.token)?;
+                let proof = PedersenProof{
+                    a: partial.proof.a,
+                    b: partial.proof.b,
+                    r: partial.proof.r,
+                }.to_curve()?;
+                let public_key = vss::share_public_key(dealing, partial.index)?;
+                claims.push(ProofClaim::Reveal{ public_key, reveal_token, masked_card, proof });
+                partials.entry((dealing.dealer.clone(), partial.card)).or_default().push((partial.index, reveal_token));
+            }
+        }
+
+        let verdicts = verify_claims_blocking(parameters.clone(), claims).await?;
+        if verdicts.iter().any(|valid| !valid) {
+            return Err(DeckCustomError::InvalidKeyShare(String::from("a partial reveal token does not match its share")))
+        }
+
+        let mut tokens: HashMap<String, Vec<(PublicKey, RevealToken)>> = HashMap::new();
+        for ((dealer, card), partial_tokens) in partials {
+            let threshold = game.key_dealing(&dealer)?.threshold;
+            if partial_tokens.len() < threshold {
+                return Err(DeckCustomError::InvalidKeyShare(format!("{} partial tokens of {} for a card, {} needed", partial_tokens.len(), dealer, threshold)))
+            }
+            let dealer_key = decode_deck_public_key(game.public_key(&dealer)?.clone())?;
+            tokens.entry(card).or_default().push((dealer_key, vss::combine_partial_tokens(&partial_tokens)?));
+        }
+        Ok(tokens)
     }
 }
 
@@ -65,35 +308,94 @@ pub trait DeckServiceTrait: Send + Sync {
     async fn compute_aggregate_key(&self,compute_agg_key: ComputeAggregateKeyRequest)->Result<ComputeAggregateKeyResponse,DeckCustomError>;
 
     async fn mask(&self, mask_req: MaskRequest)->Result<MaskResponse,DeckCustomError>;
+
+    // check another agent's masked deck against the initial cards before accepting it
+    async fn verify_mask(&self, verify_mask_req: VerifyMaskRequest)->Result<VerifyMaskResponse,DeckCustomError>;
     async fn shuffle (&self, shuffle_request: ShuffleRequest) -> Result<ShuffleResponse, DeckCustomError>;
 
     async fn verify_shuffle(&self, verify_shuffle_request: VerifyShuffleRequest) -> Result<VerifyShuffleResponse, DeckCustomError>;
 
+    // every step must shuffle the output of the previous one, the first failing step is reported
+    async fn verify_shuffle_chain(&self, verify_shuffle_chain_request: VerifyShuffleChainRequest) -> Result<VerifyShuffleChainResponse, DeckCustomError>;
+
+    // re-randomize single cards, e.g. when passing cards between players
+    async fn remask(&self, remask_request: RemaskRequest) -> Result<RemaskResponse, DeckCustomError>;
+
+    async fn verify_remask(&self, verify_remask_request: VerifyRemaskRequest) -> Result<VerifyRemaskResponse, DeckCustomError>;
+
 
     // user receive their and need to reveal others card at the same time
     async fn reveal_token(&self, reveal_token_request: RevealTokenRequest) ->Result<RevealTokenResponse, DeckCustomError>;
     async fn reveal_cards(&self,reveal_cards_request:  RevealCardsRequest)->Result<RevealCardsResponse, DeckCustomError>;
 
+    // check each player's partial decryption on its own so a bad token can be attributed
+    async fn verify_reveal_tokens(&self, verify_reveal_tokens_request: VerifyRevealTokensRequest)->Result<VerifyRevealTokensResponse, DeckCustomError>;
+
     async fn peek_cards(&self,peek_cards_request: PeekCardsRequest) -> Result<PeekCardsResponse, DeckCustomError>;
 
     async fn open_cards(&self,open_cards_request: OpenCardsRequest)->Result<OpenCardsResponse, DeckCustomError>;
+
+    async fn parameters_cache_stats(&self) -> Result<ParametersCacheStats, DeckCustomError>;
+
+    // record which positions of the final deck go to which player, or to the board
+    async fn deal(&self, deal_request: DealRequest) -> Result<DealResponse, DeckCustomError>;
+
+    // from now on every dealt card may be revealed, including our own hole cards
+    async fn showdown(&self, game_id: String) -> Result<ShowdownResponse, DeckCustomError>;
+
+    // the hand is shown down, no more tokens are issued for it
+    async fn finish_game(&self, game_id: String) -> Result<FinishGameResponse, DeckCustomError>;
+
+    // drop every game idle for longer than ttl together with its players, returns how many
+    async fn sweep_expired(&self, ttl: Duration) -> Result<usize, DeckCustomError>;
+
+    // verify many reveal and masking proofs at once, with a verdict per proof
+    async fn batch_verify(&self, batch_verify_request: BatchVerifyRequest) -> Result<BatchVerifyResponse, DeckCustomError>;
+
+    // commit to a random seed contribution of a local player
+    async fn seed_commit(&self, seed_commit_request: SeedCommitRequest) -> Result<SeedCommitmentDTO, DeckCustomError>;
+
+    // open our contribution once every participant has committed
+    async fn seed_reveal(&self, seed_reveal_request: SeedRevealRequest) -> Result<SeedRevealDTO, DeckCustomError>;
+
+    // check every reveal against its commitment and derive the agreed seed_hex
+    async fn seed_combine(&self, seed_combine_request: SeedCombineRequest) -> Result<SeedCombineResponse, DeckCustomError>;
+
+    // release the private keys of the local players of a finished game, for auditing the hand
+    async fn disclose_key(&self, game_id: String) -> Result<DiscloseKeyResponse, DeckCustomError>;
+
+    // decrypt every deck position of a finished game with all disclosed keys and compare with the revealed cards
+    async fn audit_game(&self, game_id: String, audit_game_request: AuditGameRequest) -> Result<AuditGameResponse, DeckCustomError>;
+
+    // open and check the shares other players dealt to a local player, valid dealings are kept for recovery
+    async fn verify_key_shares(&self, verify_key_shares_request: VerifyKeySharesRequest) -> Result<VerifyKeySharesResponse, DeckCustomError>;
+
+    // reveal tokens of a local player's share, standing in for a dealer that dropped out
+    async fn partial_reveal_tokens(&self, partial_reveal_tokens_request: PartialRevealTokensRequest) -> Result<PartialRevealTokensResponse, DeckCustomError>;
+
+    // combine a threshold of partial tokens into the dealer's reveal tokens
+    async fn recover_reveal_tokens(&self, recover_reveal_tokens_request: RecoverRevealTokensRequest) -> Result<RecoverRevealTokensResponse, DeckCustomError>;
+
+    // hash chained log of setup, compute_aggregate_key, mask, shuffle, verify_shuffle, reveal_token and peek_cards
+    async fn transcript(&self, game_id: String) -> Result<GameTranscriptLogResponse, DeckCustomError>;
 }
 
 #[async_trait]
 impl DeckServiceTrait for DeckService {
     async fn initial_deck(&self,initial_deck: InitialDeckRequest)->Result<InitialDeckResponse, DeckCustomError>{
-        // Each player should run this computation and verify that all players agree on the initial deck
-        let mut  rng = ChaCha20Rng::from_entropy();
-        let seed = rng.clone().get_seed();
-        let seed_hex = hex::encode(&seed);
-        let mut rng = thread_rng();
-        let card_mapping = encode_cards(&mut rng, 2*26);
+        // Each player should run this computation and verify that all players agree on the initial deck,
+        // given the same seed every agent derives exactly the same cards
+        let seed_hex = match initial_deck.seed_hex {
+            Some(seed_hex) => seed_hex,
+            None => {
+                let rng = ChaCha20Rng::from_entropy();
+                hex::encode(rng.get_seed())
+            }
+        };
+        let geometry = DeckGeometry::new(initial_deck.m, initial_deck.n)?;
+        let card_mapping = encode_cards(seed_hex.clone(), &geometry)?;
         let mut initial_cards  =  Vec::with_capacity(card_mapping.len());
         for card in card_mapping {
-            let mut encoded_card = Vec::new();
-            if let Err(e) = card.0.serialize_uncompressed(&mut encoded_card){
-                return Err(DeckCustomError::GenericError(String::from("Internal")))
-            };
             let card_hex = match encode_initial_card(card.0){
                 Ok(c) => c,
                 Err(e) => return Err(DeckCustomError::GenericError(String::from("Internal")))
@@ -107,6 +409,8 @@ impl DeckServiceTrait for DeckService {
             InitialDeckResponse{
                 cards: initial_cards,
                 seed_hex: seed_hex,
+                m: geometry.m,
+                n: geometry.n,
             }
         )
     }
@@ -130,56 +434,77 @@ impl DeckServiceTrait for DeckService {
                 missing_properties.join(", ").to_string(),
             ));
         }
-        let mut restored_rng = restore_rnd(set_up.seed_hex)?;
-        let params= match   CardProtocol::setup(&mut restored_rng, 2, 26){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::InvalidProof)
-        };
-        let rng = &mut thread_rng();
-        let (pk, sk) = match CardProtocol::player_keygen(rng, &params){
-            Ok(tuple) =>  tuple,
-            Err(_e)=> return Err(DeckCustomError::InvalidPublicKey)
-        };
-        let game_user = GameUser::new(set_up.game_user_id.clone(),set_up.user_id.clone(),pk,sk);
-
-        //TODO 用户对局信息存起来
-        let pub_key = match encode_public_key(pk){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Failed to serialize pk")))
-        };
-        let  game_user_info = set_up.game_user_id.clone().into_bytes();
-
-        let proof =match CardProtocol::prove_key_ownership(rng, &params, &pk, &sk, &game_user_info){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::InvalidProof)
+        // a started round binds the game to its seed, an existing game already has it persisted
+        let seed_agreed = match self.seed_agreements.agreed_seed(&set_up.game_id) {
+            Some(seed_hex) if !seed_hex.eq_ignore_ascii_case(&set_up.seed_hex) =>
+                return Err(DeckCustomError::SeedAgreement(format!("game {} agreed on another seed", set_up.game_id))),
+            Some(_) => true,
+            None if self.seed_agreements.started(&set_up.game_id) =>
+                return Err(DeckCustomError::OutOfOrder(format!("seed round of game {} has not been combined yet", set_up.game_id))),
+            None => false,
         };
-        let proof_third = IdentityProof::new(proof);
-        self.user_db.lock().unwrap().insert(set_up.game_user_id.clone(), game_user);
-        Ok(SetUpDeckResponse{
-            user_id:set_up.user_id,
-            game_id:set_up.game_id,
-            game_user_id: set_up.game_user_id,
-            user_public_key:  pub_key,
-            user_key_proof: Proof{
-                commit: proof_third.commit,
-                opening:proof_third.opening,
-            },
-        })
+        let input = transcript_value(&set_up)?;
+        if set_up.share_threshold == Some(0) {
+            return Err(DeckCustomError::InvalidKeyShare(String::from("share_threshold must be at least 1")))
+        }
+        // a game_user_id holds one key, setting it up again would take the key away from its game
+        match self.game_user(&set_up.game_user_id).await {
+            Ok(existing) => return Err(DeckCustomError::StateMismatch(
+                format!("player {} is already set up for game {}", set_up.game_user_id, existing.game_id))),
+            Err(DeckCustomError::UserNotFound) => {}
+            Err(e) => return Err(e),
+        }
+        let geometry = DeckGeometry::new(set_up.m, set_up.n)?;
+        let params = self.parameters(&set_up.seed_hex, &geometry)?;
+        self.register_game(&set_up.game_id, &set_up.seed_hex, seed_agreed, geometry).await?;
+        let  game_user_info = key_ownership_context(&set_up.game_id, &set_up.game_user_id, &geometry, &set_up.seed_hex);
+        self.register_card_mapping(&set_up.game_id, set_up.seed_hex.clone(), &geometry)?;
+        let pk = self.key_store.keygen(&params, &set_up.game_id, &set_up.game_user_id, &set_up.user_id, &set_up.seed_hex).await?;
+        // the key is stored by now, a setup failing after this must not leave it behind
+        let game_user_id = set_up.game_user_id.clone();
+        let joined = self.join_game(set_up, input, &params, pk, &game_user_info).await;
+        if joined.is_err() {
+            self.user_db.delete(&game_user_id).await?;
+        }
+        joined
     }
     async fn compute_aggregate_key(&self,compute_agg_key_request: ComputeAggregateKeyRequest)->Result<ComputeAggregateKeyResponse,DeckCustomError> {
-        let mut restored_rng = restore_rnd(compute_agg_key_request.seed_hex)?;
-        let parameters = match CardProtocol::setup(&mut restored_rng, 2, 26){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
-        };
+        let game_id = compute_agg_key_request.game_id.clone();
+        let input = transcript_value(&compute_agg_key_request)?;
+        let game = self.game(&game_id).await?;
+        let geometry = game.geometry;
+        let mut players = Vec::with_capacity(compute_agg_key_request.players.len());
+        let parameters = self.game_parameters(&game, &compute_agg_key_request.seed_hex)?;
+
+        if compute_agg_key_request.players.is_empty() {
+            return Err(DeckCustomError::MissingFields(String::from("players")))
+        }
 
+        let mut game_user_ids = HashSet::new();
+        let mut public_keys = HashSet::new();
+        let mut player_keys = HashMap::new();
         let mut key_proof_info = Vec::with_capacity(compute_agg_key_request.players.len());
         for player in compute_agg_key_request.players {
+            if player.game_id != game_id {
+                return Err(DeckCustomError::GameMismatch(player.game_user_id.clone()))
+            }
+            if !game_user_ids.insert(player.game_user_id.clone()) {
+                return Err(DeckCustomError::DuplicatePlayer(player.game_user_id.clone()))
+            }
+            let game_user_info = key_ownership_context(&game_id, &player.game_user_id, &geometry, &game.seed_hex);
             let public_key = match decode_public_key(player.public_key.clone()) {
                 Ok(p) => p,
                 Err(_e) => return Err(DeckCustomError::InvalidPublicKey)
             };
-
+            // the identity would cancel out of the joint key
+            if public_key.is_zero() {
+                return Err(DeckCustomError::IdentityPublicKey(player.game_user_id.clone()))
+            }
+            if !public_keys.insert(public_key) {
+                return Err(DeckCustomError::DuplicatePublicKey(player.game_user_id.clone()))
+            }
+            player_keys.insert(player.game_user_id.clone(), public_key);
+            players.push(player.game_user_id.clone());
 
             let key_proof=IdentityProof{
                 commit:player.user_key_proof.commit.clone(),
@@ -187,10 +512,17 @@ impl DeckServiceTrait for DeckService {
             }.to_curve()?;
 
             // 验证对方公钥
-            if  let Err(e) = CardProtocol::verify_key_ownership(&parameters,&public_key,&player.game_user_id.clone().into_bytes(),&key_proof){
+            if  let Err(e) = CardProtocol::verify_key_ownership(&parameters,&public_key,&game_user_info,&key_proof){
                 return Err(DeckCustomError::InvalidProof)
             }
-            key_proof_info.push((public_key, key_proof, player.game_user_id.clone().into_bytes()))
+            key_proof_info.push((public_key, key_proof, game_user_info))
+        }
+
+        // our own player must be part of the joint key, or the table could deal us out
+        for (game_user_id, own_key) in self.own_public_keys(&game_id).await? {
+            if player_keys.get(&game_user_id) != Some(&own_key) {
+                return Err(DeckCustomError::OwnKeyMissing(game_user_id))
+            }
         }
         let joint_pk = match CardProtocol::compute_aggregate_key(&parameters, &key_proof_info){
             Ok(p) => p,
@@ -201,70 +533,148 @@ impl DeckServiceTrait for DeckService {
             Ok(p) => p,
             Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Failed to serialize pk")))
         };
-        Ok(ComputeAggregateKeyResponse{
+        let mut encoded_keys = BTreeMap::new();
+        for (game_user_id, player_key) in player_keys.iter() {
+            match encode_public_key(*player_key) {
+                Ok(p) => encoded_keys.insert(game_user_id.clone(), p),
+                Err(_e) => return Err(DeckCustomError::GenericError(String::from("Failed to serialize pk")))
+            };
+        }
+        // the dealings need the joint key, they are made against the aggregated game and stored with it
+        let mut aggregated = game.clone();
+        aggregated.aggregate_keys(public_key.clone(), players.clone())?;
+        aggregated.public_keys = encoded_keys.clone();
+        let key_dealings = self.deal_key_shares(&aggregated, &parameters, &player_keys).await?;
+        let response = ComputeAggregateKeyResponse{
             joined_key:public_key,
-        })
+            key_dealings: key_dealings,
+        };
+        let output = transcript_value(&response)?;
+        self.update_game(&game_id, |game| {
+            game.aggregate_keys(response.joined_key.clone(), players)?;
+            game.public_keys = encoded_keys;
+            for dealing in response.key_dealings.iter() {
+                game.record_dealing(dealing.clone())?;
+            }
+            game.record("compute_aggregate_key", input, output);
+            Ok(())
+        }).await?;
+        Ok(response)
     }
 
     async fn mask(&self, mask_req: MaskRequest)->Result<MaskResponse,DeckCustomError>{
-        let mut restored_rng = restore_rnd(mask_req.seed_hex)?;
-        let parameters = match CardProtocol::setup(&mut restored_rng, 2, 26){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
-        };
+        let input = transcript_value(&mask_req)?;
+        let game = self.game(&mask_req.game_id).await?;
+        game.check_mask()?;
+        game.check_joint_key(&mask_req.joined_key)?;
+        let geometry = game.geometry;
+        geometry.check_deck_len(mask_req.cards.len())?;
+        let parameters = self.game_parameters(&game, &mask_req.seed_hex)?;
         let joint_pk = decode_deck_public_key(mask_req.joined_key.clone())?;
-        let rng = &mut thread_rng();
-        let mut masked_cards  =  Vec::with_capacity(mask_req.cards.len());
-        for card in mask_req.cards {
-            let initial_card = decode_initial_card(card)?;
-            let masked_result =  <DLCards<ark_ec::short_weierstrass_jacobian::GroupProjective<StarkwareParameters>> as BarnettSmartProtocol>::mask(rng, &parameters, &joint_pk, &initial_card, &Scalar::one());
-            let one_masked_card = match masked_result{
-                Ok(p)=>p,
-                Err(_e) => return Err(DeckCustomError::InvalidCard)
-            };
-            masked_cards.push(one_masked_card);
+        let initial_cards = decode_game_cards(&game, mask_req.cards)?;
+        let mut masked_cards  =  Vec::with_capacity(initial_cards.len());
+        {
+            let rng = &mut thread_rng();
+            for initial_card in initial_cards {
+                // fresh masking factor per card, otherwise the masked deck is linkable to the plaintexts
+                let masking_factor = Scalar::rand(rng);
+                let masked_result =  <DLCards<ark_ec::short_weierstrass_jacobian::GroupProjective<StarkwareParameters>> as BarnettSmartProtocol>::mask(rng, &parameters, &joint_pk, &initial_card, &masking_factor);
+                let one_masked_card = match masked_result{
+                    Ok(p)=>p,
+                    Err(_e) => return Err(DeckCustomError::InvalidCard)
+                };
+                masked_cards.push(one_masked_card);
+            }
         }
 
         let shuffle_deck = match MaskDeck::new(masked_cards){
             Ok(d) => d,
             Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
         };
-        // shuffle_deck.into_masked_card();
-        Ok(MaskResponse{
+        let deck = shuffle_deck.cards.iter().map(|card| card.masked_card.clone()).collect();
+        let response = MaskResponse{
             cards:shuffle_deck.cards,
-        })
+        };
+        let output = transcript_value(&response)?;
+        self.update_game(&mask_req.game_id, |game| {
+            game.mask(deck)?;
+            game.record("mask", input, output);
+            Ok(())
+        }).await?;
+        Ok(response)
+    }
+
+    async fn verify_mask(&self, verify_mask_req: VerifyMaskRequest)->Result<VerifyMaskResponse,DeckCustomError>{
+        let input = transcript_value(&verify_mask_req)?;
+        let game = self.game(&verify_mask_req.game_id).await?;
+        game.check_joint_key(&verify_mask_req.joined_key)?;
+        let geometry = game.geometry;
+        geometry.check_deck_len(verify_mask_req.cards.len())?;
+        geometry.check_deck_len(verify_mask_req.masked_cards.len())?;
+        let parameters = self.game_parameters(&game, &verify_mask_req.seed_hex)?;
+        let joint_pk = decode_deck_public_key(verify_mask_req.joined_key.clone())?;
+
+        let initial_cards = decode_game_cards(&game, verify_mask_req.cards)?;
+        let masked_deck = MaskDeck{
+            cards: verify_mask_req.masked_cards,
+        }.into_masked_card()?;
+
+        // masked cards are expected in the same order as the initial cards
+        for (initial_card, (masked_card, proof)) in initial_cards.iter().zip(masked_deck.iter()) {
+            if let Err(_e) = CardProtocol::verify_mask(&parameters, &joint_pk, initial_card, masked_card, proof){
+                return Err(DeckCustomError::InvalidProof)
+            }
+        }
+        // a verified deck becomes the canonical one
+        let deck = encode_deck(masked_deck.into_iter().map(|(masked_card, _)| masked_card))?;
+        let response = VerifyMaskResponse{};
+        let output = transcript_value(&response)?;
+        self.update_game(&verify_mask_req.game_id, |game| {
+            game.mask(deck)?;
+            game.record("verify_mask", input, output);
+            Ok(())
+        }).await?;
+        Ok(response)
     }
 
     async fn shuffle(&self, shuffle_request: ShuffleRequest) -> Result<ShuffleResponse, DeckCustomError> {
-        let mut restored_rng = restore_rnd(shuffle_request.seed_hex)?;
-        let parameters = match CardProtocol::setup(&mut restored_rng, 2, 26){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
-        };
+        let input = transcript_value(&shuffle_request)?;
+        let game = self.game(&shuffle_request.game_id).await?;
+        game.check_joint_key(&shuffle_request.joined_key)?;
+        let geometry = game.geometry;
+        geometry.check_deck_len(shuffle_request.cards.len())?;
+        let parameters = self.game_parameters(&game, &shuffle_request.seed_hex)?;
         let joint_pk = decode_deck_public_key(shuffle_request.joined_key.clone())?;
 
-        let pmrng =&mut  thread_rng();
-        let maskrng =&mut  thread_rng();
-        let shufflerng = &mut thread_rng();
-
         let mut deck = Vec::with_capacity(shuffle_request.cards.len());
         for card in shuffle_request.cards {
             let maked_card = decode_masked_card(card)?;
             deck.push(maked_card);
         }
-
-        let permutation = Permutation::new(pmrng, 2 * 26);
-        let masking_factors: Vec<Scalar> = sample_vector(maskrng, 2 * 26);
-        let (a_shuffled_deck, a_shuffle_proof) = match CardProtocol::shuffle_and_remask(
-            shufflerng,
-            &parameters,
-            &joint_pk,
-            &deck,
-            &masking_factors,
-            &permutation,
-        ){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::InvalidProof)
+        let origin_deck = encode_deck(deck.iter().cloned())?;
+        let shuffler = self.game_user(&shuffle_request.game_user_id).await?;
+        if shuffler.game_id != shuffle_request.game_id {
+            return Err(DeckCustomError::GameMismatch(shuffler.game_user_id.clone()))
+        }
+        game.check_shuffle(&shuffler.game_user_id, &origin_deck)?;
+
+        let (a_shuffled_deck, a_shuffle_proof) = {
+            let pmrng =&mut  thread_rng();
+            let maskrng =&mut  thread_rng();
+            let shufflerng = &mut thread_rng();
+            let permutation = Permutation::new(pmrng, geometry.num_of_cards());
+            let masking_factors: Vec<Scalar> = sample_vector(maskrng, geometry.num_of_cards());
+            match CardProtocol::shuffle_and_remask(
+                shufflerng,
+                &parameters,
+                &joint_pk,
+                &deck,
+                &masking_factors,
+                &permutation,
+            ){
+                Ok(p) => p,
+                Err(_e)=> return Err(DeckCustomError::InvalidProof)
+            }
         };
 
 
@@ -280,24 +690,32 @@ impl DeckServiceTrait for DeckService {
         let shuffled_cards = shuffle_deck_dto.cards.iter()
             .map(|x| x.masked_card.clone())
             .collect::<Vec<String>>();
-
-        Ok(ShuffleResponse{
+        let response = ShuffleResponse{
             cards: shuffled_cards,
             shuffle_proof:proof_hex,
-        })
+        };
+        let output = transcript_value(&response)?;
+        self.update_game(&shuffle_request.game_id, |game| {
+            game.shuffle(&shuffler.game_user_id, &origin_deck, response.cards.clone())?;
+            game.record("shuffle", input, output);
+            Ok(())
+        }).await?;
+        Ok(response)
     }
 
     async fn verify_shuffle(&self,verify_shuffle_request: VerifyShuffleRequest) -> Result<VerifyShuffleResponse, DeckCustomError> {
+       let input = transcript_value(&verify_shuffle_request)?;
        let proof = match  decode_shuffle_proof(verify_shuffle_request.proof){
            Ok(p) => p,
            Err(_e)=> return Err(DeckCustomError::InvalidProof)
        };
 
-        let mut restored_rng = restore_rnd(verify_shuffle_request.seed_hex)?;
-        let parameters = match CardProtocol::setup(&mut restored_rng, 2, 26){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
-        };
+        let game = self.game(&verify_shuffle_request.game_id).await?;
+        game.check_joint_key(&verify_shuffle_request.joined_key)?;
+        let geometry = game.geometry;
+        geometry.check_deck_len(verify_shuffle_request.origin_cards.len())?;
+        geometry.check_deck_len(verify_shuffle_request.shuffled_cards.len())?;
+        let parameters = self.game_parameters(&game, &verify_shuffle_request.seed_hex)?;
 
         let joined_key = match decode_public_key(verify_shuffle_request.joined_key.clone()){
             Ok(p) => p,
@@ -318,46 +736,237 @@ impl DeckServiceTrait for DeckService {
             shuffled_deck.push(maked_card);
         }
 
+        let origin_cards = encode_deck(origin_deck.iter().cloned())?;
+        let shuffler = verify_shuffle_request.game_user_id.clone();
+        if self.local_player_ids(&verify_shuffle_request.game_id).await?.contains(&shuffler) {
+            return Err(DeckCustomError::StateMismatch(format!("player {} shuffles at this agent", shuffler)))
+        }
+        game.check_shuffle(&shuffler, &origin_cards)?;
+
         if  let Err(_e)= CardProtocol::verify_shuffle(&parameters,&joined_key,&origin_deck,&shuffled_deck, &proof){
             return Err(DeckCustomError::InvalidProof)
         };
-        Ok(VerifyShuffleResponse{})
+        let shuffled_cards = encode_deck(shuffled_deck.into_iter())?;
+        let response = VerifyShuffleResponse{};
+        let output = transcript_value(&response)?;
+        self.update_game(&verify_shuffle_request.game_id, |game| {
+            game.shuffle(&shuffler, &origin_cards, shuffled_cards)?;
+            game.record("verify_shuffle", input, output);
+            Ok(())
+        }).await?;
+        Ok(response)
     }
 
-    async fn reveal_token(&self, reveal_token_req: RevealTokenRequest) ->Result<RevealTokenResponse, DeckCustomError>{
-        let mut restored_rng = restore_rnd(reveal_token_req.seed_hex)?;
-        let parameters = match CardProtocol::setup(&mut restored_rng, 2, 26){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
+    async fn verify_shuffle_chain(&self, verify_shuffle_chain_request: VerifyShuffleChainRequest) -> Result<VerifyShuffleChainResponse, DeckCustomError> {
+        let input = transcript_value(&verify_shuffle_chain_request)?;
+        let game = self.game(&verify_shuffle_chain_request.game_id).await?;
+        game.check_joint_key(&verify_shuffle_chain_request.joined_key)?;
+        let geometry = game.geometry;
+        geometry.check_deck_len(verify_shuffle_chain_request.initial_cards.len())?;
+        let parameters = self.game_parameters(&game, &verify_shuffle_chain_request.seed_hex)?;
+        let joint_pk = decode_deck_public_key(verify_shuffle_chain_request.joined_key.clone())?;
+        if verify_shuffle_chain_request.steps.is_empty() {
+            return Err(DeckCustomError::MissingFields(String::from("steps")))
+        }
+
+        let mut initial_deck = Vec::with_capacity(verify_shuffle_chain_request.initial_cards.len());
+        for card in verify_shuffle_chain_request.initial_cards {
+            initial_deck.push(decode_masked_card(card)?);
+        }
+        let initial_cards = encode_deck(initial_deck.iter().cloned())?;
+        game.check_shuffle_origin(&initial_cards)?;
+        let local_players = self.local_player_ids(&verify_shuffle_chain_request.game_id).await?;
+
+        // decode everything up front so a malformed step is attributed to its shuffler
+        let mut shufflers: HashSet<String> = game.shufflers.iter().cloned().collect();
+        let mut steps = Vec::with_capacity(verify_shuffle_chain_request.steps.len());
+        for (index, step) in verify_shuffle_chain_request.steps.into_iter().enumerate() {
+            let step_error = |reason: String| DeckCustomError::InvalidShuffleStep{ index, game_user_id: step.game_user_id.clone(), reason };
+            if !game.players.contains(&step.game_user_id) {
+                return Err(step_error(String::from("player is not part of the joint key")))
+            }
+            if !shufflers.insert(step.game_user_id.clone()) {
+                return Err(step_error(String::from("player already shuffled this deck")))
+            }
+            if local_players.contains(&step.game_user_id) {
+                return Err(step_error(String::from("player shuffles at this agent")))
+            }
+            if let Err(e) = geometry.check_deck_len(step.shuffled_cards.len()) {
+                return Err(step_error(e.to_string()))
+            }
+            let proof = match decode_shuffle_proof(step.proof.clone()){
+                Ok(p) => p,
+                Err(_e) => return Err(step_error(DeckCustomError::InvalidProof.to_string()))
+            };
+            let mut shuffled_deck = Vec::with_capacity(step.shuffled_cards.len());
+            for card in step.shuffled_cards.iter() {
+                match decode_masked_card(card.clone()) {
+                    Ok(masked_card) => shuffled_deck.push(masked_card),
+                    Err(e) => return Err(step_error(e.to_string()))
+                }
+            }
+            steps.push((step.game_user_id, shuffled_deck, proof));
+        }
+
+        // each link is fixed by the request itself, so the steps are independent and checked in parallel
+        let verified = rocket::tokio::task::spawn_blocking(move || {
+            let failed = (0..steps.len()).into_par_iter().filter(|&index| {
+                let origin_deck = if index == 0 { &initial_deck } else { &steps[index - 1].1 };
+                let (_, shuffled_deck, proof) = &steps[index];
+                CardProtocol::verify_shuffle(&parameters, &joint_pk, origin_deck, shuffled_deck, proof).is_err()
+            }).min();
+            (steps, failed)
+        }).await;
+        let (steps, failed) = match verified {
+            Ok(verified) => verified,
+            Err(e) => return Err(DeckCustomError::GenericError(e.to_string()))
         };
+        if let Some(index) = failed {
+            return Err(DeckCustomError::InvalidShuffleStep{ index, game_user_id: steps[index].0.clone(), reason: DeckCustomError::InvalidProof.to_string() })
+        }
 
-        let game_user_id = reveal_token_req.game_user_id.clone();
-        let user_db = self.user_db.lock().unwrap(); // 守卫生命周期开始
-        let get_user_result = user_db.get(&game_user_id);      // 守卫未释放，引用有效
-        let user = match get_user_result{
-            Some(game_user) => game_user,
-            None => return Err(DeckCustomError::UserNotFound),
+        let mut decks = Vec::with_capacity(steps.len());
+        for (game_user_id, shuffled_deck, _) in steps {
+            decks.push((game_user_id, encode_deck(shuffled_deck.into_iter())?));
+        }
+        let response = VerifyShuffleChainResponse{
+            final_cards: decks.last().map(|(_, cards)| cards.clone()).unwrap_or_default(),
+        };
+        let output = transcript_value(&response)?;
+        // the whole chain is applied or none of it
+        self.update_game(&verify_shuffle_chain_request.game_id, |game| {
+            let mut updated = game.clone();
+            let mut origin_cards = initial_cards;
+            for (game_user_id, shuffled_cards) in decks {
+                updated.shuffle(&game_user_id, &origin_cards, shuffled_cards.clone())?;
+                origin_cards = shuffled_cards;
+            }
+            updated.record("verify_shuffle_chain", input, output);
+            *game = updated;
+            Ok(())
+        }).await?;
+        Ok(response)
+    }
+
+    async fn remask(&self, remask_request: RemaskRequest) -> Result<RemaskResponse, DeckCustomError> {
+        let input = transcript_value(&remask_request)?;
+        let game = self.game(&remask_request.game_id).await?;
+        game.check_dealt("remask")?;
+        game.check_joint_key(&remask_request.joined_key)?;
+        let geometry = game.geometry;
+        check_card_count(&geometry, remask_request.cards.len())?;
+        let parameters = self.game_parameters(&game, &remask_request.seed_hex)?;
+        let joint_pk = decode_deck_public_key(remask_request.joined_key.clone())?;
+
+        let mut masked_cards = Vec::with_capacity(remask_request.cards.len());
+        for card in remask_request.cards {
+            let masked_card = decode_masked_card(card.clone())?;
+            game.check_remask(&encode_masked_card(masked_card)?)?;
+            masked_cards.push((card, masked_card));
+        }
+
+        let rng = &mut thread_rng();
+        let mut remasked_cards = Vec::with_capacity(masked_cards.len());
+        let mut replaced = Vec::with_capacity(masked_cards.len());
+        for (card, masked_card) in masked_cards {
+            let masking_factor = Scalar::rand(rng);
+            let (remasked_card, proof) = match CardProtocol::remask(rng, &parameters, &joint_pk, &masked_card, &masking_factor){
+                Ok(p) => p,
+                Err(_e)=> return Err(DeckCustomError::InvalidCard)
+            };
+            let proof = PedersenProof::new(proof);
+            let remasked_hex = encode_masked_card(remasked_card)?;
+            replaced.push((encode_masked_card(masked_card)?, remasked_hex.clone()));
+            remasked_cards.push(RemaskedCardDTO{
+                origin_card: card,
+                masked_card: remasked_hex,
+                proof: PedersenProofDTO{
+                    a: proof.a,
+                    b: proof.b,
+                    r: proof.r,
+                },
+            });
+        }
+        let response = RemaskResponse{
+            cards: remasked_cards,
         };
-        let user_private_key = user.private_key.clone();
-        let user_public_key = user.public_key.clone();
+        let output = transcript_value(&response)?;
+        self.update_game(&remask_request.game_id, |game| {
+            game.remask(&replaced)?;
+            game.record("remask", input, output);
+            Ok(())
+        }).await?;
+        Ok(response)
+    }
 
-        let mut reveal_token_map = HashMap::new();
-        for card_dto in reveal_token_req.reveal_cards{
+    async fn verify_remask(&self, verify_remask_request: VerifyRemaskRequest) -> Result<VerifyRemaskResponse, DeckCustomError> {
+        let input = transcript_value(&verify_remask_request)?;
+        let game = self.game(&verify_remask_request.game_id).await?;
+        game.check_dealt("remask")?;
+        game.check_joint_key(&verify_remask_request.joined_key)?;
+        let geometry = game.geometry;
+        check_card_count(&geometry, verify_remask_request.cards.len())?;
+        let parameters = self.game_parameters(&game, &verify_remask_request.seed_hex)?;
+        let joint_pk = decode_deck_public_key(verify_remask_request.joined_key.clone())?;
+
+        let mut replaced = Vec::with_capacity(verify_remask_request.cards.len());
+        for card in verify_remask_request.cards {
+            let origin_card = decode_masked_card(card.origin_card)?;
+            let remasked_card = decode_masked_card(card.masked_card)?;
+            game.check_remask(&encode_masked_card(origin_card)?)?;
+            let proof = PedersenProof{
+                a: card.proof.a,
+                b: card.proof.b,
+                r: card.proof.r,
+            }.to_curve()?;
+            if let Err(_e) = CardProtocol::verify_remask(&parameters, &joint_pk, &origin_card, &remasked_card, &proof){
+                return Err(DeckCustomError::InvalidProof)
+            }
+            replaced.push((encode_masked_card(origin_card)?, encode_masked_card(remasked_card)?));
+        }
+        // a verified remask replaces the cards like our own does
+        let response = VerifyRemaskResponse{};
+        let output = transcript_value(&response)?;
+        self.update_game(&verify_remask_request.game_id, |game| {
+            game.remask(&replaced)?;
+            game.record("verify_remask", input, output);
+            Ok(())
+        }).await?;
+        Ok(response)
+    }
+
+    async fn reveal_token(&self, reveal_token_req: RevealTokenRequest) ->Result<RevealTokenResponse, DeckCustomError>{
+        let game_user_id = reveal_token_req.game_user_id.clone();
+        let input = transcript_value(&reveal_token_req)?;
+        let user = self.game_user(&game_user_id).await?;
+        let game_id = user.game_id.clone();
+        let game = self.game(&game_id).await?;
+        let parameters = self.game_parameters(&game, &reveal_token_req.seed_hex)?;
+
+        // only ciphertexts of the final deck, otherwise our key is a decryption oracle
+        let mut masked_cards = Vec::with_capacity(reveal_token_req.reveal_cards.len());
+        let mut revealed_cards = Vec::with_capacity(reveal_token_req.reveal_cards.len());
+        for card_dto in reveal_token_req.reveal_cards.iter() {
             let masked_card = match decode_masked_card(card_dto.clone()){
                 Ok(p) => p,
                 Err(_e)=> return Err(DeckCustomError::InvalidProof)
             };
-            let reveal_token = match CardProtocol::compute_reveal_token(&mut restored_rng,&parameters,&user_private_key,&user_public_key,&masked_card){
-                Ok(p) => p,
-                Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
-            };
+            let encoded_card = encode_masked_card(masked_card.clone())?;
+            game.check_reveal(&game_user_id, &encoded_card)?;
+            revealed_cards.push(encoded_card);
+            masked_cards.push(masked_card);
+        }
+
+        let reveal_tokens = self.key_store.compute_reveal_tokens(&parameters, &game_user_id, &masked_cards).await?;
+        let pub_key_hex = match encode_public_key(user.public_key){
+            Ok(p) => p,
+            Err(_e)=> return Err(DeckCustomError::InvalidPublicKey)
+        };
 
+        let mut reveal_token_map = HashMap::new();
+        for (card_dto, reveal_token) in reveal_token_req.reveal_cards.into_iter().zip(reveal_tokens){
             let token = encode_revel_token(reveal_token.0)?;
-            let proof = PedersenProof::new(reveal_token.1);;
-            let pub_key_hex = match encode_public_key(user_public_key){
-                Ok(p) => p,
-                Err(_e)=> return Err(DeckCustomError::InvalidPublicKey)
-            };
+            let proof = PedersenProof::new(reveal_token.1);
             reveal_token_map.insert(card_dto, RevealTokenDTO{
                 token:token,
                 proof: PedersenProofDTO{
@@ -365,13 +974,21 @@ impl DeckServiceTrait for DeckService {
                     b: proof.b,
                     r:proof.r
                 },
-                public_key:pub_key_hex,
+                public_key:pub_key_hex.clone(),
             });
         }
 
-        Ok(RevealTokenResponse {
+        let response = RevealTokenResponse {
             token_map: reveal_token_map,
-        })
+        };
+        let output = transcript_value(&response)?;
+        // checked again under the lock, a remask may have replaced a card since
+        self.update_game(&game_id, |game| {
+            game.reveal(&game_user_id, &revealed_cards)?;
+            game.record("reveal_token", input, output);
+            Ok(())
+        }).await?;
+        Ok(response)
     }
 
 
@@ -381,38 +998,28 @@ impl DeckServiceTrait for DeckService {
             Err(_e)=> return Err(DeckCustomError::InvalidProof)
         };
         let game_user_id = reveal_cards_request.game_user_id.clone();
+        let user = self.game_user(&game_user_id).await?;
+        let game_id = user.game_id.clone();
+        let game = self.game(&game_id).await?;
+        let parameters = self.game_parameters(&game, &reveal_cards_request.seed_hex)?;
+        let revealed_cards = encode_deck(shuffled_deck.iter().cloned())?;
+        for masked_card in revealed_cards.iter() {
+            game.check_reveal(&game_user_id, masked_card)?;
+        }
 
-        let user_db = self.user_db.lock().unwrap(); // 守卫生命周期开始
-        let get_user_result = user_db.get(&game_user_id);      // 守卫未释放，引用有效
-        let user = match get_user_result{
-            Some(game_user) => game_user,
-            None => return Err(DeckCustomError::UserNotFound),
-        };
-        let user_private_key = user.private_key.clone();
-        let user_public_key = user.public_key.clone();
-        let rng = &mut thread_rng();
-        let parameters = match CardProtocol::setup(rng, 2, 26){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
-        };
+        let user_public_key = user.public_key;
+        let reveal_tokens = self.key_store.compute_reveal_tokens(&parameters, &game_user_id, &shuffled_deck).await?;
         let mut reveal_cards  =  Vec::with_capacity(shuffled_deck.len());
 
-        for masked_card in shuffled_deck{
-            let reveal_card = match CardProtocol::compute_reveal_token(rng,&parameters,&user_private_key,&user_public_key,&masked_card){
-                Ok(p) => p,
-                Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
-            };
-
-            println!("origin proof{:?}", reveal_card.1.clone());
-            let proof_hex = encode_revel_proof(reveal_card.1.clone())?;
-            print!("proof_hex {:?}",proof_hex.clone());
-
-            let restored_proof = decode_revel_proof(proof_hex.clone())?;
-            println!("restored proof{:?}", restored_proof.clone());
-
+        for (masked_card, reveal_card) in shuffled_deck.into_iter().zip(reveal_tokens){
+            // never hand out a token other players would reject
+            if let Err(_e) = CardProtocol::verify_reveal(&parameters, &user_public_key, &reveal_card.0, &masked_card, &reveal_card.1){
+                return Err(DeckCustomError::InvalidProof)
+            }
 
             reveal_cards.push((masked_card,reveal_card.0,reveal_card.1));
         }
+        self.update_game(&game_id, |game| game.reveal(&game_user_id, &revealed_cards)).await?;
         let deck =match  RevealedDeck::new(reveal_cards){
             Ok(p) => p,
             Err(_e)=> return Err(DeckCustomError::SerializationError(String::from("Internal")))
@@ -423,83 +1030,580 @@ impl DeckServiceTrait for DeckService {
     }
 
 
-    async fn peek_cards(&self,peek_cards_request: PeekCardsRequest) -> Result<PeekCardsResponse, DeckCustomError>{
-        let mut restored_rng = restore_rnd(peek_cards_request.seed_hex)?;
-        let parameters= match   CardProtocol::setup(&mut restored_rng, 2, 26){
-            Ok(p) => p,
-            Err(_e)=> return Err(DeckCustomError::InvalidProof)
-        };
+    async fn verify_reveal_tokens(&self, verify_reveal_tokens_request: VerifyRevealTokensRequest)->Result<VerifyRevealTokensResponse, DeckCustomError>{
+        let game = self.game(&verify_reveal_tokens_request.game_id).await?;
+        let parameters = self.game_parameters(&game, &verify_reveal_tokens_request.seed_hex)?;
+
+        let mut results = Vec::with_capacity(verify_reveal_tokens_request.tokens.len());
+        for check in verify_reveal_tokens_request.tokens {
+            let masked_card_hex = check.masked_card.clone();
+            let public_key_hex = check.reveal_token.public_key.clone();
+            let verdict = decode_masked_card(check.masked_card)
+                .and_then(|masked_card| {
+                    let (reveal_token, proof, public_key) = check.reveal_token.into_reveal_token()?;
+                    match CardProtocol::verify_reveal(&parameters, &public_key, &reveal_token, &masked_card, &proof){
+                        Ok(()) => Ok(()),
+                        Err(_e) => Err(DeckCustomError::InvalidProof)
+                    }
+                });
+            results.push(RevealTokenVerdictDTO{
+                masked_card: masked_card_hex,
+                public_key: public_key_hex,
+                valid: verdict.is_ok(),
+                reason: verdict.err().map(|e| e.to_string()),
+            });
+        }
+        Ok(VerifyRevealTokensResponse{
+            results: results,
+        })
+    }
 
+    async fn peek_cards(&self,peek_cards_request: PeekCardsRequest) -> Result<PeekCardsResponse, DeckCustomError>{
         let game_user_id = peek_cards_request.game_user_id.clone();
-
-        let user_db = self.user_db.lock().unwrap(); // 守卫生命周期开始
-        let get_user_result = user_db.get(&game_user_id);      // 守卫未释放，引用有效
-        let user = match get_user_result{
-            Some(game_user) => game_user,
-            None => return Err(DeckCustomError::UserNotFound),
-        };
-        let user_private_key = user.private_key.clone();
-        let user_public_key = user.public_key.clone();
+        let input = transcript_value(&peek_cards_request)?;
+        let user = self.game_user(&game_user_id).await?;
+        let game_id = user.game_id.clone();
+        let game = self.game(&game_id).await?;
+        let parameters = self.game_parameters(&game, &peek_cards_request.seed_hex)?;
+
+        let user_public_key = user.public_key;
+        let mut masked_cards = Vec::with_capacity(peek_cards_request.peek_cards.len());
+        let mut peeked_cards = Vec::with_capacity(peek_cards_request.peek_cards.len());
+        for card in peek_cards_request.peek_cards.iter() {
+            let masked_card = decode_masked_card(card.card.clone())?;
+            let encoded_card = encode_masked_card(masked_card.clone())?;
+            game.check_peek(&encoded_card)?;
+            peeked_cards.push(encoded_card);
+            masked_cards.push(masked_card);
+        }
+        // our own token never leaves the agent, it is only combined with the others here
+        let own_tokens = self.key_store.compute_reveal_tokens(&parameters, &game_user_id, &masked_cards).await?;
 
         let mut card_map  = HashMap::new();
-        for card  in peek_cards_request.peek_cards{
-            let mask_card = decode_masked_card(card.card.clone())?;
+        for ((card, mask_card), (reveal_token, reveal_proof)) in peek_cards_request.peek_cards.into_iter().zip(masked_cards).zip(own_tokens){
             let mut tokens = Vec::with_capacity(card.reveal_tokens.len()+1);
             for token in card.reveal_tokens {
-                let reveal_token = decode_revel_token(token.token)?;
-                let proof = PedersenProof{
-                    a: token.proof.a,
-                    b:token.proof.b,
-                    r:token.proof.r,
-                }.to_curve()?;
-                let pub_key = match decode_public_key(token.public_key){
-                    Ok(p) => p,
-                    Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
-                };
-                tokens.push((reveal_token,proof,pub_key));
+                tokens.push(token.into_reveal_token()?);
             }
-
-            let rng =   &mut thread_rng();
-            let (reveal_token, reveal_proof) =
-                match CardProtocol::compute_reveal_token(rng, &parameters, &user_private_key, &user_public_key, &mask_card){
-                    Ok(p) => p,
-                    Err(_e)=> return Err(DeckCustomError::SerializationError(String::from("Internal")))
-                };
             tokens.push((reveal_token,reveal_proof,user_public_key));
             let unmasked_card = match CardProtocol::unmask(&parameters, &tokens, &mask_card){
                 Ok(p) => p,
                 Err(_e)=> return Err(DeckCustomError::InvalidCard)
             };
-            let card_hex = match encode_initial_card(unmasked_card){
-                Ok(p) => p,
-                Err(_e)=> return Err(DeckCustomError::InvalidCard)
-            };
-            card_map.insert(card.card.clone(), card_hex);
+            let classic_card = self.classic_card(&game, &unmasked_card)?;
+            card_map.insert(card.card.clone(), classic_card);
         }
-
+        // the transcript is exported to anyone, so it only says which cards were opened, not their faces
+        let mut opened_cards: Vec<&String> = card_map.keys().collect();
+        opened_cards.sort();
+        let output = serde_json::json!({ "opened_cards": opened_cards });
+        self.update_game(&game_id, |game| {
+            game.peek(&peeked_cards)?;
+            game.record("peek_cards", input, output);
+            Ok(())
+        }).await?;
         Ok(PeekCardsResponse{
             card_map: card_map,
         })
     }
 
     async fn open_cards(&self,open_cards_request: OpenCardsRequest)->Result<OpenCardsResponse, DeckCustomError>{
-        todo!();
-        // let rng = &mut thread_rng();
-        // let parameters = match CardProtocol::setup(rng, 2, 26){
-        //     Ok(p) => p,
-        //     Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Internal")))
-        // };
-        //
-        // let unmasked_card = CardProtocol::unmask(&parameters, reveal_tokens, card)?;
-        // let opened_card = card_mappings.get(&unmasked_card);
-        // let opened_card = opened_card.ok_or(GameErrors::InvalidCard)?;
-        //
-        // Ok(*opened_card)
+        let game_id = open_cards_request.game_id.clone();
+        let game = self.game(&game_id).await?;
+        game.check_dealt("reveal")?;
+        let parameters = self.game_parameters(&game, &open_cards_request.seed_hex)?;
+
+        // a card only opens with exactly one token of every player of the joint key
+        let mut player_keys = HashSet::with_capacity(game.players.len());
+        for player in game.players.iter() {
+            player_keys.insert(decode_deck_public_key(game.public_key(player)?.clone())?);
+        }
+
+        let mut recovered_tokens = self.recover_tokens(&game, &parameters, open_cards_request.recoveries).await?;
+        let mut reveal_tokens = open_cards_request.reveal_tokens;
+        let mut masked_cards = Vec::with_capacity(open_cards_request.shuffled_deck.cards.len());
+        let mut claims = Vec::new();
+        for card in open_cards_request.shuffled_deck.cards {
+            let masked_card = decode_masked_card(card.masked_card.clone())?;
+            let token_dtos = reveal_tokens.remove(&card.masked_card).unwrap_or_default();
+
+            let mut tokens = Vec::with_capacity(token_dtos.len());
+            let mut public_keys = HashSet::new();
+            for token in token_dtos {
+                let (reveal_token, proof, public_key) = token.into_reveal_token()?;
+                if !player_keys.contains(&public_key) {
+                    return Err(DeckCustomError::StateMismatch(format!("token for {} of a key outside the joint key", card.masked_card)))
+                }
+                if !public_keys.insert(public_key) {
+                    return Err(DeckCustomError::DuplicatePublicKey(card.masked_card.clone()))
+                }
+                claims.push(ProofClaim::Reveal{ public_key, reveal_token, masked_card, proof });
+                tokens.push(reveal_token);
+            }
+            // recovered tokens were verified through their partial tokens already
+            for (public_key, reveal_token) in recovered_tokens.remove(&card.masked_card).unwrap_or_default() {
+                if !public_keys.insert(public_key) {
+                    return Err(DeckCustomError::DuplicatePublicKey(card.masked_card.clone()))
+                }
+                tokens.push(reveal_token);
+            }
+            if public_keys != player_keys {
+                return Err(DeckCustomError::MissingFields(format!("reveal_tokens of every player for {}", card.masked_card)))
+            }
+            masked_cards.push((masked_card, tokens));
+        }
+
+        // all tokens of all cards are checked in one batch before anything is unmasked
+        let verdicts = verify_claims_blocking(parameters, claims).await?;
+        if verdicts.iter().any(|valid| !valid) {
+            return Err(DeckCustomError::InvalidRevealToken)
+        }
+
+        let mut opened_cards = Vec::with_capacity(masked_cards.len());
+        for (masked_card, tokens) in masked_cards {
+            let unmasked_card = batch::unmask_verified(&tokens, &masked_card);
+            let opened_card = self.classic_card(&game, &unmasked_card)?;
+            opened_cards.push(opened_card);
+        }
+        self.update_game(&game_id, |game| game.start_revealing()).await?;
+
+        Ok(OpenCardsResponse{
+            opened_cards: OpenedCards{
+                cards: opened_cards,
+            },
+        })
+    }
+
+    async fn parameters_cache_stats(&self) -> Result<ParametersCacheStats, DeckCustomError> {
+        Ok(self.params_cache.stats())
+    }
+
+    async fn deal(&self, deal_request: DealRequest) -> Result<DealResponse, DeckCustomError> {
+        if deal_request.cards.is_empty() {
+            return Err(DeckCustomError::MissingFields(String::from("cards")))
+        }
+        // positions decide which tokens we hand out, so only one of our own players records a deal
+        let dealer = deal_request.game_user_id.clone();
+        if self.game_user(&dealer).await?.game_id != deal_request.game_id {
+            return Err(DeckCustomError::GameMismatch(dealer))
+        }
+        let assignments = deal_request.cards.into_iter()
+            .map(|card| {
+                let owner = match card.game_user_id {
+                    Some(game_user_id) => CardOwner::Player(game_user_id),
+                    None => CardOwner::Community,
+                };
+                (card.position, owner)
+            })
+            .collect();
+        let dealt = self.update_game(&deal_request.game_id, |game| {
+            game.deal(&dealer, assignments)?;
+            Ok(game.assignments.len())
+        }).await?;
+        Ok(DealResponse{
+            dealt: dealt,
+        })
+    }
+
+    async fn showdown(&self, game_id: String) -> Result<ShowdownResponse, DeckCustomError> {
+        self.update_game(&game_id, |game| game.start_showdown()).await?;
+        Ok(ShowdownResponse{})
+    }
+
+    async fn finish_game(&self, game_id: String) -> Result<FinishGameResponse, DeckCustomError> {
+        self.update_game(&game_id, |game| game.finish()).await?;
+        self.seed_agreements.forget(&game_id);
+        // no token is issued anymore, the keys wait for disclose_key or the sweep
+        let held_keys = self.user_db.get_by_game_id(&game_id).await?.len();
+        Ok(FinishGameResponse{
+            game_id,
+            held_keys,
+        })
+    }
+
+    async fn sweep_expired(&self, ttl: Duration) -> Result<usize, DeckCustomError> {
+        let cutoff = unix_now().saturating_sub(ttl.as_secs());
+        let mut swept = 0;
+        for game_id in self.game_db.list_expired(cutoff).await? {
+            let _guard = self.game_updates.lock().await;
+            // it may have been touched since it was listed
+            match self.game_db.get_by_id(&game_id).await {
+                Ok(game) if game.is_expired(cutoff) => {}
+                Ok(_) | Err(DeckCustomError::GameNotFound) => continue,
+                Err(e) => return Err(e),
+            }
+            self.user_db.delete_by_game_id(&game_id).await?;
+            self.game_db.delete(&game_id).await?;
+            self.card_mappings.lock().unwrap().remove(&game_id);
+            self.seed_agreements.forget(&game_id);
+            swept += 1;
+        }
+        Ok(swept)
+    }
+
+    async fn seed_commit(&self, seed_commit_request: SeedCommitRequest) -> Result<SeedCommitmentDTO, DeckCustomError> {
+        // the seed of a set up game is fixed, a new round could only disagree with it
+        match self.game(&seed_commit_request.game_id).await {
+            Ok(_) => return Err(DeckCustomError::OutOfOrder(format!("game {} is already set up", seed_commit_request.game_id))),
+            Err(DeckCustomError::GameNotFound) => {}
+            Err(e) => return Err(e),
+        }
+        let commitment = self.seed_agreements.commit(&seed_commit_request.game_id, &seed_commit_request.game_user_id)?;
+        Ok(SeedCommitmentDTO{
+            game_user_id: seed_commit_request.game_user_id,
+            commitment,
+        })
+    }
+
+    async fn seed_reveal(&self, seed_reveal_request: SeedRevealRequest) -> Result<SeedRevealDTO, DeckCustomError> {
+        let mut commitments = BTreeMap::new();
+        for committed in seed_reveal_request.commitments {
+            if commitments.insert(committed.game_user_id.clone(), committed.commitment).is_some() {
+                return Err(DeckCustomError::DuplicatePlayer(committed.game_user_id))
+            }
+        }
+        let (commitment, contribution) = self.seed_agreements.reveal(&seed_reveal_request.game_id, &seed_reveal_request.game_user_id, commitments)?;
+        Ok(SeedRevealDTO{
+            game_user_id: seed_reveal_request.game_user_id,
+            commitment,
+            contribution,
+        })
+    }
+
+    async fn seed_combine(&self, seed_combine_request: SeedCombineRequest) -> Result<SeedCombineResponse, DeckCustomError> {
+        let mut reveals = BTreeMap::new();
+        for reveal in seed_combine_request.reveals {
+            if reveals.insert(reveal.game_user_id.clone(), (reveal.commitment, reveal.contribution)).is_some() {
+                return Err(DeckCustomError::DuplicatePlayer(reveal.game_user_id))
+            }
+        }
+        let seed_hex = self.seed_agreements.combine(&seed_combine_request.game_id, reveals)?;
+        match self.game(&seed_combine_request.game_id).await {
+            Ok(game) => game.check_seed(&seed_hex)?,
+            Err(DeckCustomError::GameNotFound) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(SeedCombineResponse{
+            game_id: seed_combine_request.game_id,
+            seed_hex,
+        })
+    }
+
+    async fn disclose_key(&self, game_id: String) -> Result<DiscloseKeyResponse, DeckCustomError> {
+        // only after finish_game, no token can be asked for once a key is out
+        let game = self.game(&game_id).await?;
+        game.check_finished("disclose_key")?;
+        let parameters = self.parameters(&game.seed_hex, &game.geometry)?;
+
+        let mut keys = Vec::new();
+        for game_user in self.user_db.get_by_game_id(&game_id).await? {
+            let context = key_ownership_context(&game_id, &game_user.game_user_id, &game.geometry, &game.seed_hex);
+            let proof = IdentityProof::new(self.key_store.prove_key_ownership(&parameters, &game_user.game_user_id, &context).await?);
+            let private_key = self.key_store.export_private_key(&parameters, &game_user.game_user_id).await?;
+            let public_key = match encode_public_key(game_user.public_key){
+                Ok(p) => p,
+                Err(_e)=> return Err(DeckCustomError::GenericError(String::from("Failed to serialize pk")))
+            };
+            keys.push(DisclosedKeyDTO{
+                game_user_id: game_user.game_user_id.clone(),
+                public_key: public_key,
+                private_key: encode_private_key(&private_key)?,
+                user_key_proof: Proof{
+                    commit: proof.commit,
+                    opening: proof.opening,
+                },
+            });
+        }
+        // disclosed before, or swept
+        if keys.is_empty() {
+            return Err(DeckCustomError::UserNotFound)
+        }
+        // once out they are no secret anymore, there is nothing left to keep
+        self.user_db.delete_by_game_id(&game_id).await?;
+        Ok(DiscloseKeyResponse{
+            game_id: game_id,
+            keys: keys,
+        })
+    }
+
+    async fn audit_game(&self, game_id: String, audit_game_request: AuditGameRequest) -> Result<AuditGameResponse, DeckCustomError> {
+        let game = self.game(&game_id).await?;
+        game.check_finished("audit")?;
+        let parameters = self.parameters(&game.seed_hex, &game.geometry)?;
+
+        let mut revealed_cards = HashMap::new();
+        for claim in audit_game_request.revealed_cards {
+            if claim.position >= game.deck.len() {
+                return Err(DeckCustomError::StateMismatch(format!("position {} is outside the deck", claim.position)))
+            }
+            if revealed_cards.insert(claim.position, claim.card).is_some() {
+                return Err(DeckCustomError::StateMismatch(format!("position {} is revealed more than once", claim.position)))
+            }
+        }
+
+        let mut private_keys = BTreeMap::new();
+        let mut key_verdicts = Vec::with_capacity(game.players.len());
+        for disclosed in audit_game_request.keys {
+            if key_verdicts.iter().any(|verdict| verdict.game_user_id == disclosed.game_user_id) {
+                return Err(DeckCustomError::DuplicatePlayer(disclosed.game_user_id))
+            }
+            let verdict = self.check_disclosed_key(&game, &parameters, &disclosed);
+            key_verdicts.push(KeyAuditDTO{
+                game_user_id: disclosed.game_user_id.clone(),
+                valid: verdict.is_ok(),
+                reason: verdict.as_ref().err().map(|e| e.to_string()),
+            });
+            if let Ok(private_key) = verdict {
+                private_keys.insert(disclosed.game_user_id, private_key);
+            }
+        }
+        for player in game.players.iter() {
+            if !key_verdicts.iter().any(|verdict| verdict.game_user_id == *player) {
+                key_verdicts.push(KeyAuditDTO{
+                    game_user_id: player.clone(),
+                    valid: false,
+                    reason: Some(DeckCustomError::AuditFailed(format!("no key disclosed for {}", player)).to_string()),
+                });
+            }
+        }
+
+        // a position only decrypts with the key of every player in the joint key
+        let mut positions = Vec::with_capacity(game.deck.len());
+        if !game.players.is_empty() && game.players.iter().all(|player| private_keys.contains_key(player)) {
+            let private_keys: Vec<SecretScalar> = private_keys.into_values().collect();
+            let mut decrypted = HashSet::new();
+            for (position, masked_card_hex) in game.deck.iter().enumerate() {
+                let revealed = revealed_cards.get(&position).copied();
+                let card = decode_masked_card(masked_card_hex.clone()).and_then(|masked_card| {
+                    let card = audit::decrypt(&private_keys, &masked_card);
+                    if !decrypted.insert(card) {
+                        return Err(DeckCustomError::AuditFailed(format!("position {} holds a card already in the deck", position)))
+                    }
+                    self.classic_card(&game, &card)
+                });
+                let (card, reason) = match card {
+                    Ok(card) if revealed.map_or(false, |revealed| revealed != card) => (Some(card),
+                        Some(DeckCustomError::AuditFailed(format!("position {} was revealed as another card", position)).to_string())),
+                    Ok(card) => (Some(card), None),
+                    Err(e) => (None, Some(e.to_string())),
+                };
+                positions.push(PositionAuditDTO{
+                    position: position,
+                    masked_card: masked_card_hex.clone(),
+                    card: card,
+                    revealed: revealed,
+                    valid: reason.is_none(),
+                    reason: reason,
+                });
+            }
+        }
+
+        Ok(AuditGameResponse{
+            game_id: game_id,
+            all_valid: key_verdicts.iter().all(|v| v.valid) && !positions.is_empty() && positions.iter().all(|v| v.valid),
+            keys: key_verdicts,
+            positions: positions,
+        })
+    }
+
+    async fn verify_key_shares(&self, verify_key_shares_request: VerifyKeySharesRequest) -> Result<VerifyKeySharesResponse, DeckCustomError> {
+        let game_user_id = verify_key_shares_request.game_user_id.clone();
+        if self.game_user(&game_user_id).await?.game_id != verify_key_shares_request.game_id {
+            return Err(DeckCustomError::GameMismatch(game_user_id))
+        }
+        let game = self.game(&verify_key_shares_request.game_id).await?;
+        let parameters = self.game_parameters(&game, &verify_key_shares_request.seed_hex)?;
+
+        let mut results = Vec::with_capacity(verify_key_shares_request.dealings.len());
+        let mut verified = Vec::new();
+        for dealing in verify_key_shares_request.dealings {
+            let verdict = self.open_key_share(&game, &parameters, &game_user_id, &dealing).await.map(|_share| ());
+            let valid = verdict.is_ok();
+            results.push(KeyShareVerdictDTO{
+                dealer: dealing.dealer.clone(),
+                valid: valid,
+                reason: verdict.err().map(|e| e.to_string()),
+            });
+            if valid {
+                verified.push(dealing);
+            }
+        }
+        if !verified.is_empty() {
+            self.update_game(&game.game_id, |game| {
+                for dealing in verified {
+                    game.record_dealing(dealing)?;
+                }
+                Ok(())
+            }).await?;
+        }
+        Ok(VerifyKeySharesResponse{
+            results: results,
+        })
+    }
+
+    async fn partial_reveal_tokens(&self, partial_reveal_tokens_request: PartialRevealTokensRequest) -> Result<PartialRevealTokensResponse, DeckCustomError> {
+        let game_user_id = partial_reveal_tokens_request.game_user_id.clone();
+        let dealer = partial_reveal_tokens_request.dealer_game_user_id.clone();
+        if self.game_user(&game_user_id).await?.game_id != partial_reveal_tokens_request.game_id {
+            return Err(DeckCustomError::GameMismatch(game_user_id))
+        }
+        let game = self.game(&partial_reveal_tokens_request.game_id).await?;
+        let parameters = self.game_parameters(&game, &partial_reveal_tokens_request.seed_hex)?;
+        let dealing = game.key_dealing(&dealer)?;
+
+        // the share stands in for the dealer, so the dealer's reveal rules apply
+        let mut masked_cards = Vec::with_capacity(partial_reveal_tokens_request.cards.len());
+        let mut revealed_cards = Vec::with_capacity(partial_reveal_tokens_request.cards.len());
+        for card in partial_reveal_tokens_request.cards.iter() {
+            let masked_card = decode_masked_card(card.clone())?;
+            let encoded_card = encode_masked_card(masked_card.clone())?;
+            game.check_reveal(&dealer, &encoded_card)?;
+            revealed_cards.push(encoded_card);
+            masked_cards.push(masked_card);
+        }
+
+        let (index, share) = self.open_key_share(&game, &parameters, &game_user_id, dealing).await?;
+        let share_key = vss::share_public_key(dealing, index)?;
+        let mut partial_tokens = Vec::with_capacity(masked_cards.len());
+        let rng = &mut thread_rng();
+        for (card, masked_card) in partial_reveal_tokens_request.cards.into_iter().zip(masked_cards) {
+            let (reveal_token, reveal_proof) = match CardProtocol::compute_reveal_token(rng, &parameters, share.expose(), &share_key, &masked_card){
+                Ok(reveal_token) => reveal_token,
+                Err(_e) => return Err(DeckCustomError::GenericError(String::from("Internal")))
+            };
+            let proof = PedersenProof::new(reveal_proof);
+            partial_tokens.push(PartialRevealTokenDTO{
+                card: card,
+                index: index,
+                token: encode_revel_token(reveal_token)?,
+                proof: PedersenProofDTO{
+                    a: proof.a,
+                    b: proof.b,
+                    r: proof.r
+                },
+            });
+        }
+        self.update_game(&partial_reveal_tokens_request.game_id, |game| game.reveal(&dealer, &revealed_cards)).await?;
+        Ok(PartialRevealTokensResponse{
+            dealer_game_user_id: dealer,
+            partial_tokens: partial_tokens,
+        })
+    }
+
+    async fn recover_reveal_tokens(&self, recover_reveal_tokens_request: RecoverRevealTokensRequest) -> Result<RecoverRevealTokensResponse, DeckCustomError> {
+        let game_id = recover_reveal_tokens_request.game_id.clone();
+        let game = self.game(&game_id).await?;
+        let parameters = self.game_parameters(&game, &recover_reveal_tokens_request.seed_hex)?;
+        let dealer = recover_reveal_tokens_request.recovery.dealer_game_user_id.clone();
+        let public_key = game.public_key(&dealer)?.clone();
+
+        let mut tokens = HashMap::new();
+        for (card, recovered) in self.recover_tokens(&game, &parameters, vec![recover_reveal_tokens_request.recovery]).await? {
+            for (_public_key, reveal_token) in recovered {
+                tokens.insert(card.clone(), encode_revel_token(reveal_token)?);
+            }
+        }
+        self.update_game(&game_id, |game| game.start_revealing()).await?;
+        Ok(RecoverRevealTokensResponse{
+            dealer_game_user_id: dealer,
+            public_key: public_key,
+            tokens: tokens,
+        })
+    }
+
+    async fn transcript(&self, game_id: String) -> Result<GameTranscriptLogResponse, DeckCustomError> {
+        let game = self.game(&game_id).await?;
+        // a stored log that no longer chains was changed behind the agent's back
+        game.transcript.verify(&game_id)?;
+        Ok(GameTranscriptLogResponse{
+            version: TRANSCRIPT_LOG_VERSION,
+            head: game.transcript.head(),
+            game_id: game_id,
+            entries: game.transcript.entries,
+        })
+    }
+
+    async fn batch_verify(&self, batch_verify_request: BatchVerifyRequest) -> Result<BatchVerifyResponse, DeckCustomError> {
+        let game = self.game(&batch_verify_request.game_id).await?;
+        let geometry = game.geometry;
+        let parameters = self.game_parameters(&game, &batch_verify_request.seed_hex)?;
+        let joint_key = match batch_verify_request.joined_key {
+            Some(joined_key) => Some(decode_deck_public_key(joined_key)?),
+            None if !batch_verify_request.masks.is_empty() => return Err(DeckCustomError::MissingFields(String::from("joined_key"))),
+            None => None,
+        };
+
+        // claims that fail to decode get their verdict right away and are not sent to the batch
+        let mut claims = Vec::new();
+        let mut reveal_token_verdicts = Vec::with_capacity(batch_verify_request.reveal_tokens.len());
+        for check in batch_verify_request.reveal_tokens {
+            let mut verdict = RevealTokenVerdictDTO{
+                masked_card: check.masked_card.clone(),
+                public_key: check.reveal_token.public_key.clone(),
+                valid: false,
+                reason: None,
+            };
+            let claim = decode_masked_card(check.masked_card).and_then(|masked_card| {
+                let (reveal_token, proof, public_key) = check.reveal_token.into_reveal_token()?;
+                Ok(ProofClaim::Reveal{ public_key, reveal_token, masked_card, proof })
+            });
+            match claim {
+                Ok(claim) => claims.push(claim),
+                Err(e) => verdict.reason = Some(e.to_string()),
+            }
+            reveal_token_verdicts.push(verdict);
+        }
+        let mut mask_verdicts = Vec::with_capacity(batch_verify_request.masks.len());
+        for check in batch_verify_request.masks {
+            let mut verdict = MaskVerdictDTO{
+                masked_card: check.masked_card.masked_card.clone(),
+                valid: false,
+                reason: None,
+            };
+            let claim = decode_initial_card(check.card).and_then(|card| {
+                let joint_key = joint_key.ok_or(DeckCustomError::MissingFields(String::from("joined_key")))?;
+                let (masked_card, proof) = MaskDeck{ cards: vec![check.masked_card] }.into_masked_card()?.remove(0);
+                Ok(ProofClaim::Mask{ joint_key, card, masked_card, proof })
+            });
+            match claim {
+                Ok(claim) => claims.push(claim),
+                Err(e) => verdict.reason = Some(e.to_string()),
+            }
+            mask_verdicts.push(verdict);
+        }
+
+        let mut verdicts = verify_claims_blocking(parameters, claims).await?.into_iter();
+        for verdict in reveal_token_verdicts.iter_mut().filter(|v| v.reason.is_none()) {
+            verdict.valid = verdicts.next().unwrap_or(false);
+            if !verdict.valid {
+                verdict.reason = Some(DeckCustomError::InvalidProof.to_string());
+            }
+        }
+        for verdict in mask_verdicts.iter_mut().filter(|v| v.reason.is_none()) {
+            verdict.valid = verdicts.next().unwrap_or(false);
+            if !verdict.valid {
+                verdict.reason = Some(DeckCustomError::InvalidProof.to_string());
+            }
+        }
+
+        Ok(BatchVerifyResponse{
+            all_valid: reveal_token_verdicts.iter().all(|v| v.valid) && mask_verdicts.iter().all(|v| v.valid),
+            reveal_tokens: reveal_token_verdicts,
+            masks: mask_verdicts,
+        })
     }
 }
 
-use crate::card::classic_card::{Suite,ClassicPlayingCard,Value};
-use ark_ff::{to_bytes, UniformRand};
+use crate::card::classic_card::ClassicPlayingCard;
+use crate::deck::models::geometry::DeckGeometry;
+use crate::game::models::game::{unix_now, CardOwner, Game};
+use crate::game::repository::GameDbTrait;
+use crate::keys::key_store::KeyStore;
+use crate::keys::secret::SecretScalar;
+use crate::keys::vss::{self, KeyDealing, ShareRecipient};
+use ark_ff::{to_bytes, UniformRand, Zero};
+use blake2::{Blake2s, Digest};
+use crate::serialize::fields::length_prefixed;
 use asn1_der::e;
 use proof_essentials::vector_commitment::pedersen::PedersenCommitment;
 use proof_essentials::zkp::arguments::shuffle;
@@ -510,7 +1614,53 @@ use crate::serialize::proof::{IdentityProof, PedersenProof};
 use crate::user::errors::CustomError;
 use crate::user::models::user::User;
 
-fn restore_rnd(seed_hex:String)->Result<ChaCha20Rng,DeckCustomError>{
+const CARD_ENCODING_DOMAIN: &[u8] = b"deck_agent/card_encoding/v1";
+const KEY_OWNERSHIP_DOMAIN: &[u8] = b"deck_agent/key_ownership/v1";
+
+// public info the key ownership proof is bound to, so a key and proof cannot be
+// replayed in another game, deck or seed. every field is length prefixed.
+pub(crate) fn key_ownership_context(game_id: &str, game_user_id: &str, geometry: &DeckGeometry, seed_hex: &str) -> Vec<u8> {
+    length_prefixed(&[
+        KEY_OWNERSHIP_DOMAIN,
+        game_id.as_bytes(),
+        game_user_id.as_bytes(),
+        seed_hex.to_lowercase().as_bytes(),
+        &(geometry.m as u64).to_le_bytes(),
+        &(geometry.n as u64).to_le_bytes(),
+    ])
+}
+
+// hex of each card as the game stores it, so decks from requests compare regardless of encoding
+fn encode_deck(masked_cards: impl Iterator<Item = MaskedCard>) -> Result<Vec<String>, DeckCustomError> {
+    masked_cards.map(encode_masked_card).collect()
+}
+
+// proof checks are CPU bound, keep them off the async workers
+async fn verify_claims_blocking(parameters: Arc<Parameters>, claims: Vec<ProofClaim>) -> Result<Vec<bool>, DeckCustomError> {
+    match rocket::tokio::task::spawn_blocking(move || batch::verify_claims(&parameters, &claims)).await {
+        Ok(verdicts) => Ok(verdicts),
+        Err(e) => Err(DeckCustomError::GenericError(e.to_string())),
+    }
+}
+
+// remasking works on any subset of the deck, but never on more cards than it holds
+fn check_card_count(geometry: &DeckGeometry, len: usize) -> Result<(), DeckCustomError> {
+    if len == 0 || len > geometry.num_of_cards() {
+        return Err(DeckCustomError::InvalidDeckSize(format!("expected 1 to {} cards, got {}", geometry.num_of_cards(), len)));
+    }
+    Ok(())
+}
+
+fn transcript_value<T: Serialize>(value: &T) -> Result<serde_json::Value, DeckCustomError> {
+    match serde_json::to_value(value) {
+        Ok(value) => Ok(value),
+        Err(e) => Err(DeckCustomError::SerializationError(e.to_string())),
+    }
+}
+
+// the card plaintexts are drawn from their own stream so they never overlap with
+// the randomness CardProtocol::setup consumes from the same seed
+fn card_encoding_rng(seed_hex:String)->Result<ChaCha20Rng,DeckCustomError>{
     let  seed = match Vec::from_hex(seed_hex){
         Ok(seed) => seed,
         Err(_e) => return Err(DeckCustomError::InvalidSeed)
@@ -518,28 +1668,42 @@ fn restore_rnd(seed_hex:String)->Result<ChaCha20Rng,DeckCustomError>{
     if seed.len()!=32{
         return Err(DeckCustomError::InvalidSeed)
     }
-    let array_data: [u8; 32] = seed.try_into()
-        .expect("Vec<u8> 的长度必须为 32");
-    let  restored_rng = ChaCha20Rng::from_seed(array_data);
-    Ok(restored_rng)
+    let mut hasher = Blake2s::new();
+    hasher.update(CARD_ENCODING_DOMAIN);
+    hasher.update(&seed);
+    let mut card_seed = [0u8; 32];
+    card_seed.copy_from_slice(&hasher.finalize());
+    Ok(ChaCha20Rng::from_seed(card_seed))
 }
 
-fn encode_cards<R: Rng>(rng: &mut R, num_of_cards: usize) -> HashMap<Card, ClassicPlayingCard> {
-    let mut map: HashMap<Card, ClassicPlayingCard> = HashMap::new();
-    let plaintexts = (0..num_of_cards)
-        .map(|_| Card::rand(rng))
-        .collect::<Vec<_>>();
+// deterministic (Card, ClassicPlayingCard) pairs in deck order for the given seed
+pub(crate) fn encode_cards(seed_hex:String, geometry: &DeckGeometry) -> Result<Vec<(Card, ClassicPlayingCard)>,DeckCustomError> {
+    let mut rng = card_encoding_rng(seed_hex)?;
+    let classic_cards = match ClassicPlayingCard::deck_of(geometry.num_of_cards()){
+        Some(cards) => cards,
+        None => return Err(DeckCustomError::InvalidGeometry(format!("unsupported deck of {} cards", geometry.num_of_cards())))
+    };
+    let mut cards = Vec::with_capacity(classic_cards.len());
+    for current_card in classic_cards {
+        cards.push((Card::rand(&mut rng), current_card));
+    }
+
+    Ok(cards)
+}
 
-    let mut i = 0;
-    for value in Value::VALUES.iter().copied() {
-        for suite in Suite::VALUES.iter().copied() {
-            let current_card = ClassicPlayingCard::new(value, suite);
-            map.insert(plaintexts[i], current_card);
-            i += 1;
+// the cards to mask are the ones the seed encodes, in order, a deck with a card swapped or repeated
+// would still carry valid masking proofs
+fn decode_game_cards(game: &Game, cards: Vec<String>) -> Result<Vec<Card>, DeckCustomError> {
+    let expected = encode_cards(game.seed_hex.clone(), &game.geometry)?;
+    let mut initial_cards = Vec::with_capacity(cards.len());
+    for (card, (expected_card, _)) in cards.into_iter().zip(expected.iter()) {
+        let initial_card = decode_initial_card(card)?;
+        if initial_card != *expected_card {
+            return Err(DeckCustomError::StateMismatch(format!("card {} is not the card the seed of game {} encodes", initial_cards.len(), game.game_id)))
         }
+        initial_cards.push(initial_card);
     }
-
-    map
+    Ok(initial_cards)
 }
 
 // pub fn peek_at_card(
@@ -567,6 +1731,845 @@ fn encode_cards<R: Rng>(rng: &mut R, num_of_cards: usize) -> HashMap<Card, Class
 
 #[cfg(test)]
 mod unit_tests {
+    use super::*;
+    use crate::game::mem::game_mem::GameMem;
+    use crate::game::repository::MockGameDbTrait;
+    use crate::game::models::game::GamePhase;
+    use crate::game_user::mem::game_user_mem::GameUserMem;
+    use crate::keys::software_key_store::SoftwareKeyStore;
+    use crate::deck::models::deck_case::deck::{Player, CardAssignmentDTO, MaskedCardDTO, ShuffleStepDTO, PeekCardInput};
+    use crate::deck::transcript::{self, GameTranscript};
+    use crate::game::models::transcript::TranscriptLog;
+
+    const SEED_HEX: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_encode_cards_is_deterministic() {
+        let geometry = DeckGeometry::CLASSIC;
+        let first = encode_cards(SEED_HEX.to_string(), &geometry).unwrap();
+        let second = encode_cards(SEED_HEX.to_string(), &geometry).unwrap();
+        assert_eq!(first.len(), geometry.num_of_cards());
+        assert_eq!(first, second);
+
+        let other_seed = "ff".repeat(32);
+        let third = encode_cards(other_seed, &geometry).unwrap();
+        assert_ne!(first, third);
+    }
+
+    #[test]
+    fn test_key_ownership_context_is_game_scoped() {
+        let geometry = DeckGeometry::CLASSIC;
+        let context = key_ownership_context("game-1", "player-1", &geometry, SEED_HEX);
+        assert_eq!(context, key_ownership_context("game-1", "player-1", &geometry, SEED_HEX));
+        assert_ne!(context, key_ownership_context("game-2", "player-1", &geometry, SEED_HEX));
+        assert_ne!(context, key_ownership_context("game-1", "player-1", &DeckGeometry::new(4, 9).unwrap(), SEED_HEX));
+        assert_ne!(context, key_ownership_context("game-1", "player-1", &geometry, &"ff".repeat(32)));
+        // length prefixes keep the field boundaries unambiguous
+        assert_ne!(key_ownership_context("ab", "c", &geometry, SEED_HEX), key_ownership_context("a", "bc", &geometry, SEED_HEX));
+    }
+
+    #[test]
+    fn test_encode_cards_invalid_seed() {
+        assert_eq!(encode_cards(String::from("abcd"), &DeckGeometry::CLASSIC), Err(DeckCustomError::InvalidSeed));
+    }
 
+    fn mem_service() -> DeckService {
+        let game_users: Arc<dyn GameUserMemTrait> = Arc::new(GameUserMem::new());
+        DeckService::new(game_users.clone(), Box::new(GameMem::new()), Box::new(SoftwareKeyStore::new(game_users)))
+    }
+
+    fn set_up_request(game_id: &str, game_user_id: &str) -> SetUpDeckRequest {
+        SetUpDeckRequest{
+            user_id: String::from("user"),
+            game_id: game_id.to_string(),
+            game_user_id: game_user_id.to_string(),
+            seed_hex: SEED_HEX.to_string(),
+            m: 2,
+            n: 26,
+            share_threshold: None,
+        }
+    }
+
+    // the smallest supported deck keeps the shuffle proofs of the table tests quick
+    fn table_request(game_id: &str, game_user_id: &str) -> SetUpDeckRequest {
+        SetUpDeckRequest{ n: 16, ..set_up_request(game_id, game_user_id) }
+    }
+
+    async fn join_table(service: &DeckService, game_id: &str, game_user_ids: &[&str]) -> Vec<Player> {
+        let mut players = Vec::with_capacity(game_user_ids.len());
+        for game_user_id in game_user_ids {
+            let set_up = service.setup(table_request(game_id, game_user_id)).await.unwrap();
+            players.push(Player{
+                game_id: game_id.to_string(),
+                game_user_id: game_user_id.to_string(),
+                public_key: set_up.user_public_key,
+                user_key_proof: set_up.user_key_proof,
+            });
+        }
+        players
+    }
+
+    // one agent holding every player of the table, keys aggregated and the deck masked
+    async fn masked_table(game_id: &str, game_user_ids: &[&str]) -> (DeckService, String, Vec<String>) {
+        let service = mem_service();
+        let players = join_table(&service, game_id, game_user_ids).await;
+        let (joined_key, deck) = mask_table(&service, game_id, players).await;
+        (service, joined_key, deck)
+    }
+
+    // only the local player sits at the returned agent, the others are set up at another one
+    // and reach this agent through verify_shuffle_chain
+    async fn watched_table(game_id: &str, local: &str, remote: &[&str]) -> (DeckService, String, Vec<String>) {
+        let service = mem_service();
+        let mut players = join_table(&service, game_id, &[local]).await;
+        players.extend(join_table(&mem_service(), game_id, remote).await);
+        let (joined_key, deck) = mask_table(&service, game_id, players).await;
+        (service, joined_key, deck)
+    }
+
+    async fn mask_table(service: &DeckService, game_id: &str, players: Vec<Player>) -> (String, Vec<String>) {
+        let joined_key = service.compute_aggregate_key(ComputeAggregateKeyRequest{
+            game_id: game_id.to_string(),
+            players,
+            seed_hex: SEED_HEX.to_string(),
+        }).await.unwrap().joined_key;
+        let initial_deck = service.initial_deck(InitialDeckRequest{ m: 2, n: 16, seed_hex: Some(SEED_HEX.to_string()) }).await.unwrap();
+        let masked = service.mask(MaskRequest{
+            game_id: game_id.to_string(),
+            seed_hex: SEED_HEX.to_string(),
+            joined_key: joined_key.clone(),
+            cards: initial_deck.cards.into_iter().map(|card| card.card).collect(),
+        }).await.unwrap();
+        let deck = masked.cards.into_iter().map(|card| card.masked_card).collect();
+        (joined_key, deck)
+    }
+
+    async fn local_shuffle(service: &DeckService, game_id: &str, joined_key: &str, game_user_id: &str, cards: Vec<String>) -> Result<Vec<String>, DeckCustomError> {
+        Ok(service.shuffle(ShuffleRequest{
+            game_id: game_id.to_string(),
+            game_user_id: game_user_id.to_string(),
+            seed_hex: SEED_HEX.to_string(),
+            joined_key: joined_key.to_string(),
+            cards,
+        }).await?.cards)
+    }
+
+    // the masked table, shuffled by every player in turn
+    async fn shuffled_table(game_id: &str, game_user_ids: &[&str]) -> (DeckService, String, Vec<String>) {
+        let (service, joined_key, mut deck) = masked_table(game_id, game_user_ids).await;
+        for game_user_id in game_user_ids {
+            deck = local_shuffle(&service, game_id, &joined_key, game_user_id, deck).await.unwrap();
+        }
+        (service, joined_key, deck)
+    }
 
-}
\ No newline at end of file
+    async fn deal(service: &DeckService, game_id: &str, dealer: &str, cards: Vec<(usize, Option<&str>)>) {
+        service.deal(DealRequest{
+            game_id: game_id.to_string(),
+            game_user_id: dealer.to_string(),
+            cards: cards.into_iter().map(|(position, owner)| CardAssignmentDTO{ position, game_user_id: owner.map(String::from) }).collect(),
+        }).await.unwrap();
+    }
+
+    async fn reveal_tokens(service: &DeckService, game_user_id: &str, cards: &[String]) -> Result<HashMap<String, RevealTokenDTO>, DeckCustomError> {
+        Ok(service.reveal_token(RevealTokenRequest{
+            game_user_id: game_user_id.to_string(),
+            seed_hex: SEED_HEX.to_string(),
+            reveal_cards: cards.to_vec(),
+        }).await?.token_map)
+    }
+
+    fn open_request(game_id: &str, cards: &[String], reveal_tokens: HashMap<String, Vec<RevealTokenDTO>>) -> OpenCardsRequest {
+        OpenCardsRequest{
+            game_id: game_id.to_string(),
+            seed_hex: SEED_HEX.to_string(),
+            shuffled_deck: ShuffledDeck{ cards: cards.iter().map(|card| MaskedCardDTO{ masked_card: card.clone() }).collect() },
+            reveal_tokens,
+            recoveries: Vec::new(),
+        }
+    }
+
+    fn remask_request(game_id: &str, joined_key: &str, cards: Vec<String>) -> RemaskRequest {
+        RemaskRequest{
+            game_id: game_id.to_string(),
+            seed_hex: SEED_HEX.to_string(),
+            joined_key: joined_key.to_string(),
+            cards,
+        }
+    }
+
+    // what another agent's remask of the card would look like
+    fn remask_card(service: &DeckService, joined_key: &str, origin_card: &str) -> RemaskedCardDTO {
+        let parameters = service.parameters(SEED_HEX, &DeckGeometry::new(2, 16).unwrap()).unwrap();
+        let joint_key = decode_deck_public_key(joined_key.to_string()).unwrap();
+        let rng = &mut thread_rng();
+        let masked_card = decode_masked_card(origin_card.to_string()).unwrap();
+        let (remasked_card, proof) = CardProtocol::remask(rng, &parameters, &joint_key, &masked_card, &Scalar::rand(rng)).unwrap();
+        let proof = PedersenProof::new(proof);
+        RemaskedCardDTO{
+            origin_card: origin_card.to_string(),
+            masked_card: encode_masked_card(remasked_card).unwrap(),
+            proof: PedersenProofDTO{ a: proof.a, b: proof.b, r: proof.r },
+        }
+    }
+
+    #[test]
+    fn test_card_count_bounds() {
+        let geometry = DeckGeometry::new(2, 16).unwrap();
+        assert!(matches!(check_card_count(&geometry, 0), Err(DeckCustomError::InvalidDeckSize(_))));
+        assert!(check_card_count(&geometry, 1).is_ok());
+        assert!(check_card_count(&geometry, 32).is_ok());
+        assert!(matches!(check_card_count(&geometry, 33), Err(DeckCustomError::InvalidDeckSize(_))));
+    }
+
+    #[tokio::test]
+    async fn test_mask_refuses_cards_the_seed_does_not_encode() {
+        let service = mem_service();
+        let players = join_table(&service, "duplicated", &["player1"]).await;
+        let joined_key = service.compute_aggregate_key(ComputeAggregateKeyRequest{
+            game_id: String::from("duplicated"),
+            players,
+            seed_hex: SEED_HEX.to_string(),
+        }).await.unwrap().joined_key;
+        let initial_deck = service.initial_deck(InitialDeckRequest{ m: 2, n: 16, seed_hex: Some(SEED_HEX.to_string()) }).await.unwrap();
+        let mut cards: Vec<String> = initial_deck.cards.into_iter().map(|card| card.card).collect();
+        cards[1] = cards[0].clone();
+        let request = MaskRequest{
+            game_id: String::from("duplicated"),
+            seed_hex: SEED_HEX.to_string(),
+            joined_key,
+            cards,
+        };
+        assert!(matches!(service.mask(request).await, Err(DeckCustomError::StateMismatch(_))));
+        assert_eq!(service.game("duplicated").await.unwrap().phase, GamePhase::KeysAggregated);
+    }
+
+    #[tokio::test]
+    async fn test_remasked_card_keeps_its_position() {
+        let (service, joined_key, deck) = shuffled_table("remasked", &["player1", "player2"]).await;
+        deal(&service, "remasked", "player1", vec![(0, None)]).await;
+        let open = |card: &String, tokens: Vec<RevealTokenDTO>| open_request("remasked", &[card.clone()], HashMap::from([(card.clone(), tokens)]));
+        let mut tokens = Vec::new();
+        for player in ["player1", "player2"] {
+            tokens.push(reveal_tokens(&service, player, &deck[..1]).await.unwrap().remove(&deck[0]).unwrap());
+        }
+        let before = service.open_cards(open(&deck[0], tokens)).await.unwrap().opened_cards.cards;
+
+        assert!(matches!(service.remask(remask_request("remasked", &joined_key, Vec::new())).await, Err(DeckCustomError::InvalidDeckSize(_))));
+        assert!(matches!(service.remask(remask_request("remasked", &joined_key, vec![deck[0].clone(); 33])).await, Err(DeckCustomError::InvalidDeckSize(_))));
+        let remasked = service.remask(remask_request("remasked", &joined_key, vec![deck[0].clone()])).await.unwrap().cards.remove(0);
+        let game = service.game("remasked").await.unwrap();
+        assert_eq!(game.deck[0], remasked.masked_card);
+        let entry = game.transcript.entries.last().unwrap();
+        assert_eq!(entry.operation, "remask");
+        assert_eq!(entry.output["cards"][0]["masked_card"], remasked.masked_card.as_str());
+        // the old ciphertext is gone from the deck, the new one opens to the same card
+        assert!(matches!(reveal_tokens(&service, "player1", &deck[..1]).await, Err(DeckCustomError::RevealNotAllowed(_))));
+        let remasked_cards = vec![remasked.masked_card.clone()];
+        let mut tokens = Vec::new();
+        for player in ["player1", "player2"] {
+            tokens.push(reveal_tokens(&service, player, &remasked_cards).await.unwrap().remove(&remasked.masked_card).unwrap());
+        }
+        assert_eq!(service.open_cards(open(&remasked.masked_card, tokens)).await.unwrap().opened_cards.cards, before);
+    }
+
+    #[tokio::test]
+    async fn test_verify_remask_rejects_a_bad_proof() {
+        let (service, joined_key, deck) = shuffled_table("verified_remask", &["player1", "player2"]).await;
+        let first = remask_card(&service, &joined_key, &deck[0]);
+        let second = remask_card(&service, &joined_key, &deck[1]);
+        let verify = |cards: Vec<RemaskedCardDTO>| VerifyRemaskRequest{
+            game_id: String::from("verified_remask"),
+            seed_hex: SEED_HEX.to_string(),
+            joined_key: joined_key.clone(),
+            cards,
+        };
+
+        let swapped = RemaskedCardDTO{ proof: second.proof.clone(), ..first.clone() };
+        assert!(matches!(service.verify_remask(verify(vec![swapped])).await, Err(DeckCustomError::InvalidProof)));
+        assert_eq!(service.game("verified_remask").await.unwrap().deck, deck);
+
+        service.verify_remask(verify(vec![first.clone(), second.clone()])).await.unwrap();
+        let game = service.game("verified_remask").await.unwrap();
+        assert_eq!(game.deck[0], first.masked_card);
+        assert_eq!(game.deck[1], second.masked_card);
+        // the same remask can't be applied twice, its origin is no longer in the deck
+        assert!(matches!(service.verify_remask(verify(vec![first])).await, Err(DeckCustomError::StateMismatch(_))));
+    }
+
+    fn aggregate_request(game_id: &str, players: Vec<Player>) -> ComputeAggregateKeyRequest {
+        ComputeAggregateKeyRequest{
+            game_id: game_id.to_string(),
+            players,
+            seed_hex: SEED_HEX.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_key_rejects_a_repeated_player() {
+        let service = mem_service();
+        let mut players = join_table(&service, "repeated", &["player1"]).await;
+        players.push(players[0].clone());
+        let result = service.compute_aggregate_key(aggregate_request("repeated", players)).await;
+        assert!(matches!(result, Err(DeckCustomError::DuplicatePlayer(game_user_id)) if game_user_id == "player1"));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_key_rejects_a_copied_public_key() {
+        let service = mem_service();
+        let mut players = join_table(&service, "copied", &["player1"]).await;
+        players.push(Player{ game_user_id: String::from("player2"), ..players[0].clone() });
+        let result = service.compute_aggregate_key(aggregate_request("copied", players)).await;
+        assert!(matches!(result, Err(DeckCustomError::DuplicatePublicKey(game_user_id)) if game_user_id == "player2"));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_key_rejects_the_identity() {
+        let service = mem_service();
+        let mut players = join_table(&service, "identity", &["player1"]).await;
+        players.push(Player{
+            game_user_id: String::from("player2"),
+            public_key: encode_public_key(PublicKey::zero()).unwrap(),
+            ..players[0].clone()
+        });
+        let result = service.compute_aggregate_key(aggregate_request("identity", players)).await;
+        assert!(matches!(result, Err(DeckCustomError::IdentityPublicKey(game_user_id)) if game_user_id == "player2"));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_key_must_include_our_players() {
+        let service = mem_service();
+        let mut players = join_table(&service, "dealt_out", &["player1", "player2"]).await;
+        players.pop();
+        let result = service.compute_aggregate_key(aggregate_request("dealt_out", players)).await;
+        assert!(matches!(result, Err(DeckCustomError::OwnKeyMissing(game_user_id)) if game_user_id == "player2"));
+    }
+
+    #[tokio::test]
+    async fn test_open_cards_needs_one_token_of_every_player() {
+        let (service, joined_key, deck) = shuffled_table("opened", &["player1", "player2"]).await;
+        deal(&service, "opened", "player1", vec![(0, None)]).await;
+        let board = vec![deck[0].clone()];
+        let token1 = reveal_tokens(&service, "player1", &board).await.unwrap().remove(&deck[0]).unwrap();
+        let token2 = reveal_tokens(&service, "player2", &board).await.unwrap().remove(&deck[0]).unwrap();
+        let tokens = |tokens: Vec<RevealTokenDTO>| HashMap::from([(deck[0].clone(), tokens)]);
+
+        let opened = service.open_cards(open_request("opened", &board, tokens(vec![token1.clone(), token2.clone()]))).await.unwrap();
+        assert_eq!(opened.opened_cards.cards.len(), 1);
+
+        let missing = service.open_cards(open_request("opened", &board, tokens(vec![token1.clone()]))).await;
+        assert!(matches!(missing, Err(DeckCustomError::MissingFields(_))));
+        let repeated = service.open_cards(open_request("opened", &board, tokens(vec![token1.clone(), token1.clone()]))).await;
+        assert!(matches!(repeated, Err(DeckCustomError::DuplicatePublicKey(_))));
+        // the key decodes, but nobody at the table holds it
+        let mut stranger = token2.clone();
+        stranger.public_key = joined_key;
+        let foreign = service.open_cards(open_request("opened", &board, tokens(vec![token1, token2, stranger]))).await;
+        assert!(matches!(foreign, Err(DeckCustomError::StateMismatch(_))));
+    }
+
+    // a hole card for every player after the first card, the rest to the board, and the hand
+    // shown down, ready to be finished
+    async fn settled_table(game_id: &str, game_user_ids: &[&str]) -> (DeckService, String, Vec<String>) {
+        let (service, joined_key, deck) = shuffled_table(game_id, game_user_ids).await;
+        let owner = |position: usize| if position == 0 { None } else { game_user_ids.get(position - 1).copied() };
+        deal(&service, game_id, game_user_ids[0], (0..deck.len()).map(|position| (position, owner(position))).collect()).await;
+        reveal_tokens(&service, game_user_ids[0], &deck[..1]).await.unwrap();
+        service.showdown(game_id.to_string()).await.unwrap();
+        (service, joined_key, deck)
+    }
+
+    #[tokio::test]
+    async fn test_finish_waits_for_a_settled_hand() {
+        let (service, _joined_key, deck) = shuffled_table("finished", &["player1"]).await;
+        assert!(matches!(service.finish_game(String::from("finished")).await, Err(DeckCustomError::OutOfOrder(_))));
+        // a hole card and one board card, the rest of the deck is never dealt
+        deal(&service, "finished", "player1", vec![(0, None), (1, Some("player1"))]).await;
+        reveal_tokens(&service, "player1", &deck[..1]).await.unwrap();
+        assert!(matches!(service.finish_game(String::from("finished")).await, Err(DeckCustomError::OutOfOrder(_))));
+        service.showdown(String::from("finished")).await.unwrap();
+        assert!(matches!(reveal_tokens(&service, "player1", &deck[2..3]).await, Err(DeckCustomError::RevealNotAllowed(_))));
+
+        let finished = service.finish_game(String::from("finished")).await.unwrap();
+        assert_eq!(finished.held_keys, 1);
+        assert!(matches!(reveal_tokens(&service, "player1", &deck[..1]).await, Err(DeckCustomError::OutOfOrder(_))));
+        assert!(matches!(service.setup(table_request("finished", "player2")).await, Err(DeckCustomError::OutOfOrder(_))));
+        // the key stays for disclose_key
+        assert!(service.game_user("player1").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_sweep_removes_keys() {
+        let service = mem_service();
+        service.setup(set_up_request("abandoned", "player2")).await.unwrap();
+        service.setup(set_up_request("active", "player3")).await.unwrap();
+
+        let mut abandoned = service.game("abandoned").await.unwrap();
+        abandoned.updated_at = 0;
+        service.game_db.save(abandoned).await.unwrap();
+        assert_eq!(service.sweep_expired(Duration::from_secs(60)).await.unwrap(), 1);
+        assert!(matches!(service.game("abandoned").await, Err(DeckCustomError::GameNotFound)));
+        assert!(service.game_user("player2").await.is_err());
+        assert!(service.game_user("player3").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_transcript_chains_recorded_calls() {
+        let service = mem_service();
+        let set_up = service.setup(set_up_request("recorded", "player1")).await.unwrap();
+        // failed calls leave no entry
+        assert!(service.compute_aggregate_key(ComputeAggregateKeyRequest{
+            game_id: String::from("recorded"),
+            players: Vec::new(),
+            seed_hex: SEED_HEX.to_string(),
+        }).await.is_err());
+
+        let transcript = service.transcript(String::from("recorded")).await.unwrap();
+        assert_eq!(transcript.version, TRANSCRIPT_LOG_VERSION);
+        assert_eq!(transcript.entries.len(), 1);
+        assert_eq!(transcript.entries[0].operation, "setup");
+        assert_eq!(transcript.entries[0].output["user_public_key"], set_up.user_public_key.as_str());
+        assert_eq!(transcript.head, transcript.entries[0].hash);
+
+        let mut game = service.game("recorded").await.unwrap();
+        game.transcript.entries[0].input["user_id"] = serde_json::json!("someone else");
+        service.game_db.save(game).await.unwrap();
+        assert!(matches!(service.transcript(String::from("recorded")).await, Err(DeckCustomError::StateMismatch(_))));
+        assert!(matches!(service.transcript(String::from("unknown")).await, Err(DeckCustomError::GameNotFound)));
+
+        // a hand from mask to peek, the refused reveal leaves no entry
+        let (service, _joined_key, deck) = shuffled_table("logged", &["player1", "player2"]).await;
+        deal(&service, "logged", "player1", vec![(0, Some("player1")), (1, None)]).await;
+        assert!(reveal_tokens(&service, "player1", &deck[..1]).await.is_err());
+        let token = reveal_tokens(&service, "player2", &deck[..1]).await.unwrap().remove(&deck[0]).unwrap();
+        service.peek_cards(PeekCardsRequest{
+            game_user_id: String::from("player1"),
+            seed_hex: SEED_HEX.to_string(),
+            peek_cards: vec![PeekCardInput{ card: deck[0].clone(), reveal_tokens: vec![token] }],
+        }).await.unwrap();
+
+        let transcript = service.transcript(String::from("logged")).await.unwrap();
+        let operations: Vec<&str> = transcript.entries.iter().map(|entry| entry.operation.as_str()).collect();
+        assert_eq!(operations, vec!["setup", "setup", "compute_aggregate_key", "mask", "shuffle", "shuffle", "reveal_token", "peek_cards"]);
+        let game = service.game("logged").await.unwrap();
+        assert_eq!(transcript.entries[3].output["cards"].as_array().unwrap().len(), game.deck.len());
+        assert_eq!(transcript.entries[5].output["cards"], serde_json::json!(game.deck));
+        assert!(transcript.entries[6].output["token_map"].get(&deck[0]).is_some());
+        assert_eq!(transcript.entries[7].output["opened_cards"], serde_json::json!([deck[0]]));
+        assert_eq!(transcript.head, transcript.entries[7].hash);
+    }
+
+    #[tokio::test]
+    async fn test_exported_log_passes_the_offline_verifier() {
+        let (service, joined_key, deck) = shuffled_table("audited", &["player1", "player2"]).await;
+        deal(&service, "audited", "player1", vec![(0, None), (1, None)]).await;
+        let remasked = service.remask(remask_request("audited", &joined_key, vec![deck[1].clone()])).await.unwrap().cards.remove(0);
+        reveal_tokens(&service, "player1", &deck[..1]).await.unwrap();
+        reveal_tokens(&service, "player2", &deck[..1]).await.unwrap();
+        reveal_tokens(&service, "player2", &[remasked.masked_card]).await.unwrap();
+        let mut log = service.transcript(String::from("audited")).await.unwrap();
+
+        let game_transcript = GameTranscript::from_log(&log).unwrap();
+        assert_eq!(game_transcript.players.len(), 2);
+        let shufflers: Vec<&str> = game_transcript.shuffles.iter().map(|step| step.game_user_id.as_str()).collect();
+        assert_eq!(shufflers, vec!["player1", "player2"]);
+        assert_eq!(game_transcript.remasks.len(), 1);
+        assert_eq!(game_transcript.reveal_tokens.len(), 3);
+        let report = transcript::verify(&game_transcript);
+        assert!(report.iter().all(|step| step.passed), "{:?}", report);
+
+        // the proof of the second shuffle is for another order of the cards
+        let mut tampered = GameTranscript::from_log(&log).unwrap();
+        tampered.shuffles[1].shuffled_cards.swap(0, 1);
+        let report = transcript::verify(&tampered);
+        let failed: Vec<&str> = report.iter().filter(|step| !step.passed).map(|step| step.step.as_str()).collect();
+        assert_eq!(failed, vec!["shuffle 1 by player2"]);
+
+        // player1's key with player2's token
+        let mut tampered = GameTranscript::from_log(&log).unwrap();
+        tampered.reveal_tokens[0].reveal_token.token = tampered.reveal_tokens[1].reveal_token.token.clone();
+        let report = transcript::verify(&tampered);
+        let failed: Vec<&str> = report.iter().filter(|step| !step.passed).map(|step| step.step.as_str()).collect();
+        assert_eq!(failed, vec!["reveal token 0"]);
+
+        // a log without the shufflers still rebuilds, but nobody vouches for whose shuffles they were
+        let mut unnamed = TranscriptLog::default();
+        for entry in log.entries.iter() {
+            let mut input = entry.input.clone();
+            if entry.operation == "shuffle" {
+                input.as_object_mut().unwrap().remove("game_user_id");
+            }
+            unnamed.append("audited", &entry.operation, input, entry.output.clone());
+        }
+        let unnamed_log = GameTranscriptLogResponse{ version: log.version, game_id: log.game_id.clone(), head: unnamed.head(), entries: unnamed.entries };
+        let report = transcript::verify(&GameTranscript::from_log(&unnamed_log).unwrap());
+        let failed: Vec<&str> = report.iter().filter(|step| !step.passed).map(|step| step.step.as_str()).collect();
+        assert_eq!(failed, vec!["shuffle 0 by an unrecorded player", "shuffle 1 by an unrecorded player", "every player shuffled"]);
+
+        // an edited log is refused before anything is rebuilt from it
+        log.entries[3].output = serde_json::json!({});
+        assert!(matches!(GameTranscript::from_log(&log), Err(DeckCustomError::StateMismatch(_))));
+    }
+
+    #[tokio::test]
+    async fn test_disclosed_key_passes_the_audit_key_check() {
+        use crate::deck::models::deck_case::deck::AuditGameRequest;
+        let (service, _joined_key, deck) = settled_table("disclosed", &["player1", "player2"]).await;
+        let audit_request = |keys| AuditGameRequest{ keys, revealed_cards: Vec::new() };
+        // no key leaves the agent while tokens can still be asked for
+        assert!(matches!(service.disclose_key(String::from("disclosed")).await, Err(DeckCustomError::OutOfOrder(_))));
+        assert!(matches!(service.audit_game(String::from("disclosed"), audit_request(Vec::new())).await, Err(DeckCustomError::OutOfOrder(_))));
+        assert_eq!(service.game("disclosed").await.unwrap().phase, GamePhase::Revealing);
+        service.finish_game(String::from("disclosed")).await.unwrap();
+
+        let disclosed = service.disclose_key(String::from("disclosed")).await.unwrap();
+        assert_eq!(disclosed.keys.len(), 2);
+        // handed out once, then dropped
+        assert!(service.game_user("player1").await.is_err());
+        assert!(matches!(service.disclose_key(String::from("disclosed")).await, Err(DeckCustomError::UserNotFound)));
+
+        let game = service.game("disclosed").await.unwrap();
+        let parameters = service.parameters(SEED_HEX, &game.geometry).unwrap();
+        for key in disclosed.keys.iter() {
+            assert!(service.check_disclosed_key(&game, &parameters, key).is_ok());
+        }
+        let mut forged = disclosed.keys.clone();
+        forged[0].private_key = encode_private_key(&SecretScalar::new(Scalar::from(7u64))).unwrap();
+        assert!(matches!(service.check_disclosed_key(&game, &parameters, &forged[0]), Err(DeckCustomError::AuditFailed(_))));
+        assert!(!service.audit_game(String::from("disclosed"), audit_request(forged)).await.unwrap().all_valid);
+
+        let audit = service.audit_game(String::from("disclosed"), audit_request(disclosed.keys)).await.unwrap();
+        assert!(audit.all_valid);
+        assert_eq!(audit.positions.len(), deck.len());
+    }
+
+    // a shuffle made off the service, so a whole chain can be handed to verify_shuffle_chain
+    fn shuffle_step(service: &DeckService, joined_key: &str, game_user_id: &str, origin_cards: &[String]) -> ShuffleStepDTO {
+        let geometry = DeckGeometry::new(2, 16).unwrap();
+        let parameters = service.parameters(SEED_HEX, &geometry).unwrap();
+        let joint_key = decode_deck_public_key(joined_key.to_string()).unwrap();
+        let rng = &mut thread_rng();
+        let deck: Vec<MaskedCard> = origin_cards.iter().map(|card| decode_masked_card(card.clone()).unwrap()).collect();
+        let permutation = Permutation::new(rng, geometry.num_of_cards());
+        let masking_factors: Vec<Scalar> = sample_vector(rng, geometry.num_of_cards());
+        let (shuffled_deck, proof) = CardProtocol::shuffle_and_remask(rng, &parameters, &joint_key, &deck, &masking_factors, &permutation).unwrap();
+        ShuffleStepDTO{
+            game_user_id: game_user_id.to_string(),
+            shuffled_cards: encode_deck(shuffled_deck.into_iter()).unwrap(),
+            proof: encode_shuffle_proof(&proof).unwrap(),
+        }
+    }
+
+    fn shuffle_chain(service: &DeckService, joined_key: &str, game_user_ids: &[&str], initial_cards: &[String]) -> Vec<ShuffleStepDTO> {
+        let mut steps: Vec<ShuffleStepDTO> = Vec::with_capacity(game_user_ids.len());
+        for game_user_id in game_user_ids {
+            let origin_cards = steps.last().map(|step| step.shuffled_cards.clone()).unwrap_or_else(|| initial_cards.to_vec());
+            steps.push(shuffle_step(service, joined_key, game_user_id, &origin_cards));
+        }
+        steps
+    }
+
+    fn chain_request(game_id: &str, joined_key: &str, initial_cards: &[String], steps: Vec<ShuffleStepDTO>) -> VerifyShuffleChainRequest {
+        VerifyShuffleChainRequest{
+            game_id: game_id.to_string(),
+            joined_key: joined_key.to_string(),
+            seed_hex: SEED_HEX.to_string(),
+            initial_cards: initial_cards.to_vec(),
+            steps,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shuffle_chain_is_applied_as_a_whole() {
+        let players = ["player1", "player2", "player3"];
+        let (service, joined_key, deck) = watched_table("chain", "player0", &players).await;
+        let deck = local_shuffle(&service, "chain", &joined_key, "player0", deck).await.unwrap();
+        let steps = shuffle_chain(&service, &joined_key, &players, &deck);
+        let last_cards = steps[2].shuffled_cards.clone();
+
+        let verified = service.verify_shuffle_chain(chain_request("chain", &joined_key, &deck, steps)).await.unwrap();
+        assert_eq!(verified.final_cards, last_cards);
+        let game = service.game("chain").await.unwrap();
+        assert_eq!(game.phase, GamePhase::Dealt);
+        assert_eq!(game.deck, last_cards);
+    }
+
+    #[tokio::test]
+    async fn test_shuffle_chain_names_the_bad_step() {
+        let players = ["player1", "player2", "player3"];
+        let (service, joined_key, deck) = watched_table("bad-link", "player0", &players).await;
+        let mut steps = shuffle_chain(&service, &joined_key, &players, &deck);
+        // a valid proof, but of the next shuffle
+        steps[1].proof = steps[2].proof.clone();
+
+        match service.verify_shuffle_chain(chain_request("bad-link", &joined_key, &deck, steps)).await {
+            Err(DeckCustomError::InvalidShuffleStep{ index, game_user_id, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(game_user_id, "player2");
+            }
+            other => panic!("expected the second step to fail, got {:?}", other.map(|response| response.final_cards)),
+        }
+        // none of the chain was applied
+        let game = service.game("bad-link").await.unwrap();
+        assert_eq!(game.phase, GamePhase::Masked);
+        assert_eq!(game.deck, deck);
+    }
+
+    #[tokio::test]
+    async fn test_shuffle_chain_rejects_foreign_and_repeated_shufflers() {
+        let (service, joined_key, deck) = watched_table("shufflers", "player0", &["player1", "player2"]).await;
+
+        let steps = shuffle_chain(&service, &joined_key, &["stranger", "player2"], &deck);
+        assert!(matches!(
+            service.verify_shuffle_chain(chain_request("shufflers", &joined_key, &deck, steps)).await,
+            Err(DeckCustomError::InvalidShuffleStep{ index: 0, .. })
+        ));
+
+        let steps = shuffle_chain(&service, &joined_key, &["player1", "player1"], &deck);
+        match service.verify_shuffle_chain(chain_request("shufflers", &joined_key, &deck, steps)).await {
+            Err(DeckCustomError::InvalidShuffleStep{ index, game_user_id, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(game_user_id, "player1");
+            }
+            other => panic!("expected the repeated shuffler to fail, got {:?}", other.map(|response| response.final_cards)),
+        }
+        // player0 sits at this agent, nobody else shuffles in its name
+        let steps = shuffle_chain(&service, &joined_key, &["player1", "player0"], &deck);
+        match service.verify_shuffle_chain(chain_request("shufflers", &joined_key, &deck, steps)).await {
+            Err(DeckCustomError::InvalidShuffleStep{ index, game_user_id, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(game_user_id, "player0");
+            }
+            other => panic!("expected the local shuffler to fail, got {:?}", other.map(|response| response.final_cards)),
+        }
+        assert_eq!(service.game("shufflers").await.unwrap().phase, GamePhase::Masked);
+
+        // a player that shuffled in an earlier request doesn't get a second turn
+        let steps = shuffle_chain(&service, &joined_key, &["player1"], &deck);
+        let deck = service.verify_shuffle_chain(chain_request("shufflers", &joined_key, &deck, steps)).await.unwrap().final_cards;
+        let steps = shuffle_chain(&service, &joined_key, &["player1"], &deck);
+        assert!(matches!(
+            service.verify_shuffle_chain(chain_request("shufflers", &joined_key, &deck, steps)).await,
+            Err(DeckCustomError::InvalidShuffleStep{ index: 0, .. })
+        ));
+        let deck = local_shuffle(&service, "shufflers", &joined_key, "player0", deck).await.unwrap();
+        assert!(matches!(local_shuffle(&service, "shufflers", &joined_key, "player0", deck.clone()).await, Err(DeckCustomError::StateMismatch(_))));
+        // the remaining player still has to shuffle before the deck is dealt
+        assert_eq!(service.game("shufflers").await.unwrap().phase, GamePhase::Shuffling(2));
+        let steps = shuffle_chain(&service, &joined_key, &["player2"], &deck);
+        service.verify_shuffle_chain(chain_request("shufflers", &joined_key, &deck, steps)).await.unwrap();
+        assert_eq!(service.game("shufflers").await.unwrap().phase, GamePhase::Dealt);
+    }
+
+    #[tokio::test]
+    async fn test_requests_must_name_the_game_seed() {
+        let other_seed = "ff".repeat(32);
+        let service = mem_service();
+        let players = join_table(&service, "seeded", &["player1", "player2"]).await;
+        let request = ComputeAggregateKeyRequest{ seed_hex: other_seed.clone(), ..aggregate_request("seeded", players.clone()) };
+        assert!(matches!(service.compute_aggregate_key(request).await, Err(DeckCustomError::StateMismatch(_))));
+        assert_eq!(service.game("seeded").await.unwrap().phase, GamePhase::Setup);
+        // hex digits compare regardless of case
+        let request = ComputeAggregateKeyRequest{ seed_hex: SEED_HEX.to_uppercase(), ..aggregate_request("seeded", players) };
+        let joined_key = service.compute_aggregate_key(request).await.unwrap().joined_key;
+
+        let initial_deck = service.initial_deck(InitialDeckRequest{ m: 2, n: 16, seed_hex: Some(SEED_HEX.to_string()) }).await.unwrap();
+        let mask_request = |seed_hex: &str| MaskRequest{
+            game_id: String::from("seeded"),
+            seed_hex: seed_hex.to_string(),
+            joined_key: joined_key.clone(),
+            cards: initial_deck.cards.iter().map(|card| card.card.clone()).collect(),
+        };
+        assert!(matches!(service.mask(mask_request(&other_seed)).await, Err(DeckCustomError::StateMismatch(_))));
+        assert_eq!(service.game("seeded").await.unwrap().phase, GamePhase::KeysAggregated);
+        service.mask(mask_request(SEED_HEX)).await.unwrap();
+    }
+
+    // alice shares her key with bob and carol, who sit at another agent that lists the table
+    // in another order, returns both agents, the joint key and alice's dealing
+    async fn shared_table(game_id: &str) -> (DeckService, DeckService, String, KeyDealing) {
+        let alice_agent = mem_service();
+        let set_up = alice_agent.setup(SetUpDeckRequest{ share_threshold: Some(2), ..table_request(game_id, "alice") }).await.unwrap();
+        let alice = Player{
+            game_id: game_id.to_string(),
+            game_user_id: String::from("alice"),
+            public_key: set_up.user_public_key,
+            user_key_proof: set_up.user_key_proof,
+        };
+        let other_agent = mem_service();
+        let mut others = join_table(&other_agent, game_id, &["carol", "bob"]).await;
+
+        let mut players = vec![alice.clone()];
+        players.extend(others.iter().rev().cloned());
+        let aggregated = alice_agent.compute_aggregate_key(aggregate_request(game_id, players)).await.unwrap();
+        others.push(alice);
+        let joined_key = other_agent.compute_aggregate_key(aggregate_request(game_id, others)).await.unwrap().joined_key;
+        assert_eq!(aggregated.joined_key, joined_key);
+        let dealing = aggregated.key_dealings.into_iter().next().unwrap();
+        (alice_agent, other_agent, joined_key, dealing)
+    }
+
+    #[tokio::test]
+    async fn test_share_indices_ignore_the_order_of_the_players() {
+        let (alice_agent, other_agent, _joined_key, dealing) = shared_table("permuted").await;
+        let alice_game = alice_agent.game("permuted").await.unwrap();
+        let other_game = other_agent.game("permuted").await.unwrap();
+        assert_ne!(alice_game.players, other_game.players);
+        assert_eq!(alice_game.share_recipients("alice").unwrap(), other_game.share_recipients("alice").unwrap());
+
+        for game_user_id in ["bob", "carol"] {
+            let verified = other_agent.verify_key_shares(VerifyKeySharesRequest{
+                game_id: String::from("permuted"),
+                seed_hex: SEED_HEX.to_string(),
+                game_user_id: game_user_id.to_string(),
+                dealings: vec![dealing.clone()],
+            }).await.unwrap();
+            assert!(verified.results[0].valid, "{:?}", verified.results[0].reason);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shares_stand_in_for_a_dropped_player() {
+        let (alice_agent, other_agent, joined_key, dealing) = shared_table("dropped").await;
+        for game_user_id in ["bob", "carol"] {
+            other_agent.verify_key_shares(VerifyKeySharesRequest{
+                game_id: String::from("dropped"),
+                seed_hex: SEED_HEX.to_string(),
+                game_user_id: game_user_id.to_string(),
+                dealings: vec![dealing.clone()],
+            }).await.unwrap();
+        }
+        let initial_deck = other_agent.initial_deck(InitialDeckRequest{ m: 2, n: 16, seed_hex: Some(SEED_HEX.to_string()) }).await.unwrap();
+        let deck: Vec<String> = other_agent.mask(MaskRequest{
+            game_id: String::from("dropped"),
+            seed_hex: SEED_HEX.to_string(),
+            joined_key: joined_key.clone(),
+            cards: initial_deck.cards.into_iter().map(|card| card.card).collect(),
+        }).await.unwrap().cards.into_iter().map(|card| card.masked_card).collect();
+        let mut deck = deck;
+        for game_user_id in ["carol", "bob"] {
+            deck = local_shuffle(&other_agent, "dropped", &joined_key, game_user_id, deck).await.unwrap();
+        }
+        let steps = shuffle_chain(&other_agent, &joined_key, &["alice"], &deck);
+        let deck = other_agent.verify_shuffle_chain(chain_request("dropped", &joined_key, &deck, steps)).await.unwrap().final_cards;
+        deal(&other_agent, "dropped", "bob", vec![(0, None)]).await;
+        let board = vec![deck[0].clone()];
+
+        // alice is gone, bob and carol open the card with their shares of her key
+        let mut partial_tokens = Vec::new();
+        for game_user_id in ["bob", "carol"] {
+            partial_tokens.extend(other_agent.partial_reveal_tokens(PartialRevealTokensRequest{
+                game_id: String::from("dropped"),
+                seed_hex: SEED_HEX.to_string(),
+                game_user_id: game_user_id.to_string(),
+                dealer_game_user_id: String::from("alice"),
+                cards: board.clone(),
+            }).await.unwrap().partial_tokens);
+        }
+        let recovery = |partial_tokens: Vec<PartialRevealTokenDTO>| KeyRecoveryDTO{ dealer_game_user_id: String::from("alice"), partial_tokens };
+        let tokens = HashMap::from([(deck[0].clone(), vec![
+            reveal_tokens(&other_agent, "bob", &board).await.unwrap().remove(&deck[0]).unwrap(),
+            reveal_tokens(&other_agent, "carol", &board).await.unwrap().remove(&deck[0]).unwrap(),
+        ])]);
+
+        let below_threshold = OpenCardsRequest{ recoveries: vec![recovery(partial_tokens[..1].to_vec())], ..open_request("dropped", &board, tokens.clone()) };
+        assert!(matches!(other_agent.open_cards(below_threshold).await, Err(DeckCustomError::InvalidKeyShare(_))));
+        let recovered = OpenCardsRequest{ recoveries: vec![recovery(partial_tokens.clone())], ..open_request("dropped", &board, tokens) };
+        assert_eq!(other_agent.open_cards(recovered).await.unwrap().opened_cards.cards.len(), 1);
+
+        // the recovered token is the one alice's own key gives
+        let recovered = other_agent.recover_reveal_tokens(RecoverRevealTokensRequest{
+            game_id: String::from("dropped"),
+            seed_hex: SEED_HEX.to_string(),
+            recovery: recovery(partial_tokens),
+        }).await.unwrap();
+        let parameters = alice_agent.parameters(SEED_HEX, &DeckGeometry::new(2, 16).unwrap()).unwrap();
+        let masked_card = decode_masked_card(deck[0].clone()).unwrap();
+        let (own_token, _proof) = alice_agent.key_store.compute_reveal_tokens(&parameters, "alice", &[masked_card]).await.unwrap().remove(0);
+        assert_eq!(recovered.tokens[&deck[0]], encode_revel_token(own_token).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_setup_follows_the_agreed_seed() {
+        let service = mem_service();
+        let commitment = service.seed_commit(SeedCommitRequest{ game_id: String::from("agreed"), game_user_id: String::from("player1") }).await.unwrap();
+        assert!(matches!(service.setup(table_request("agreed", "player1")).await, Err(DeckCustomError::OutOfOrder(_))));
+
+        let reveal = service.seed_reveal(SeedRevealRequest{
+            game_id: String::from("agreed"),
+            game_user_id: String::from("player1"),
+            commitments: vec![commitment],
+        }).await.unwrap();
+        let seed_hex = service.seed_combine(SeedCombineRequest{ game_id: String::from("agreed"), reveals: vec![reveal] }).await.unwrap().seed_hex;
+        assert!(matches!(service.setup(table_request("agreed", "player1")).await, Err(DeckCustomError::SeedAgreement(_))));
+        service.setup(SetUpDeckRequest{ seed_hex: seed_hex.clone(), ..table_request("agreed", "player1") }).await.unwrap();
+        let game = service.game("agreed").await.unwrap();
+        assert!(game.seed_agreed);
+        assert_eq!(game.seed_hex, seed_hex);
+        assert!(matches!(service.seed_commit(SeedCommitRequest{ game_id: String::from("agreed"), game_user_id: String::from("player2") }).await,
+            Err(DeckCustomError::OutOfOrder(_))));
+
+        // the round is gone after a restart, the game still holds the seed
+        service.seed_agreements.forget("agreed");
+        assert!(matches!(service.setup(table_request("agreed", "player2")).await, Err(DeckCustomError::StateMismatch(_))));
+        service.setup(SetUpDeckRequest{ seed_hex, ..table_request("agreed", "player2") }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_setup_keeps_the_key_of_a_reused_id() {
+        let (service, _joined_key, deck) = shuffled_table("first", &["player1", "player2"]).await;
+        deal(&service, "first", "player1", vec![(0, None)]).await;
+        let tokens = reveal_tokens(&service, "player1", &deck[..1]).await.unwrap();
+
+        assert!(matches!(service.setup(table_request("second", "player1")).await, Err(DeckCustomError::StateMismatch(_))));
+        assert!(matches!(service.setup(table_request("first", "player1")).await, Err(DeckCustomError::StateMismatch(_))));
+        assert_eq!(service.game_user("player1").await.unwrap().game_id, "first");
+        // the first game still gets tokens of the key it aggregated
+        assert_eq!(reveal_tokens(&service, "player1", &deck[..1]).await.unwrap()[&deck[0]].public_key, tokens[&deck[0]].public_key);
+    }
+
+    #[tokio::test]
+    async fn test_failed_setup_drops_the_new_key() {
+        let game_users: Arc<dyn GameUserMemTrait> = Arc::new(GameUserMem::new());
+        let mut game_db = MockGameDbTrait::new();
+        game_db.expect_get_by_id().returning(|game_id| Ok(Game::new(game_id.to_string(), SEED_HEX.to_string(), DeckGeometry::new(2, 16).unwrap())));
+        game_db.expect_save().returning(|_| Err(DeckCustomError::GenericError(String::from("store unavailable"))));
+        let service = DeckService::new(game_users.clone(), Box::new(game_db), Box::new(SoftwareKeyStore::new(game_users.clone())));
+
+        assert!(matches!(service.setup(table_request("unsaved", "player1")).await, Err(DeckCustomError::GenericError(_))));
+        assert!(game_users.get_by_id("player1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_refused_reveals_leave_the_phase_alone() {
+        let (service, joined_key, deck) = shuffled_table("policy", &["player1", "player2"]).await;
+        let stranger_deal = DealRequest{ game_id: String::from("policy"), game_user_id: String::from("stranger"), cards: vec![CardAssignmentDTO{ position: 0, game_user_id: None }] };
+        assert!(matches!(service.deal(stranger_deal).await, Err(DeckCustomError::UserNotFound)));
+        deal(&service, "policy", "player1", vec![(0, Some("player1")), (1, None)]).await;
+        let phase = || async { service.game("policy").await.unwrap().phase };
+
+        // our own hole card stays sealed, and asking for it doesn't start the reveal
+        assert!(matches!(reveal_tokens(&service, "player1", &deck[..1]).await, Err(DeckCustomError::RevealNotAllowed(_))));
+        assert_eq!(phase().await, GamePhase::Dealt);
+        assert!(matches!(service.showdown(String::from("policy")).await, Err(DeckCustomError::OutOfOrder(_))));
+
+        // peeking combines our token in the agent, but only for cards of the deck
+        let peek = |card: &str, reveal_tokens: Vec<RevealTokenDTO>| PeekCardsRequest{
+            game_user_id: String::from("player1"),
+            seed_hex: SEED_HEX.to_string(),
+            peek_cards: vec![PeekCardInput{ card: card.to_string(), reveal_tokens }],
+        };
+        let forged = remask_card(&service, &joined_key, &deck[0]).masked_card;
+        assert!(matches!(service.peek_cards(peek(&forged, Vec::new())).await, Err(DeckCustomError::RevealNotAllowed(_))));
+        assert_eq!(phase().await, GamePhase::Dealt);
+        let token = reveal_tokens(&service, "player2", &deck[..1]).await.unwrap().remove(&deck[0]).unwrap();
+        assert_eq!(service.peek_cards(peek(&deck[0], vec![token])).await.unwrap().card_map.len(), 1);
+        assert_eq!(phase().await, GamePhase::Revealing);
+
+        // showdown waits for the hole cards of every player
+        assert!(matches!(service.showdown(String::from("policy")).await, Err(DeckCustomError::StateMismatch(_))));
+        deal(&service, "policy", "player1", vec![(2, Some("player2"))]).await;
+        service.showdown(String::from("policy")).await.unwrap();
+        assert!(reveal_tokens(&service, "player1", &deck[..1]).await.is_ok());
+    }
+}
@@ -0,0 +1,36 @@
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{info, warn};
+use crate::deck::service::DeckServiceTrait;
+
+// a game nobody touched for this long is considered abandoned
+pub const GAME_TTL_ENV: &str = "DECK_GAME_TTL_SECS";
+pub const SWEEP_INTERVAL_ENV: &str = "DECK_SWEEP_INTERVAL_SECS";
+
+const DEFAULT_GAME_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+fn duration_from_env(name: &str, default: Duration) -> Duration {
+    match env::var(name).ok().and_then(|secs| secs.trim().parse::<u64>().ok()) {
+        Some(secs) if secs > 0 => Duration::from_secs(secs),
+        _ => default,
+    }
+}
+
+// evicts abandoned games and their keys for as long as the agent runs
+pub fn spawn(deck_service: Arc<dyn DeckServiceTrait>) {
+    let ttl = duration_from_env(GAME_TTL_ENV, DEFAULT_GAME_TTL);
+    let interval = duration_from_env(SWEEP_INTERVAL_ENV, DEFAULT_SWEEP_INTERVAL);
+    rocket::tokio::spawn(async move {
+        let mut ticker = rocket::tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match deck_service.sweep_expired(ttl).await {
+                Ok(0) => {}
+                Ok(swept) => info!("swept {} expired games", swept),
+                Err(e) => warn!("sweeping expired games failed: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,421 @@
+use std::collections::{HashMap, HashSet};
+use barnett_smart_card_protocol::BarnettSmartProtocol;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use crate::deck::errors::DeckCustomError;
+use crate::deck::models::deck_case::deck::{ComputeAggregateKeyRequest, ComputeAggregateKeyResponse, GameTranscriptLogResponse, MaskDeck, MaskResponse, MaskedCardAndProofDTO, PeekCardsRequest, Player, RemaskResponse, RemaskedCardDTO, RevealTokenCheckDTO, RevealTokenResponse, SetUpDeckRequest, ShuffleResponse, ShuffleStepDTO, VerifyMaskRequest, VerifyRemaskRequest, VerifyShuffleChainRequest};
+use crate::deck::models::geometry::DeckGeometry;
+use crate::deck::params_cache::ParametersCache;
+use crate::deck::service::{encode_cards, key_ownership_context};
+use crate::game::models::transcript::{TranscriptEntry, TranscriptLog, TRANSCRIPT_LOG_VERSION};
+use crate::serialize::proof::{IdentityProof, PedersenProof};
+use crate::serialize::serialize::{decode_deck_public_key, decode_masked_card, decode_shuffle_proof, encode_masked_card};
+
+type Curve = starknet_curve::Projective;
+type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
+type Parameters = <CardProtocol as BarnettSmartProtocol>::Parameters;
+type PublicKey = <CardProtocol as BarnettSmartProtocol>::PlayerPublicKey;
+type MaskedCard = barnett_smart_card_protocol::discrete_log_cards::MaskedCard<Curve>;
+type KeyOwnershipProof = <CardProtocol as BarnettSmartProtocol>::ZKProofKeyOwnership;
+
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+// The public record of a whole hand, enough to re-run every check without the agents.
+// Values are hex encoded exactly as the deck endpoints exchange them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GameTranscript {
+    pub version: u32,
+    pub game_id: String,
+    pub seed_hex: String,
+    pub m: usize,
+    pub n: usize,
+    // public keys and key ownership proofs from setup
+    pub players: Vec<Player>,
+    pub joined_key: String,
+    // the initial cards of the seed masked under the joint key, in the same order
+    pub masked_deck: Vec<MaskedCardAndProofDTO>,
+    // in shuffle order, the first one starts from masked_deck
+    pub shuffles: Vec<ShuffleStepDTO>,
+    // cards remasked after the shuffles, in order, each takes the place of its origin card
+    #[serde(default)]
+    pub remasks: Vec<RemaskedCardDTO>,
+    // tokens for cards of the final deck
+    #[serde(default)]
+    pub reveal_tokens: Vec<RevealTokenCheckDTO>,
+}
+
+impl GameTranscript {
+    // Rebuilds the transcript from the log one agent exports for the game. The chain of the log
+    // is checked first, then every entry adds what its operation fixed: setup the seed and the
+    // geometry, compute_aggregate_key the players, mask or verify_mask the masked deck, the
+    // shuffles in order with the shuffler each call names, remask or verify_remask the
+    // remasked cards and the tokens handed out or received for reveal_token and peek_cards.
+    pub fn from_log(log: &GameTranscriptLogResponse) -> Result<Self, DeckCustomError> {
+        if log.version != TRANSCRIPT_LOG_VERSION {
+            return Err(DeckCustomError::StateMismatch(
+                format!("transcript log version {} is not supported, expected {}", log.version, TRANSCRIPT_LOG_VERSION)))
+        }
+        let chain = TranscriptLog { entries: log.entries.clone() };
+        chain.verify(&log.game_id)?;
+        if chain.head() != log.head {
+            return Err(DeckCustomError::StateMismatch(format!("head of the transcript of game {} is not its last entry", log.game_id)))
+        }
+
+        let mut transcript = GameTranscript {
+            version: TRANSCRIPT_VERSION,
+            game_id: log.game_id.clone(),
+            seed_hex: String::new(),
+            m: 0,
+            n: 0,
+            players: Vec::new(),
+            joined_key: String::new(),
+            masked_deck: Vec::new(),
+            shuffles: Vec::new(),
+            remasks: Vec::new(),
+            reveal_tokens: Vec::new(),
+        };
+        for entry in log.entries.iter() {
+            match entry.operation.as_str() {
+                "setup" if transcript.seed_hex.is_empty() => {
+                    let set_up: SetUpDeckRequest = entry_value(entry, &entry.input)?;
+                    transcript.seed_hex = set_up.seed_hex;
+                    transcript.m = set_up.m;
+                    transcript.n = set_up.n;
+                }
+                "compute_aggregate_key" => {
+                    let request: ComputeAggregateKeyRequest = entry_value(entry, &entry.input)?;
+                    let response: ComputeAggregateKeyResponse = entry_value(entry, &entry.output)?;
+                    transcript.players = request.players;
+                    transcript.joined_key = response.joined_key;
+                }
+                // a deck masked again to the same cards leaves the first one in place
+                "mask" if transcript.masked_deck.is_empty() => {
+                    let response: MaskResponse = entry_value(entry, &entry.output)?;
+                    transcript.masked_deck = response.cards;
+                }
+                "verify_mask" if transcript.masked_deck.is_empty() => {
+                    let request: VerifyMaskRequest = entry_value(entry, &entry.input)?;
+                    transcript.masked_deck = request.masked_cards;
+                }
+                "shuffle" => {
+                    let response: ShuffleResponse = entry_value(entry, &entry.output)?;
+                    transcript.shuffles.push(ShuffleStepDTO { game_user_id: recorded_shuffler(entry), shuffled_cards: response.cards, proof: response.shuffle_proof });
+                }
+                "verify_shuffle" => {
+                    let request: LoggedShuffle = entry_value(entry, &entry.input)?;
+                    transcript.shuffles.push(ShuffleStepDTO { game_user_id: recorded_shuffler(entry), shuffled_cards: request.shuffled_cards, proof: request.proof });
+                }
+                "verify_shuffle_chain" => {
+                    let request: VerifyShuffleChainRequest = entry_value(entry, &entry.input)?;
+                    transcript.shuffles.extend(request.steps);
+                }
+                "remask" => {
+                    let response: RemaskResponse = entry_value(entry, &entry.output)?;
+                    transcript.remasks.extend(response.cards);
+                }
+                "verify_remask" => {
+                    let request: VerifyRemaskRequest = entry_value(entry, &entry.input)?;
+                    transcript.remasks.extend(request.cards);
+                }
+                "reveal_token" => {
+                    let response: RevealTokenResponse = entry_value(entry, &entry.output)?;
+                    let mut tokens: Vec<_> = response.token_map.into_iter().collect();
+                    tokens.sort_by(|a, b| a.0.cmp(&b.0));
+                    for (masked_card, reveal_token) in tokens {
+                        transcript.reveal_tokens.push(RevealTokenCheckDTO { masked_card, reveal_token });
+                    }
+                }
+                "peek_cards" => {
+                    let request: PeekCardsRequest = entry_value(entry, &entry.input)?;
+                    for card in request.peek_cards {
+                        for reveal_token in card.reveal_tokens {
+                            transcript.reveal_tokens.push(RevealTokenCheckDTO { masked_card: card.card.clone(), reveal_token });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(transcript)
+    }
+}
+
+// what verify_shuffle logs of a shuffle, the shuffler is read on its own
+#[derive(Deserialize)]
+struct LoggedShuffle {
+    shuffled_cards: Vec<String>,
+    proof: String,
+}
+
+// logs written before shuffles named their shuffler leave it empty, verify reports such a
+// step as unverifiable instead of guessing whose it was
+fn recorded_shuffler(entry: &TranscriptEntry) -> String {
+    entry.input.get("game_user_id").and_then(|game_user_id| game_user_id.as_str()).unwrap_or_default().to_string()
+}
+
+// deck-verify takes a transcript as well as the log an agent exports
+pub fn from_json(json: &str) -> Result<GameTranscript, DeckCustomError> {
+    match serde_json::from_str::<GameTranscript>(json) {
+        Ok(transcript) => Ok(transcript),
+        Err(transcript_error) => match serde_json::from_str::<GameTranscriptLogResponse>(json) {
+            Ok(log) => GameTranscript::from_log(&log),
+            Err(_e) => Err(DeckCustomError::SerializationError(format!("not a game transcript or transcript log: {}", transcript_error))),
+        },
+    }
+}
+
+fn entry_value<T: DeserializeOwned>(entry: &TranscriptEntry, value: &serde_json::Value) -> Result<T, DeckCustomError> {
+    match serde_json::from_value(value.clone()) {
+        Ok(value) => Ok(value),
+        Err(e) => Err(DeckCustomError::SerializationError(format!("entry {} ({}): {}", entry.seq, entry.operation, e))),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepReport {
+    pub step: String,
+    pub passed: bool,
+    pub detail: Option<String>,
+}
+
+impl StepReport {
+    fn new(step: String, result: Result<Option<String>, DeckCustomError>) -> Self {
+        match result {
+            Ok(detail) => StepReport { step, passed: true, detail },
+            Err(e) => StepReport { step, passed: false, detail: Some(e.to_string()) },
+        }
+    }
+}
+
+// Every step gets its own verdict. Key and token checks go on after a failure so a dispute
+// sees everything that is wrong, the deck checks stop at the first broken link.
+pub fn verify(transcript: &GameTranscript) -> Vec<StepReport> {
+    let mut report = Vec::new();
+    if transcript.version != TRANSCRIPT_VERSION {
+        report.push(StepReport::new(String::from("version"), Err(DeckCustomError::StateMismatch(
+            format!("transcript version {} is not supported, expected {}", transcript.version, TRANSCRIPT_VERSION)))));
+        return report
+    }
+    let setup = DeckGeometry::new(transcript.m, transcript.n)
+        .and_then(|geometry| Ok((geometry, ParametersCache::new(1).get_or_setup(&transcript.seed_hex, &geometry)?)));
+    let (geometry, parameters) = match setup {
+        Ok(setup) => setup,
+        Err(e) => {
+            report.push(StepReport::new(String::from("parameters"), Err(e)));
+            return report
+        }
+    };
+    report.push(StepReport::new(String::from("parameters"), Ok(None)));
+
+    let mut player_keys = HashMap::new();
+    let mut key_proof_info = Vec::with_capacity(transcript.players.len());
+    for player in transcript.players.iter() {
+        let checked = check_player(transcript, &geometry, &parameters, player).and_then(|key_proof| {
+            if player_keys.insert(player.game_user_id.clone(), key_proof.0).is_some() {
+                return Err(DeckCustomError::DuplicatePlayer(player.game_user_id.clone()))
+            }
+            key_proof_info.push(key_proof);
+            Ok(None)
+        });
+        report.push(StepReport::new(format!("key ownership of {}", player.game_user_id), checked));
+    }
+
+    let joint_key = match decode_deck_public_key(transcript.joined_key.clone()) {
+        Ok(joint_key) => joint_key,
+        Err(e) => {
+            report.push(StepReport::new(String::from("joint key"), Err(e)));
+            return report
+        }
+    };
+    let aggregated = match CardProtocol::compute_aggregate_key(&parameters, &key_proof_info) {
+        Ok(aggregated) if aggregated == joint_key => Ok(None),
+        Ok(_) => Err(DeckCustomError::StateMismatch(String::from("joined_key is not the sum of the player keys"))),
+        Err(_e) => Err(DeckCustomError::InvalidProof),
+    };
+    report.push(StepReport::new(String::from("joint key"), aggregated));
+
+    // the shuffles are only meaningful on top of a correctly masked deck
+    let mut deck = match check_mask(transcript, &geometry, &parameters, &joint_key) {
+        Ok(deck) => {
+            report.push(StepReport::new(String::from("mask"), Ok(None)));
+            deck
+        }
+        Err(e) => {
+            report.push(StepReport::new(String::from("mask"), Err(e)));
+            return report
+        }
+    };
+
+    let mut shufflers = HashSet::new();
+    for (index, step) in transcript.shuffles.iter().enumerate() {
+        let recorded = !step.game_user_id.is_empty();
+        let shuffled = check_shuffle(&geometry, &parameters, &joint_key, &deck, step).and_then(|shuffled| {
+            if !recorded {
+                return Ok(shuffled)
+            }
+            if !player_keys.contains_key(&step.game_user_id) {
+                return Err(DeckCustomError::StateMismatch(String::from("player is not part of the joint key")))
+            }
+            if !shufflers.insert(step.game_user_id.clone()) {
+                return Err(DeckCustomError::StateMismatch(String::from("player already shuffled this deck")))
+            }
+            Ok(shuffled)
+        });
+        let name = if recorded { format!("shuffle {} by {}", index, step.game_user_id) } else { format!("shuffle {} by an unrecorded player", index) };
+        match shuffled {
+            // the proof holds, but nobody can tell whose shuffle it was
+            Ok(shuffled) if !recorded => {
+                deck = shuffled;
+                report.push(StepReport::new(name, Err(DeckCustomError::StateMismatch(String::from("shuffler was not recorded, the step is unverifiable")))));
+            }
+            Ok(shuffled) => {
+                deck = shuffled;
+                report.push(StepReport::new(name, Ok(None)));
+            }
+            // later shuffles start from a deck nobody can vouch for
+            Err(e) => {
+                report.push(StepReport::new(name, Err(e)));
+                return report
+            }
+        }
+    }
+    let shuffled_by_all = player_keys.keys().all(|game_user_id| shufflers.contains(game_user_id));
+    report.push(StepReport::new(String::from("every player shuffled"), if shuffled_by_all {
+        Ok(None)
+    } else {
+        Err(DeckCustomError::StateMismatch(format!("{} of {} players shuffled", shufflers.len(), player_keys.len())))
+    }));
+
+    // a remask that doesn't verify leaves its origin card in place
+    for (index, remask) in transcript.remasks.iter().enumerate() {
+        let remasked = check_remask(&parameters, &joint_key, &deck, remask).map(|(position, masked_card)| {
+            deck[position] = masked_card;
+            None
+        });
+        report.push(StepReport::new(format!("remask {}", index), remasked));
+    }
+
+    let final_deck: HashSet<String> = deck.into_iter().filter_map(|masked_card| encode_masked_card(masked_card).ok()).collect();
+    let known_keys: HashSet<PublicKey> = player_keys.values().copied().collect();
+    for (index, check) in transcript.reveal_tokens.iter().enumerate() {
+        let verified = decode_masked_card(check.masked_card.clone()).and_then(|masked_card| {
+            if !final_deck.contains(&encode_masked_card(masked_card)?) {
+                return Err(DeckCustomError::StateMismatch(String::from("card is not part of the final deck")))
+            }
+            let (reveal_token, proof, public_key) = check.reveal_token.clone().into_reveal_token()?;
+            if !known_keys.contains(&public_key) {
+                return Err(DeckCustomError::StateMismatch(String::from("token of a key outside the joint key")))
+            }
+            match CardProtocol::verify_reveal(&parameters, &public_key, &reveal_token, &masked_card, &proof) {
+                Ok(()) => Ok(None),
+                Err(_e) => Err(DeckCustomError::InvalidRevealToken),
+            }
+        });
+        report.push(StepReport::new(format!("reveal token {}", index), verified));
+    }
+    report
+}
+
+fn check_player(transcript: &GameTranscript, geometry: &DeckGeometry, parameters: &Parameters, player: &Player) -> Result<(PublicKey, KeyOwnershipProof, Vec<u8>), DeckCustomError> {
+    if player.game_id != transcript.game_id {
+        return Err(DeckCustomError::GameMismatch(player.game_user_id.clone()))
+    }
+    let public_key = decode_deck_public_key(player.public_key.clone())?;
+    let key_proof = IdentityProof {
+        commit: player.user_key_proof.commit.clone(),
+        opening: player.user_key_proof.opening.clone(),
+    }.to_curve()?;
+    let context = key_ownership_context(&transcript.game_id, &player.game_user_id, geometry, &transcript.seed_hex);
+    match CardProtocol::verify_key_ownership(parameters, &public_key, &context, &key_proof) {
+        Ok(()) => Ok((public_key, key_proof, context)),
+        Err(_e) => Err(DeckCustomError::InvalidProof),
+    }
+}
+
+// masked_deck[i] must be the i-th initial card of the seed under the joint key
+fn check_mask(transcript: &GameTranscript, geometry: &DeckGeometry, parameters: &Parameters, joint_key: &PublicKey) -> Result<Vec<MaskedCard>, DeckCustomError> {
+    geometry.check_deck_len(transcript.masked_deck.len())?;
+    let initial_cards = encode_cards(transcript.seed_hex.clone(), geometry)?;
+    let masked_deck = MaskDeck { cards: transcript.masked_deck.clone() }.into_masked_card()?;
+    let mut deck = Vec::with_capacity(masked_deck.len());
+    for (position, ((card, _), (masked_card, proof))) in initial_cards.iter().zip(masked_deck.into_iter()).enumerate() {
+        if let Err(_e) = CardProtocol::verify_mask(parameters, joint_key, card, &masked_card, &proof) {
+            return Err(DeckCustomError::StateMismatch(format!("masking proof of position {} does not verify", position)))
+        }
+        deck.push(masked_card);
+    }
+    Ok(deck)
+}
+
+fn check_shuffle(geometry: &DeckGeometry, parameters: &Parameters, joint_key: &PublicKey, origin_deck: &Vec<MaskedCard>, step: &ShuffleStepDTO) -> Result<Vec<MaskedCard>, DeckCustomError> {
+    geometry.check_deck_len(step.shuffled_cards.len())?;
+    let proof = match decode_shuffle_proof(step.proof.clone()) {
+        Ok(proof) => proof,
+        Err(_e) => return Err(DeckCustomError::InvalidProof),
+    };
+    let mut shuffled_deck = Vec::with_capacity(step.shuffled_cards.len());
+    for card in step.shuffled_cards.iter() {
+        shuffled_deck.push(decode_masked_card(card.clone())?);
+    }
+    match CardProtocol::verify_shuffle(parameters, joint_key, origin_deck, &shuffled_deck, &proof) {
+        Ok(()) => Ok(shuffled_deck),
+        Err(_e) => Err(DeckCustomError::InvalidProof),
+    }
+}
+
+fn check_remask(parameters: &Parameters, joint_key: &PublicKey, deck: &[MaskedCard], remask: &RemaskedCardDTO) -> Result<(usize, MaskedCard), DeckCustomError> {
+    let origin_card = decode_masked_card(remask.origin_card.clone())?;
+    let origin_hex = encode_masked_card(origin_card)?;
+    let position = match deck.iter().position(|masked_card| encode_masked_card(*masked_card).map_or(false, |card| card == origin_hex)) {
+        Some(position) => position,
+        None => return Err(DeckCustomError::StateMismatch(String::from("remasked card is not part of the deck"))),
+    };
+    let masked_card = decode_masked_card(remask.masked_card.clone())?;
+    let proof = PedersenProof {
+        a: remask.proof.a.clone(),
+        b: remask.proof.b.clone(),
+        r: remask.proof.r.clone(),
+    }.to_curve()?;
+    match CardProtocol::verify_remask(parameters, joint_key, &origin_card, &masked_card, &proof) {
+        Ok(()) => Ok((position, masked_card)),
+        Err(_e) => Err(DeckCustomError::InvalidProof),
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn transcript(version: u32, seed_hex: &str) -> GameTranscript {
+        GameTranscript {
+            version,
+            game_id: String::from("game"),
+            seed_hex: seed_hex.to_string(),
+            m: 2,
+            n: 26,
+            players: Vec::new(),
+            joined_key: String::new(),
+            masked_deck: Vec::new(),
+            shuffles: Vec::new(),
+            remasks: Vec::new(),
+            reveal_tokens: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_report_stops_at_unusable_transcripts() {
+        let report = verify(&transcript(TRANSCRIPT_VERSION + 1, &"00".repeat(32)));
+        assert_eq!(report.len(), 1);
+        assert!(!report[0].passed);
+
+        let report = verify(&transcript(TRANSCRIPT_VERSION, "abcd"));
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].step, "parameters");
+        assert!(!report[0].passed);
+
+        // parameters are fine, but there is no joint key to check anything against
+        let report = verify(&transcript(TRANSCRIPT_VERSION, &"00".repeat(32)));
+        assert!(report[0].passed);
+        assert!(!report.last().unwrap().passed);
+    }
+}
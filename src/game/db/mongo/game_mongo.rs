@@ -0,0 +1,49 @@
+use mongodb::{error::Result as MongoResult, Client, bson::doc, options::ReplaceOptions, Collection};
+use rocket::futures::TryStreamExt;
+use crate::deck::errors::DeckCustomError;
+use crate::game::models::game::Game;
+use crate::game::repository::GameDbTrait;
+
+const COLLECTION_NAME: &str = "games";
+
+pub struct GameMongo {
+    client: Client,
+    db_name: String,
+}
+
+impl GameMongo {
+    pub async fn new(uri: &str, db_name: &str) -> MongoResult<Self> {
+        let client = Client::with_uri_str(uri).await?;
+        Ok(Self { client, db_name: db_name.into() })
+    }
+
+    fn collection(&self) -> Collection<Game> {
+        self.client.database(&self.db_name).collection(COLLECTION_NAME)
+    }
+}
+
+#[async_trait]
+impl GameDbTrait for GameMongo {
+    async fn get_by_id(&self, game_id: &str) -> Result<Game, DeckCustomError> {
+        match self.collection().find_one(doc! {"game_id": game_id}, None).await? {
+            Some(game) => Ok(game),
+            None => Err(DeckCustomError::GameNotFound),
+        }
+    }
+
+    async fn save(&self, game: Game) -> Result<(), DeckCustomError> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.collection().replace_one(doc! {"game_id": game.game_id.clone()}, game, options).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, game_id: &str) -> Result<(), DeckCustomError> {
+        self.collection().delete_one(doc! {"game_id": game_id}, None).await?;
+        Ok(())
+    }
+
+    async fn list_expired(&self, cutoff: u64) -> Result<Vec<String>, DeckCustomError> {
+        let games: Vec<Game> = self.collection().find(doc! {"updated_at": {"$lt": cutoff as i64}}, None).await?.try_collect().await?;
+        Ok(games.into_iter().map(|game| game.game_id).collect())
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use crate::deck::errors::DeckCustomError;
+use crate::game::models::game::Game;
+use crate::game::repository::GameDbTrait;
+
+pub struct GameMem {
+    store: Mutex<HashMap<String, Game>>,
+}
+
+impl GameMem {
+    pub fn new() -> Self {
+        GameMem {
+            store: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl GameDbTrait for GameMem {
+    async fn get_by_id(&self, game_id: &str) -> Result<Game, DeckCustomError> {
+        match self.store.lock().unwrap().get(game_id) {
+            Some(game) => Ok(game.clone()),
+            None => Err(DeckCustomError::GameNotFound),
+        }
+    }
+
+    async fn save(&self, game: Game) -> Result<(), DeckCustomError> {
+        self.store.lock().unwrap().insert(game.game_id.clone(), game);
+        Ok(())
+    }
+
+    async fn delete(&self, game_id: &str) -> Result<(), DeckCustomError> {
+        self.store.lock().unwrap().remove(game_id);
+        Ok(())
+    }
+
+    async fn list_expired(&self, cutoff: u64) -> Result<Vec<String>, DeckCustomError> {
+        Ok(self.store.lock().unwrap()
+            .values()
+            .filter(|game| game.is_expired(cutoff))
+            .map(|game| game.game_id.clone())
+            .collect())
+    }
+}
@@ -0,0 +1,4 @@
+pub mod models;
+pub mod mem;
+pub mod db;
+pub mod repository;
@@ -0,0 +1,514 @@
+use core::fmt;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use crate::deck::errors::DeckCustomError;
+use crate::deck::models::geometry::DeckGeometry;
+use crate::game::models::transcript::TranscriptLog;
+use crate::keys::vss::KeyDealing;
+
+// Where a game is in the protocol. Every deck operation checks the phase first and
+// the successful ones move it forward, so cards can't be revealed before every
+// player has shuffled a verified deck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GamePhase {
+    Setup,
+    KeysAggregated,
+    Masked,
+    // number of players that shuffled so far
+    Shuffling(usize),
+    Dealt,
+    Revealing,
+    Finished,
+}
+
+impl fmt::Display for GamePhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GamePhase::Shuffling(k) => write!(f, "Shuffling({})", k),
+            phase => write!(f, "{:?}", phase),
+        }
+    }
+}
+
+// who a dealt position of the final deck belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CardOwner {
+    // hole card, only its owner may see it before showdown
+    Player(String),
+    // board card, revealed to everyone
+    Community,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Game {
+    pub game_id: String,
+    pub seed_hex: String,
+    // seed_hex came out of a commit-reveal round, the round itself is not kept
+    #[serde(default)]
+    pub seed_agreed: bool,
+    pub geometry: DeckGeometry,
+    pub phase: GamePhase,
+    // agreed at compute_aggregate_key
+    pub joint_key: Option<String>,
+    // game_user_ids that contributed to the joint key, each of them shuffles once
+    pub players: Vec<String>,
+    // current canonical masked deck, hex encoded
+    pub deck: Vec<String>,
+    // game_user_ids that shuffled the deck so far, in order
+    #[serde(default)]
+    pub shufflers: Vec<String>,
+    // positions of the final deck handed out so far, the rest is never revealed
+    #[serde(with = "dealt_positions")]
+    pub assignments: BTreeMap<usize, CardOwner>,
+    pub showdown: bool,
+    // local players that share their key with the table, game_user_id -> threshold
+    #[serde(default)]
+    pub share_thresholds: BTreeMap<String, usize>,
+    // hex public key of every player, recorded with the joint key
+    #[serde(default)]
+    pub public_keys: BTreeMap<String, String>,
+    // verified key dealings by dealer, what a dropped player's key is recovered from
+    #[serde(default)]
+    pub key_dealings: BTreeMap<String, KeyDealing>,
+    // every deck message this agent handled for the game, in order
+    #[serde(default)]
+    pub transcript: TranscriptLog,
+    // unix seconds of the last change, games idle for longer than the ttl are swept
+    #[serde(default = "unix_now")]
+    pub updated_at: u64,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+impl Game {
+    pub fn new(game_id: String, seed_hex: String, geometry: DeckGeometry) -> Self {
+        Game {
+            game_id,
+            seed_hex,
+            seed_agreed: false,
+            geometry,
+            phase: GamePhase::Setup,
+            joint_key: None,
+            players: Vec::new(),
+            deck: Vec::new(),
+            shufflers: Vec::new(),
+            assignments: BTreeMap::new(),
+            showdown: false,
+            share_thresholds: BTreeMap::new(),
+            public_keys: BTreeMap::new(),
+            key_dealings: BTreeMap::new(),
+            transcript: TranscriptLog::default(),
+            updated_at: unix_now(),
+        }
+    }
+
+    pub fn record(&mut self, operation: &str, input: serde_json::Value, output: serde_json::Value) {
+        self.transcript.append(&self.game_id, operation, input, output);
+    }
+
+    pub fn touch(&mut self) {
+        self.updated_at = unix_now();
+    }
+
+    pub fn is_expired(&self, cutoff: u64) -> bool {
+        self.updated_at < cutoff
+    }
+
+    // only a settled hand, shown down and revealing, is finished, an abandoned one is left to the sweep
+    pub fn finish(&mut self) -> Result<(), DeckCustomError> {
+        self.expect(self.phase == GamePhase::Revealing && self.showdown, "finish")?;
+        self.phase = GamePhase::Finished;
+        Ok(())
+    }
+
+    pub fn check_finished(&self, operation: &str) -> Result<(), DeckCustomError> {
+        self.expect(self.phase == GamePhase::Finished, operation)
+    }
+
+    // another local player joining the same game
+    pub fn check_setup(&self, seed_hex: &str, geometry: &DeckGeometry) -> Result<(), DeckCustomError> {
+        if self.geometry != *geometry {
+            return Err(DeckCustomError::InvalidGeometry(
+                format!("game {} already set up with {}x{}", self.game_id, self.geometry.m, self.geometry.n)))
+        }
+        self.check_seed(seed_hex)?;
+        self.expect(self.phase == GamePhase::Setup, "setup")
+    }
+
+    // every later request names the seed too, a different one would derive other parameters
+    pub fn check_seed(&self, seed_hex: &str) -> Result<(), DeckCustomError> {
+        if !self.seed_hex.eq_ignore_ascii_case(seed_hex) {
+            return Err(DeckCustomError::StateMismatch(format!("game {} was set up with another seed", self.game_id)))
+        }
+        Ok(())
+    }
+
+    pub fn aggregate_keys(&mut self, joint_key: String, players: Vec<String>) -> Result<(), DeckCustomError> {
+        // recomputing the same key is harmless, a different one after the fact is not
+        if self.phase == GamePhase::KeysAggregated && self.joint_key.as_deref() == Some(joint_key.as_str()) {
+            return Ok(())
+        }
+        self.expect(self.phase == GamePhase::Setup, "compute_aggregate_key")?;
+        self.joint_key = Some(joint_key);
+        self.players = players;
+        self.phase = GamePhase::KeysAggregated;
+        Ok(())
+    }
+
+    pub fn share_key(&mut self, game_user_id: String, threshold: usize) -> Result<(), DeckCustomError> {
+        self.expect(self.phase == GamePhase::Setup, "share a key of")?;
+        self.share_thresholds.insert(game_user_id, threshold);
+        Ok(())
+    }
+
+    // shares of a dealer go to every other player, evaluated at their position in game_user_id
+    // order plus one, agents listing the players differently still agree on the indices
+    pub fn share_recipients(&self, dealer: &str) -> Result<BTreeMap<String, u64>, DeckCustomError> {
+        if self.joint_key.is_none() || self.phase == GamePhase::Finished {
+            return Err(self.out_of_order("share keys in"))
+        }
+        if !self.players.iter().any(|player| player == dealer) {
+            return Err(DeckCustomError::StateMismatch(format!("player {} is not part of the joint key", dealer)))
+        }
+        let mut players: Vec<&String> = self.players.iter().collect();
+        players.sort();
+        Ok(players.into_iter().enumerate()
+            .filter(|(_, player)| *player != dealer)
+            .map(|(position, player)| (player.clone(), position as u64 + 1))
+            .collect())
+    }
+
+    pub fn public_key(&self, game_user_id: &str) -> Result<&String, DeckCustomError> {
+        match self.public_keys.get(game_user_id) {
+            Some(public_key) => Ok(public_key),
+            None => Err(DeckCustomError::StateMismatch(format!("player {} is not part of the joint key", game_user_id))),
+        }
+    }
+
+    pub fn key_dealing(&self, dealer: &str) -> Result<&KeyDealing, DeckCustomError> {
+        match self.key_dealings.get(dealer) {
+            Some(dealing) => Ok(dealing),
+            None => Err(DeckCustomError::InvalidKeyShare(format!("no verified key dealing of {}", dealer))),
+        }
+    }
+
+    // a dealer gets one dealing per game, a second different one would split the recovery
+    pub fn record_dealing(&mut self, dealing: KeyDealing) -> Result<(), DeckCustomError> {
+        match self.key_dealings.get(&dealing.dealer) {
+            Some(existing) if *existing != dealing => Err(DeckCustomError::StateMismatch(
+                format!("player {} already dealt other key shares", dealing.dealer))),
+            Some(_) => Ok(()),
+            None => {
+                self.key_dealings.insert(dealing.dealer.clone(), dealing);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn check_joint_key(&self, joint_key: &str) -> Result<(), DeckCustomError> {
+        match &self.joint_key {
+            Some(agreed) if agreed.eq_ignore_ascii_case(joint_key) => Ok(()),
+            Some(_) => Err(DeckCustomError::StateMismatch(String::from("joined_key differs from the aggregated key"))),
+            None => Err(self.out_of_order("use the joint key")),
+        }
+    }
+
+    pub fn check_mask(&self) -> Result<(), DeckCustomError> {
+        self.expect(self.phase == GamePhase::KeysAggregated, "mask")
+    }
+
+    pub fn mask(&mut self, deck: Vec<String>) -> Result<(), DeckCustomError> {
+        // the deck masked by one agent is verified by the others, both land here
+        if self.phase == GamePhase::Masked && self.deck == deck {
+            return Ok(())
+        }
+        self.check_mask()?;
+        self.deck = deck;
+        self.phase = GamePhase::Masked;
+        Ok(())
+    }
+
+    pub fn shuffles(&self) -> Option<usize> {
+        match self.phase {
+            GamePhase::Masked => Some(0),
+            GamePhase::Shuffling(k) => Some(k),
+            _ => None,
+        }
+    }
+
+    pub fn check_shuffle_origin(&self, origin_deck: &[String]) -> Result<(), DeckCustomError> {
+        match self.shuffles() {
+            Some(k) if k < self.players.len() => {}
+            _ => return Err(self.out_of_order("shuffle")),
+        }
+        if self.deck != origin_deck {
+            return Err(DeckCustomError::StateMismatch(String::from("origin deck is not the current deck of the game")))
+        }
+        Ok(())
+    }
+
+    // each player of the joint key shuffles once, whoever shuffles is named
+    pub fn check_shuffle(&self, shuffler: &str, origin_deck: &[String]) -> Result<(), DeckCustomError> {
+        self.check_shuffle_origin(origin_deck)?;
+        if !self.players.iter().any(|player| player == shuffler) {
+            return Err(DeckCustomError::StateMismatch(format!("shuffler {} is not part of the joint key", shuffler)))
+        }
+        if self.shufflers.iter().any(|player| player == shuffler) {
+            return Err(DeckCustomError::StateMismatch(format!("player {} already shuffled the deck", shuffler)))
+        }
+        Ok(())
+    }
+
+    // once every player has shuffled the deck is final and the cards are dealt by position
+    pub fn shuffle(&mut self, shuffler: &str, origin_deck: &[String], shuffled_deck: Vec<String>) -> Result<(), DeckCustomError> {
+        self.check_shuffle(shuffler, origin_deck)?;
+        self.shufflers.push(shuffler.to_string());
+        self.deck = shuffled_deck;
+        let shuffles = self.shufflers.len();
+        self.phase = if shuffles == self.players.len() { GamePhase::Dealt } else { GamePhase::Shuffling(shuffles) };
+        Ok(())
+    }
+
+    pub fn check_dealt(&self, operation: &str) -> Result<(), DeckCustomError> {
+        self.expect(matches!(self.phase, GamePhase::Dealt | GamePhase::Revealing), operation)
+    }
+
+    pub fn check_remask(&self, masked_card: &str) -> Result<usize, DeckCustomError> {
+        self.check_dealt("remask")?;
+        match self.deck.iter().position(|card| card == masked_card) {
+            Some(position) => Ok(position),
+            None => Err(DeckCustomError::StateMismatch(String::from("remasked card is not part of the current deck"))),
+        }
+    }
+
+    // a remasked card keeps its position, and with it its owner
+    pub fn remask(&mut self, remasked_cards: &[(String, String)]) -> Result<(), DeckCustomError> {
+        let mut deck = self.deck.clone();
+        for (origin_card, masked_card) in remasked_cards {
+            let position = self.check_remask(origin_card)?;
+            deck[position] = masked_card.clone();
+        }
+        self.deck = deck;
+        Ok(())
+    }
+
+    pub fn start_revealing(&mut self) -> Result<(), DeckCustomError> {
+        self.check_dealt("reveal")?;
+        self.phase = GamePhase::Revealing;
+        Ok(())
+    }
+
+    // a position keeps its first owner, so a dealt hole card can't be turned into a board card later
+    pub fn deal(&mut self, dealer: &str, assignments: Vec<(usize, CardOwner)>) -> Result<(), DeckCustomError> {
+        self.check_dealt("deal")?;
+        if !self.players.iter().any(|player| player == dealer) {
+            return Err(DeckCustomError::StateMismatch(format!("dealer {} is not part of the joint key", dealer)))
+        }
+        let mut dealt = self.assignments.clone();
+        for (position, owner) in assignments {
+            if position >= self.deck.len() {
+                return Err(DeckCustomError::StateMismatch(format!("position {} is outside the deck", position)))
+            }
+            if let CardOwner::Player(game_user_id) = &owner {
+                if !self.players.contains(game_user_id) {
+                    return Err(DeckCustomError::StateMismatch(format!("player {} is not part of the joint key", game_user_id)))
+                }
+            }
+            match dealt.get(&position) {
+                Some(existing) if *existing != owner => return Err(DeckCustomError::StateMismatch(
+                    format!("position {} is already dealt to {:?}", position, existing))),
+                _ => {}
+            }
+            dealt.insert(position, owner);
+        }
+        self.assignments = dealt;
+        Ok(())
+    }
+
+    // hole cards open up only once the hand is played out, every player holding its hole cards and
+    // revealing under way, positions that were never dealt stay sealed
+    pub fn start_showdown(&mut self) -> Result<(), DeckCustomError> {
+        self.expect(self.phase == GamePhase::Revealing, "showdown")?;
+        let holds_cards = |player: &String| self.assignments.values().any(|owner| *owner == CardOwner::Player(player.clone()));
+        if let Some(player) = self.players.iter().find(|player| !holds_cards(player)) {
+            return Err(DeckCustomError::StateMismatch(format!("player {} has no hole cards dealt", player)))
+        }
+        self.showdown = true;
+        Ok(())
+    }
+
+    // Our token for a card is what lets whoever collects the tokens decrypt it. Board cards
+    // and other players' hole cards are fine, those players need our token to see their
+    // cards. Our own hole cards stay sealed until showdown, peek_cards uses the key locally.
+    pub fn check_reveal(&self, game_user_id: &str, masked_card: &str) -> Result<usize, DeckCustomError> {
+        let (position, owner) = self.dealt_card(masked_card)?;
+        match owner {
+            CardOwner::Player(owner) if owner == game_user_id && !self.showdown => Err(DeckCustomError::RevealNotAllowed(
+                format!("card at position {} is a hole card of {} and the game is not at showdown", position, owner))),
+            _ => Ok(position),
+        }
+    }
+
+    // peeking keeps our token in the agent, so any dealt card of the deck will do, our own hole cards included
+    pub fn check_peek(&self, masked_card: &str) -> Result<usize, DeckCustomError> {
+        Ok(self.dealt_card(masked_card)?.0)
+    }
+
+    // the refusal comes before the transition, a refused request leaves the phase as it was
+    pub fn reveal(&mut self, game_user_id: &str, masked_cards: &[String]) -> Result<(), DeckCustomError> {
+        for masked_card in masked_cards {
+            self.check_reveal(game_user_id, masked_card)?;
+        }
+        self.start_revealing()
+    }
+
+    pub fn peek(&mut self, masked_cards: &[String]) -> Result<(), DeckCustomError> {
+        for masked_card in masked_cards {
+            self.check_peek(masked_card)?;
+        }
+        self.start_revealing()
+    }
+
+    fn dealt_card(&self, masked_card: &str) -> Result<(usize, &CardOwner), DeckCustomError> {
+        self.check_dealt("reveal")?;
+        let position = match self.deck.iter().position(|card| card == masked_card) {
+            Some(position) => position,
+            None => return Err(DeckCustomError::RevealNotAllowed(format!("card is not part of the deck of game {}", self.game_id))),
+        };
+        match self.assignments.get(&position) {
+            Some(owner) => Ok((position, owner)),
+            None => Err(DeckCustomError::RevealNotAllowed(format!("card at position {} has not been dealt", position))),
+        }
+    }
+
+    fn expect(&self, allowed: bool, operation: &str) -> Result<(), DeckCustomError> {
+        if allowed {
+            Ok(())
+        } else {
+            Err(self.out_of_order(operation))
+        }
+    }
+
+    fn out_of_order(&self, operation: &str) -> DeckCustomError {
+        DeckCustomError::OutOfOrder(format!("cannot {} game {} in phase {}", operation, self.game_id, self.phase))
+    }
+}
+
+// stored as a list of (position, owner) pairs, document stores only take string keys
+mod dealt_positions {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(assignments: &BTreeMap<usize, CardOwner>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(assignments.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<usize, CardOwner>, D::Error> {
+        Ok(Vec::<(usize, CardOwner)>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn masked_game(players: usize) -> Game {
+        let mut game = Game::new(String::from("game"), String::from("00"), DeckGeometry::CLASSIC);
+        let players = (0..players).map(|i| format!("player{}", i)).collect();
+        game.aggregate_keys(String::from("key"), players).unwrap();
+        game.mask(vec![String::from("deck0")]).unwrap();
+        game
+    }
+
+    #[test]
+    fn test_reveal_policy() {
+        let mut game = masked_game(2);
+        game.shuffle("player0", &[String::from("deck0")], vec![String::from("a"), String::from("b"), String::from("c")]).unwrap();
+        game.shuffle("player1", &[String::from("a"), String::from("b"), String::from("c")], vec![String::from("x"), String::from("y"), String::from("z")]).unwrap();
+        assert!(matches!(game.deal("stranger", vec![(1, CardOwner::Community)]), Err(DeckCustomError::StateMismatch(_))));
+        game.deal("player1", vec![(0, CardOwner::Player(String::from("player0"))), (1, CardOwner::Community)]).unwrap();
+        assert!(game.deal("player1", vec![(0, CardOwner::Community)]).is_err());
+
+        assert!(matches!(game.check_reveal("player0", "x"), Err(DeckCustomError::RevealNotAllowed(_))));
+        assert_eq!(game.check_reveal("player1", "x").unwrap(), 0);
+        assert_eq!(game.check_reveal("player0", "y").unwrap(), 1);
+        assert!(matches!(game.check_reveal("player0", "z"), Err(DeckCustomError::RevealNotAllowed(_))));
+        assert!(matches!(game.check_reveal("player0", "forged"), Err(DeckCustomError::RevealNotAllowed(_))));
+        assert_eq!(game.check_peek("x").unwrap(), 0);
+        assert!(matches!(game.check_peek("forged"), Err(DeckCustomError::RevealNotAllowed(_))));
+
+        // a refused reveal doesn't move the game on
+        assert!(game.reveal("player0", &[String::from("y"), String::from("x")]).is_err());
+        assert_eq!(game.phase, GamePhase::Dealt);
+        assert!(matches!(game.start_showdown(), Err(DeckCustomError::OutOfOrder(_))));
+        game.reveal("player0", &[String::from("y")]).unwrap();
+        assert_eq!(game.phase, GamePhase::Revealing);
+
+        // every player needs its hole cards first
+        assert!(matches!(game.start_showdown(), Err(DeckCustomError::StateMismatch(_))));
+        assert!(!game.showdown);
+        game.deal("player1", vec![(2, CardOwner::Player(String::from("player1")))]).unwrap();
+        game.start_showdown().unwrap();
+        assert_eq!(game.check_reveal("player0", "x").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_undealt_positions_stay_sealed() {
+        let mut game = masked_game(2);
+        game.shuffle("player0", &[String::from("deck0")], vec![String::from("a"), String::from("b"), String::from("c"), String::from("d")]).unwrap();
+        game.shuffle("player1", &[String::from("a"), String::from("b"), String::from("c"), String::from("d")], vec![String::from("w"), String::from("x"), String::from("y"), String::from("z")]).unwrap();
+        game.deal("player0", vec![(0, CardOwner::Player(String::from("player0"))), (1, CardOwner::Player(String::from("player1"))), (2, CardOwner::Community)]).unwrap();
+        game.reveal("player0", &[String::from("y")]).unwrap();
+        game.start_showdown().unwrap();
+        assert!(matches!(game.check_reveal("player0", "z"), Err(DeckCustomError::RevealNotAllowed(_))));
+        game.finish().unwrap();
+        assert_eq!(game.phase, GamePhase::Finished);
+        assert!(game.check_reveal("player0", "z").is_err());
+        assert!(game.check_peek("z").is_err());
+    }
+
+    #[test]
+    fn test_phases_advance_in_order() {
+        let mut game = masked_game(2);
+        game.shuffle("player0", &[String::from("deck0")], vec![String::from("deck1")]).unwrap();
+        assert_eq!(game.phase, GamePhase::Shuffling(1));
+        game.shuffle("player1", &[String::from("deck1")], vec![String::from("deck2"), String::from("deck3")]).unwrap();
+        assert_eq!(game.phase, GamePhase::Dealt);
+        game.start_revealing().unwrap();
+        assert_eq!(game.phase, GamePhase::Revealing);
+        assert!(matches!(game.finish(), Err(DeckCustomError::OutOfOrder(_))));
+        game.deal("player0", vec![(0, CardOwner::Player(String::from("player0"))), (1, CardOwner::Player(String::from("player1")))]).unwrap();
+        game.start_showdown().unwrap();
+        game.finish().unwrap();
+        assert_eq!(game.phase, GamePhase::Finished);
+        assert!(matches!(game.start_revealing(), Err(DeckCustomError::OutOfOrder(_))));
+    }
+
+    #[test]
+    fn test_out_of_order_calls_are_rejected() {
+        let mut game = Game::new(String::from("game"), String::from("00"), DeckGeometry::CLASSIC);
+        assert!(matches!(game.start_revealing(), Err(DeckCustomError::OutOfOrder(_))));
+        assert!(matches!(game.mask(vec![]), Err(DeckCustomError::OutOfOrder(_))));
+
+        let mut game = masked_game(1);
+        assert!(matches!(game.shuffle("player0", &[String::from("other")], vec![]), Err(DeckCustomError::StateMismatch(_))));
+        game.shuffle("player0", &[String::from("deck0")], vec![String::from("deck1")]).unwrap();
+        assert!(matches!(game.shuffle("player0", &[String::from("deck1")], vec![]), Err(DeckCustomError::OutOfOrder(_))));
+    }
+
+    #[test]
+    fn test_every_player_shuffles_once() {
+        let mut game = masked_game(2);
+        assert!(matches!(game.shuffle("stranger", &[String::from("deck0")], vec![String::from("deck1")]), Err(DeckCustomError::StateMismatch(_))));
+        game.shuffle("player0", &[String::from("deck0")], vec![String::from("deck1")]).unwrap();
+        // the same player twice would leave the other's shuffle out of the final deck
+        assert!(matches!(game.shuffle("player0", &[String::from("deck1")], vec![String::from("deck2")]), Err(DeckCustomError::StateMismatch(_))));
+        assert_eq!(game.phase, GamePhase::Shuffling(1));
+        assert_eq!(game.deck, vec![String::from("deck1")]);
+        game.shuffle("player1", &[String::from("deck1")], vec![String::from("deck2")]).unwrap();
+        assert_eq!(game.shufflers, vec![String::from("player0"), String::from("player1")]);
+        assert_eq!(game.phase, GamePhase::Dealt);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::serialize::fields::hash_fields;
+use serde::{Serialize, Deserialize};
+use crate::deck::errors::DeckCustomError;
+use crate::game::models::game::unix_now;
+
+pub const TRANSCRIPT_LOG_VERSION: u32 = 1;
+
+const ENTRY_DOMAIN: &[u8] = b"deck_agent/transcript_entry/v1";
+
+// one deck operation handled by this agent, chained to the one before it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    pub seq: u64,
+    pub operation: String,
+    // unix seconds
+    pub recorded_at: u64,
+    pub input: serde_json::Value,
+    pub output: serde_json::Value,
+    // hash of the previous entry, all zero for the first one
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+// Append only log of the messages of a game. Each hash covers the previous one, so
+// changing, dropping or reordering an entry breaks every hash after it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptLog {
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl TranscriptLog {
+    pub fn head(&self) -> String {
+        match self.entries.last() {
+            Some(entry) => entry.hash.clone(),
+            None => genesis_hash(),
+        }
+    }
+
+    pub fn append(&mut self, game_id: &str, operation: &str, input: serde_json::Value, output: serde_json::Value) {
+        let mut entry = TranscriptEntry {
+            seq: self.entries.len() as u64,
+            operation: operation.to_string(),
+            recorded_at: unix_now(),
+            input,
+            output,
+            prev_hash: self.head(),
+            hash: String::new(),
+        };
+        entry.hash = entry_hash(game_id, &entry);
+        self.entries.push(entry);
+    }
+
+    pub fn verify(&self, game_id: &str) -> Result<(), DeckCustomError> {
+        let mut prev_hash = genesis_hash();
+        for (seq, entry) in self.entries.iter().enumerate() {
+            if entry.seq != seq as u64 || entry.prev_hash != prev_hash || entry.hash != entry_hash(game_id, entry) {
+                return Err(DeckCustomError::StateMismatch(format!("transcript of game {} is broken at entry {}", game_id, seq)))
+            }
+            prev_hash = entry.hash.clone();
+        }
+        Ok(())
+    }
+}
+
+fn genesis_hash() -> String {
+    hex::encode([0u8; 32])
+}
+
+// input and output are hashed as compact json with object keys sorted, serde_json keeps
+// insertion order once preserve_order is on (bson turns it on), so a verifier re-serializing
+// the exported values would otherwise depend on how they were built
+pub fn entry_hash(game_id: &str, entry: &TranscriptEntry) -> String {
+    let input = canonical_json(&entry.input).to_string();
+    let output = canonical_json(&entry.output).to_string();
+    let fields: [&[u8]; 8] = [
+        ENTRY_DOMAIN,
+        game_id.as_bytes(),
+        &entry.seq.to_le_bytes(),
+        entry.operation.as_bytes(),
+        &entry.recorded_at.to_le_bytes(),
+        input.as_bytes(),
+        output.as_bytes(),
+        entry.prev_hash.as_bytes(),
+    ];
+    hex::encode(hash_fields(&fields))
+}
+
+fn canonical_json(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            serde_json::Value::Object(keys.into_iter().map(|key| (key.clone(), canonical_json(&map[key]))).collect())
+        }
+        serde_json::Value::Array(values) => serde_json::Value::Array(values.iter().map(canonical_json).collect()),
+        value => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use serde_json::json;
+
+    fn log() -> TranscriptLog {
+        let mut log = TranscriptLog::default();
+        log.append("game", "setup", json!({"game_user_id": "player1"}), json!({"user_public_key": "aa"}));
+        log.append("game", "mask", json!({"cards": ["c0", "c1"]}), json!({"cards": ["m0", "m1"]}));
+        log.append("game", "shuffle", json!({"cards": ["m0", "m1"]}), json!({"cards": ["s1", "s0"]}));
+        log
+    }
+
+    #[test]
+    fn test_chain_detects_tampering() {
+        let log = log();
+        assert!(log.verify("game").is_ok());
+        assert_eq!(log.head(), log.entries[2].hash);
+        assert_eq!(log.entries[1].prev_hash, log.entries[0].hash);
+        // the chain is bound to its game
+        assert!(log.verify("other").is_err());
+
+        let mut changed = log.clone();
+        changed.entries[1].output = json!({"cards": ["m1", "m0"]});
+        assert!(changed.verify("game").is_err());
+
+        let mut dropped = log.clone();
+        dropped.entries.remove(1);
+        assert!(dropped.verify("game").is_err());
+
+        // rehashing the changed entry still breaks the link of the next one
+        let mut rehashed = log.clone();
+        rehashed.entries[0].input = json!({"game_user_id": "player2"});
+        rehashed.entries[0].hash = entry_hash("game", &rehashed.entries[0]);
+        assert!(rehashed.verify("game").is_err());
+    }
+
+    #[test]
+    fn test_key_order_does_not_change_the_hash() {
+        let mut inner = serde_json::Map::new();
+        inner.insert(String::from("z"), json!(1));
+        inner.insert(String::from("a"), json!(2));
+        let mut first = serde_json::Map::new();
+        first.insert(String::from("seed_hex"), json!("00"));
+        first.insert(String::from("cards"), json!([serde_json::Value::Object(inner)]));
+
+        let mut inner = serde_json::Map::new();
+        inner.insert(String::from("a"), json!(2));
+        inner.insert(String::from("z"), json!(1));
+        let mut second = serde_json::Map::new();
+        second.insert(String::from("cards"), json!([serde_json::Value::Object(inner)]));
+        second.insert(String::from("seed_hex"), json!("00"));
+
+        let mut first_log = TranscriptLog::default();
+        first_log.append("game", "setup", serde_json::Value::Object(first), json!({}));
+        let mut second_entry = first_log.entries[0].clone();
+        second_entry.input = serde_json::Value::Object(second);
+        assert_eq!(entry_hash("game", &second_entry), first_log.entries[0].hash);
+    }
+
+    #[test]
+    fn test_exported_log_verifies_after_a_round_trip() {
+        let log = log();
+        let exported = serde_json::to_string(&log).unwrap();
+        let imported: TranscriptLog = serde_json::from_str(&exported).unwrap();
+        assert_eq!(imported, log);
+        assert!(imported.verify("game").is_ok());
+    }
+}
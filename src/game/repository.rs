@@ -0,0 +1,17 @@
+use crate::deck::errors::DeckCustomError;
+
+use super::models::game::Game;
+
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait GameDbTrait: Sync + Send {
+    async fn get_by_id(&self, game_id: &str) -> Result<Game, DeckCustomError>;
+    // insert or replace the game with the same game_id
+    async fn save(&self, game: Game) -> Result<(), DeckCustomError>;
+    async fn delete(&self, game_id: &str) -> Result<(), DeckCustomError>;
+    // game_ids last updated before cutoff, in unix seconds
+    async fn list_expired(&self, cutoff: u64) -> Result<Vec<String>, DeckCustomError>;
+}
@@ -0,0 +1,163 @@
+use std::sync::Arc;
+use log::info;
+use mongodb::{error::Result as MongoResult, Client, bson::{self, doc}, options::UpdateOptions, Collection};
+use rocket::futures::TryStreamExt;
+use serde::{Serialize, Deserialize};
+use crate::game_user::models::game_user::GameUser;
+use crate::game_user::repository::GameUserMemTrait;
+use crate::keys::envelope::{MasterKeyRing, WrappedKey};
+use crate::keys::secret::SecretScalar;
+use crate::serialize::fields::length_prefixed;
+use crate::serialize::serialize::{decode_deck_public_key, decode_masked_card, encode_masked_card, encode_public_key};
+use crate::user::errors::CustomError;
+
+const COLLECTION_NAME: &str = "game_users";
+
+// keys and cards are stored hex encoded, the same way they travel over the api,
+// except the private key which only exists envelope encrypted under the master key
+#[derive(Serialize, Deserialize)]
+struct GameUserDocument {
+    game_id: String,
+    game_user_id: String,
+    user_id: String,
+    public_key: String,
+    #[serde(default)]
+    private_key: Option<WrappedKey>,
+    #[serde(default)]
+    seed_hex: Option<String>,
+    cards: Vec<String>,
+}
+
+// binds a key blob to its player, a blob copied onto another record does not open
+fn key_context(game_id: &str, game_user_id: &str) -> Vec<u8> {
+    length_prefixed(&[game_id.as_bytes(), game_user_id.as_bytes()])
+}
+
+impl GameUserDocument {
+    fn from_game_user(game_user: GameUser, master_keys: &MasterKeyRing) -> Result<Self, CustomError> {
+        let public_key = match encode_public_key(game_user.public_key) {
+            Ok(p) => p,
+            Err(e) => return Err(CustomError::GenericError(e.to_string())),
+        };
+        let mut cards = Vec::with_capacity(game_user.cards.len());
+        for card in game_user.cards {
+            cards.push(encode_masked_card(card).map_err(|e| CustomError::GenericError(e.to_string()))?);
+        }
+        let private_key = match &game_user.private_key {
+            Some(private_key) => {
+                let secret = private_key.to_bytes().map_err(|e| CustomError::GenericError(e.to_string()))?;
+                let context = key_context(&game_user.game_id, &game_user.game_user_id);
+                Some(master_keys.seal(&secret, &context).map_err(|e| CustomError::GenericError(e.to_string()))?)
+            }
+            None => None,
+        };
+        Ok(GameUserDocument {
+            game_id: game_user.game_id,
+            game_user_id: game_user.game_user_id,
+            user_id: game_user.user_id,
+            public_key,
+            private_key,
+            seed_hex: game_user.seed_hex,
+            cards,
+        })
+    }
+
+    fn into_game_user(self, master_keys: &MasterKeyRing) -> Result<GameUser, CustomError> {
+        let public_key = decode_deck_public_key(self.public_key).map_err(|e| CustomError::GenericError(e.to_string()))?;
+        let mut game_user = GameUser::without_key(self.game_id, self.game_user_id, self.user_id, public_key);
+        game_user.seed_hex = self.seed_hex;
+        if let Some(blob) = &self.private_key {
+            let context = key_context(&game_user.game_id, &game_user.game_user_id);
+            let secret = master_keys.open(blob, &context).map_err(|e| CustomError::GenericError(e.to_string()))?;
+            game_user.private_key = Some(SecretScalar::from_bytes(&secret).map_err(|e| CustomError::GenericError(e.to_string()))?);
+        }
+        for card in self.cards {
+            game_user.cards.push(decode_masked_card(card).map_err(|e| CustomError::GenericError(e.to_string()))?);
+        }
+        Ok(game_user)
+    }
+}
+
+pub struct GameUserMongo {
+    client: Client,
+    db_name: String,
+    master_keys: Arc<MasterKeyRing>,
+}
+
+impl GameUserMongo {
+    pub async fn new(uri: &str, db_name: &str, master_keys: Arc<MasterKeyRing>) -> MongoResult<Self> {
+        let client = Client::with_uri_str(uri).await?;
+        Ok(Self { client, db_name: db_name.into(), master_keys })
+    }
+
+    fn collection(&self) -> Collection<GameUserDocument> {
+        self.client.database(&self.db_name).collection(COLLECTION_NAME)
+    }
+
+    // after a master key rotation, move every blob still wrapped under an older key to the current one
+    pub async fn rewrap_private_keys(&self) -> Result<usize, CustomError> {
+        let current_id = self.master_keys.current_id().to_string();
+        let filter = doc! {"private_key": {"$ne": null}, "private_key.master_key_id": {"$ne": current_id.clone()}};
+        let documents: Vec<GameUserDocument> = self.collection().find(filter, None).await?.try_collect().await?;
+        let mut rewrapped = 0;
+        for mut document in documents {
+            let blob = match &document.private_key {
+                Some(blob) => blob,
+                None => continue,
+            };
+            let context = key_context(&document.game_id, &document.game_user_id);
+            let previous_id = blob.master_key_id.clone();
+            let rewrapped_blob = self.master_keys.rewrap(blob, &context)
+                .map_err(|e| CustomError::GenericError(e.to_string()))?;
+            document.private_key = Some(rewrapped_blob);
+            // only replace the blob that was read, a concurrent setup may have written a new one
+            let filter = doc! {"game_user_id": document.game_user_id.clone(), "private_key.master_key_id": previous_id};
+            if self.collection().replace_one(filter, document, None).await?.modified_count == 1 {
+                rewrapped += 1;
+            }
+        }
+        info!("re-wrapped {} private keys under master key {}", rewrapped, current_id);
+        Ok(rewrapped)
+    }
+}
+
+#[async_trait]
+impl GameUserMemTrait for GameUserMongo {
+    async fn get_by_id(&self, game_user_id: &str) -> Result<Arc<GameUser>, CustomError> {
+        match self.collection().find_one(doc! {"game_user_id": game_user_id}, None).await? {
+            Some(document) => Ok(Arc::new(document.into_game_user(&self.master_keys)?)),
+            None => Err(CustomError::UserNotFound),
+        }
+    }
+
+    async fn get_by_game_id(&self, game_id: &str) -> Result<Vec<Arc<GameUser>>, CustomError> {
+        let documents: Vec<GameUserDocument> = self.collection().find(doc! {"game_id": game_id}, None).await?.try_collect().await?;
+        documents.into_iter().map(|document| Ok(Arc::new(document.into_game_user(&self.master_keys)?))).collect()
+    }
+
+    async fn create(&self, game_user: GameUser) -> Result<String, CustomError> {
+        if game_user.game_user_id.is_empty(){
+            return Err(CustomError::GenericError(String::from("user game_user_id empty")));
+        }
+        let game_user_id = game_user.game_user_id.clone();
+        let document = GameUserDocument::from_game_user(game_user, &self.master_keys)?;
+        let document = bson::to_document(&document).map_err(|e| CustomError::GenericError(e.to_string()))?;
+        // only inserted when the game_user_id is free, like the in-memory store an existing record keeps its key
+        let options = UpdateOptions::builder().upsert(true).build();
+        let result = self.collection().update_one(doc! {"game_user_id": game_user_id.clone()}, doc! {"$setOnInsert": document}, options).await?;
+        if result.matched_count > 0 {
+            return Err(CustomError::UserAlreadyExists);
+        }
+        Ok(game_user_id)
+    }
+
+    async fn delete(&self, game_user_id: &str) -> Result<(), CustomError> {
+        self.collection().delete_one(doc! {"game_user_id": game_user_id}, None).await?;
+        Ok(())
+    }
+
+    async fn delete_by_game_id(&self, game_id: &str) -> Result<usize, CustomError> {
+        let result = self.collection().delete_many(doc! {"game_id": game_id}, None).await?;
+        Ok(result.deleted_count as usize)
+    }
+}
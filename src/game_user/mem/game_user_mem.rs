@@ -1,45 +1,61 @@
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use crate::game_user::models::game_user::GameUser;
 use crate::user::errors::CustomError;
-use crate::user::models::use_case::user::GetUserResponse;
-use crate::user::models::user::User;
 use crate::game_user::repository::GameUserMemTrait;
 
 pub struct GameUserMem {
-    store: HashMap<String, GameUser>,
+    store: Mutex<HashMap<String, Arc<GameUser>>>,
 }
 
 impl GameUserMem {
     pub  fn new() -> Self {
         GameUserMem {
-            store:HashMap::new(),
+            store:Mutex::new(HashMap::new()),
         }
     }
 }
 
+#[async_trait]
 impl GameUserMemTrait for GameUserMem {
-    fn get_by_id(&self,game_user_id: &str) -> Result<&GameUser, CustomError> {
-        let user = match self.store.get(game_user_id){
-            Some(game_user) => game_user,
-            None => return Err(CustomError::UserNotFound),
-        };
-        Ok(user)
+    async fn get_by_id(&self,game_user_id: &str) -> Result<Arc<GameUser>, CustomError> {
+        match self.store.lock().unwrap().get(game_user_id){
+            Some(game_user) => Ok(game_user.clone()),
+            None => Err(CustomError::UserNotFound),
+        }
+    }
+
+    async fn get_by_game_id(&self, game_id: &str) -> Result<Vec<Arc<GameUser>>, CustomError> {
+        Ok(self.store.lock().unwrap()
+            .values()
+            .filter(|game_user| game_user.game_id == game_id)
+            .cloned()
+            .collect())
     }
 
-    fn create(&mut self, user: GameUser) -> Result<String, CustomError> {
+    async fn create(&self, user: GameUser) -> Result<String, CustomError> {
         if user.game_user_id.is_empty(){
             return Err(CustomError::GenericError(String::from("user game_user_id empty")));
         }
         let game_user_id = user.game_user_id.clone();
-        self.store.insert(user.game_user_id.clone(), user);
+        // a game_user_id holds one key, replacing it would cut off the game that got it first
+        let mut store = self.store.lock().unwrap();
+        if store.contains_key(&game_user_id) {
+            return Err(CustomError::UserAlreadyExists);
+        }
+        store.insert(game_user_id.clone(), Arc::new(user));
         Ok(game_user_id)
     }
 
-    fn delete(&mut self, id: &str) -> Result<(), CustomError> {
-        self.store.remove(id);
+    async fn delete(&self, id: &str) -> Result<(), CustomError> {
+        self.store.lock().unwrap().remove(id);
         Ok(())
     }
-}
-
-
 
+    async fn delete_by_game_id(&self, game_id: &str) -> Result<usize, CustomError> {
+        let mut store = self.store.lock().unwrap();
+        let before = store.len();
+        store.retain(|_, game_user| game_user.game_id != game_id);
+        Ok(before - store.len())
+    }
+}
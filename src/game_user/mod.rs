@@ -1,3 +1,4 @@
 pub mod models;
 pub mod mem;
+pub mod db;
 pub mod repository;
\ No newline at end of file
@@ -1,10 +1,8 @@
-use ark_ec::AffineCurve;
 use ark_ec::short_weierstrass_jacobian::GroupAffine;
 use proof_essentials::zkp::proofs::{chaum_pedersen_dl_equality, schnorr_identification};
 use starknet_curve::StarkwareParameters;
 
 type PublicKey = GroupAffine<StarkwareParameters>;
-type PrivateKey = <ark_ec::short_weierstrass_jacobian::GroupAffine<StarkwareParameters> as AffineCurve>::ScalarField;
 
 type Curve = starknet_curve::Projective;
 type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
@@ -14,27 +12,46 @@ type ProofKeyOwnership = schnorr_identification::proof::Proof<Curve>;
 type RemaskingProof = chaum_pedersen_dl_equality::proof::Proof<Curve>;
 type RevealProof = chaum_pedersen_dl_equality::proof::Proof<Curve>;
 use crate::deck::errors::DeckCustomError;
+use crate::keys::secret::SecretScalar;
 use crate::serialize::serialize::{decode_masked_card, decode_masking_proof, encode_masked_card, encode_masking_proof};
 
 type MaskedCard = barnett_smart_card_protocol::discrete_log_cards::MaskedCard<Curve>;
 type RevealToken = barnett_smart_card_protocol::discrete_log_cards::RevealToken<Curve>;
 
-#[derive(Clone)]
+// not Clone, repositories hand out shared references so the key exists once
 pub struct GameUser {
+    pub game_id:String,
     pub game_user_id:String,
     pub user_id: String,
     pub public_key: PublicKey,
-    pub private_key: PrivateKey,
+    // None when the key store derives the key on demand instead of keeping it
+    pub private_key: Option<SecretScalar>,
+    // seed_hex of the game, part of the derivation path when the key store derives the key
+    pub seed_hex: Option<String>,
     pub cards: Vec<MaskedCard>,
 }
 
 impl GameUser {
-    pub fn new(game_user_id:String,user_id:String,public_key: PublicKey,private_key: PrivateKey) -> Self {
+    pub fn new(game_id:String,game_user_id:String,user_id:String,public_key: PublicKey,private_key: SecretScalar) -> Self {
         Self{
+            game_id:game_id,
             game_user_id:game_user_id,
             user_id:user_id,
             public_key:public_key,
-            private_key:private_key,
+            private_key:Some(private_key),
+            seed_hex: None,
+            cards: Vec::new(),
+        }
+    }
+
+    pub fn without_key(game_id:String,game_user_id:String,user_id:String,public_key: PublicKey) -> Self {
+        Self{
+            game_id:game_id,
+            game_user_id:game_user_id,
+            user_id:user_id,
+            public_key:public_key,
+            private_key:None,
+            seed_hex: None,
             cards: Vec::new(),
         }
     }
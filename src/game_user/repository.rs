@@ -1,3 +1,4 @@
+use std::sync::Arc;
 use crate::user::errors::CustomError;
 
 use super::models::game_user::GameUser;
@@ -5,17 +6,14 @@ use super::models::game_user::GameUser;
 #[cfg(test)]
 use mockall::automock;
 
-// #[cfg_attr(test, automock)]
-// #[async_trait]
-// pub trait UserDbTrait: Sync + Send {
-//     async fn get_by_id(&self, id: &str) -> Result<GetUserResponse, CustomError>;
-//     async fn create(&self, user: User) -> Result<String, CustomError>;
-//     async fn delete(&self, id: &str) -> Result<(), CustomError>;
-// }
-#[cfg_attr(test, automock )]
+#[cfg_attr(test, automock)]
+#[async_trait]
 pub trait GameUserMemTrait: Sync + Send {
-     fn get_by_id(&self, id: &str) -> Result<& GameUser, CustomError>;
-     fn create(&mut self, game_user: GameUser) -> Result<String, CustomError>;
-     fn delete(&mut self, id: &str) -> Result<(), CustomError>;
+    async fn get_by_id(&self, id: &str) -> Result<Arc<GameUser>, CustomError>;
+    // local players of a game, usually one per agent
+    async fn get_by_game_id(&self, game_id: &str) -> Result<Vec<Arc<GameUser>>, CustomError>;
+    async fn create(&self, game_user: GameUser) -> Result<String, CustomError>;
+    async fn delete(&self, id: &str) -> Result<(), CustomError>;
+    // drops every local player of a game together with its key, returns how many
+    async fn delete_by_game_id(&self, game_id: &str) -> Result<usize, CustomError>;
 }
-
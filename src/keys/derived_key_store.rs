@@ -0,0 +1,186 @@
+use std::env;
+use std::fs;
+use std::sync::Arc;
+use barnett_smart_card_protocol::BarnettSmartProtocol;
+use hex::FromHex;
+use rand_chacha::ChaCha20Rng;
+use rand_core::SeedableRng;
+use zeroize::Zeroizing;
+use crate::deck::errors::DeckCustomError;
+use crate::game_user::models::game_user::GameUser;
+use crate::game_user::repository::GameUserMemTrait;
+use crate::keys::key_store::{self, KeyStore};
+use crate::keys::secret::SecretScalar;
+use crate::keys::vss::{KeyDealing, ShareRecipient};
+use crate::serialize::fields::hash_fields;
+
+type Curve = starknet_curve::Projective;
+type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
+type Parameters = <CardProtocol as BarnettSmartProtocol>::Parameters;
+type PublicKey = <CardProtocol as BarnettSmartProtocol>::PlayerPublicKey;
+type MaskedCard = barnett_smart_card_protocol::discrete_log_cards::MaskedCard<Curve>;
+type RevealToken = barnett_smart_card_protocol::discrete_log_cards::RevealToken<Curve>;
+type ZKProofKeyOwnership = <CardProtocol as BarnettSmartProtocol>::ZKProofKeyOwnership;
+type RevealProof = <CardProtocol as BarnettSmartProtocol>::ZKProofReveal;
+
+// hex encoded agent master seed, at least 32 bytes
+pub const KEY_SEED_ENV: &str = "DECK_KEY_SEED";
+// file holding the hex encoded master seed
+pub const KEY_SEED_FILE_ENV: &str = "DECK_KEY_SEED_FILE";
+
+const KEY_DERIVATION_DOMAIN: &[u8] = b"deck_agent/player_key/v2";
+const MIN_SEED_LEN: usize = 32;
+
+// Derives every player key from the agent master seed, (user_id, game_id, game_user_id)
+// and the seed_hex the game was set up with, so only the non secret player records are
+// stored. An agent that lost its keys derives the same ones again from the master seed and
+// the public setup values and can finish the reveals of a running game. A game set up again
+// under the same ids gets another key as long as it agrees on a fresh seed, which the seed
+// rounds give every game.
+pub struct DerivedKeyStore {
+    master_seed: Zeroizing<Vec<u8>>,
+    game_users: Arc<dyn GameUserMemTrait>,
+}
+
+impl DerivedKeyStore {
+    pub fn new(master_seed: Zeroizing<Vec<u8>>, game_users: Arc<dyn GameUserMemTrait>) -> Result<Self, DeckCustomError> {
+        if master_seed.len() < MIN_SEED_LEN {
+            return Err(DeckCustomError::KeyEncryption(format!("master seed must be at least {} bytes", MIN_SEED_LEN)))
+        }
+        Ok(DerivedKeyStore {
+            master_seed,
+            game_users,
+        })
+    }
+
+    // None when no master seed is configured, the agent then keeps random keys
+    pub fn seed_from_env() -> Result<Option<Zeroizing<Vec<u8>>>, DeckCustomError> {
+        let seed_hex = match (env::var(KEY_SEED_ENV), env::var(KEY_SEED_FILE_ENV)) {
+            (Ok(seed_hex), _) => Zeroizing::new(seed_hex),
+            (Err(_e), Ok(path)) => match fs::read_to_string(&path) {
+                Ok(seed_hex) => Zeroizing::new(seed_hex),
+                Err(e) => return Err(DeckCustomError::KeyEncryption(format!("cannot read {}: {}", path, e))),
+            },
+            (Err(_e), Err(_)) => return Ok(None),
+        };
+        match Vec::from_hex(seed_hex.trim()) {
+            Ok(seed) => Ok(Some(Zeroizing::new(seed))),
+            Err(_e) => Err(DeckCustomError::KeyEncryption(String::from("master seed is not hex encoded"))),
+        }
+    }
+
+    // the key pair comes out of player_keygen fed with a stream keyed by the derivation path,
+    // every field is length prefixed so distinct paths never collide
+    // seed_hex compares regardless of case everywhere else, so it is lowercased first
+    fn derive(&self, parameters: &Parameters, user_id: &str, game_id: &str, game_user_id: &str, seed_hex: &str) -> Result<(PublicKey, SecretScalar), DeckCustomError> {
+        let seed_hex = seed_hex.to_ascii_lowercase();
+        let child_seed = Zeroizing::new(hash_fields(&[KEY_DERIVATION_DOMAIN, self.master_seed.as_slice(), user_id.as_bytes(), game_id.as_bytes(), game_user_id.as_bytes(), seed_hex.as_bytes()]));
+        let mut rng = ChaCha20Rng::from_seed(*child_seed);
+        match CardProtocol::player_keygen(&mut rng, parameters) {
+            Ok((pk, sk)) => Ok((pk, SecretScalar::new(sk))),
+            Err(_e) => Err(DeckCustomError::InvalidPublicKey),
+        }
+    }
+
+    // re-derive the key of a known player, a changed master seed must not go unnoticed
+    async fn player_key(&self, parameters: &Parameters, game_user_id: &str) -> Result<(PublicKey, SecretScalar), DeckCustomError> {
+        let game_user = self.game_users.get_by_id(game_user_id).await?;
+        let seed_hex = match &game_user.seed_hex {
+            Some(seed_hex) => seed_hex,
+            None => return Err(DeckCustomError::KeyEncryption(format!("no seed stored for {}", game_user_id))),
+        };
+        let (pk, sk) = self.derive(parameters, &game_user.user_id, &game_user.game_id, &game_user.game_user_id, seed_hex)?;
+        if pk != game_user.public_key {
+            return Err(DeckCustomError::KeyEncryption(format!("derived key of {} does not match its public key", game_user_id)))
+        }
+        Ok((pk, sk))
+    }
+}
+
+#[async_trait]
+impl KeyStore for DerivedKeyStore {
+    async fn keygen(&self, parameters: &Parameters, game_id: &str, game_user_id: &str, user_id: &str, seed_hex: &str) -> Result<PublicKey, DeckCustomError> {
+        let (pk, _sk) = self.derive(parameters, user_id, game_id, game_user_id, seed_hex)?;
+        let mut game_user = GameUser::without_key(game_id.to_string(), game_user_id.to_string(), user_id.to_string(), pk);
+        game_user.seed_hex = Some(seed_hex.to_string());
+        self.game_users.create(game_user).await?;
+        Ok(pk)
+    }
+
+    async fn prove_key_ownership(&self, parameters: &Parameters, game_user_id: &str, context: &[u8]) -> Result<ZKProofKeyOwnership, DeckCustomError> {
+        let (pk, sk) = self.player_key(parameters, game_user_id).await?;
+        key_store::prove_key_ownership(parameters, &pk, &sk, context)
+    }
+
+    async fn compute_reveal_tokens(&self, parameters: &Parameters, game_user_id: &str, masked_cards: &[MaskedCard]) -> Result<Vec<(RevealToken, RevealProof)>, DeckCustomError> {
+        let (pk, sk) = self.player_key(parameters, game_user_id).await?;
+        key_store::compute_reveal_tokens(parameters, &pk, &sk, masked_cards)
+    }
+
+    async fn export_public_key(&self, game_user_id: &str) -> Result<PublicKey, DeckCustomError> {
+        Ok(self.game_users.get_by_id(game_user_id).await?.public_key)
+    }
+
+    async fn export_private_key(&self, parameters: &Parameters, game_user_id: &str) -> Result<SecretScalar, DeckCustomError> {
+        let (_pk, sk) = self.player_key(parameters, game_user_id).await?;
+        Ok(sk)
+    }
+
+    async fn deal_key_shares(&self, parameters: &Parameters, game_user_id: &str, game_id: &str, threshold: usize, recipients: &[ShareRecipient]) -> Result<KeyDealing, DeckCustomError> {
+        let (_pk, sk) = self.player_key(parameters, game_user_id).await?;
+        key_store::deal_key_shares(parameters, &sk, game_user_id, game_id, threshold, recipients)
+    }
+
+    async fn diffie_hellman(&self, parameters: &Parameters, game_user_id: &str, point: &PublicKey) -> Result<Zeroizing<Vec<u8>>, DeckCustomError> {
+        let (_pk, sk) = self.player_key(parameters, game_user_id).await?;
+        key_store::diffie_hellman(&sk, point)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use rand::thread_rng;
+    use crate::game_user::mem::game_user_mem::GameUserMem;
+
+    fn key_store(seed: u8, game_users: &Arc<GameUserMem>) -> DerivedKeyStore {
+        DerivedKeyStore::new(Zeroizing::new(vec![seed; MIN_SEED_LEN]), game_users.clone()).unwrap()
+    }
+
+    const SEED_HEX: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[tokio::test]
+    async fn test_keys_are_rederived_after_a_restart() {
+        let parameters = CardProtocol::setup(&mut thread_rng(), 2, 26).unwrap();
+        let game_users = Arc::new(GameUserMem::new());
+        let pk = key_store(7, &game_users).keygen(&parameters, "game", "player1", "user1", SEED_HEX).await.unwrap();
+
+        // a fresh agent with the same master seed and records ends up with the same key
+        let restarted = key_store(7, &game_users);
+        let (restarted_pk, _sk) = restarted.player_key(&parameters, "player1").await.unwrap();
+        assert_eq!(restarted_pk, pk);
+        assert!(restarted.prove_key_ownership(&parameters, "player1", b"context").await.is_ok());
+        assert!(matches!(key_store(8, &game_users).player_key(&parameters, "player1").await, Err(DeckCustomError::KeyEncryption(_))));
+        assert!(DerivedKeyStore::new(Zeroizing::new(vec![7; 16]), game_users.clone()).is_err());
+
+        // with the records lost too, the setup values alone give the key back
+        let restored_users = Arc::new(GameUserMem::new());
+        let restored = key_store(7, &restored_users);
+        assert_eq!(restored.keygen(&parameters, "game", "player1", "user1", &SEED_HEX.to_uppercase()).await.unwrap(), pk);
+        assert!(restored.prove_key_ownership(&parameters, "player1", b"context").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_another_seed_gets_another_key() {
+        let parameters = CardProtocol::setup(&mut thread_rng(), 2, 26).unwrap();
+        let game_users = Arc::new(GameUserMem::new());
+        let key_store = key_store(7, &game_users);
+        let pk = key_store.keygen(&parameters, "game", "player1", "user1", SEED_HEX).await.unwrap();
+
+        // a disclosed or swept player is gone, its key is not handed out again
+        game_users.delete("player1").await.unwrap();
+        assert!(key_store.export_private_key(&parameters, "player1").await.is_err());
+        // and the game set up again under the same ids with a fresh seed gets another one
+        assert_ne!(key_store.keygen(&parameters, "game", "player1", "user1", &"ff".repeat(32)).await.unwrap(), pk);
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use hex::FromHex;
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use zeroize::{Zeroize, Zeroizing};
+use crate::deck::errors::DeckCustomError;
+
+// comma separated id:hex pairs, the first one is the current master key
+pub const MASTER_KEYS_ENV: &str = "DECK_MASTER_KEYS";
+// one id:hex pair per line, the first one is the current master key
+pub const MASTER_KEY_FILE_ENV: &str = "DECK_MASTER_KEY_FILE";
+
+const BLOB_VERSION: u8 = 1;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+struct MasterKey {
+    id: String,
+    key: [u8; KEY_LEN],
+}
+
+impl Drop for MasterKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+// Envelope encryption of secrets at rest. Every blob gets its own random data key which
+// encrypts the secret, the data key itself is encrypted with a master key. Rotating the
+// master key only re-encrypts the small wrapped data keys, old master keys stay in the
+// ring until every blob has been re-wrapped.
+pub struct MasterKeyRing {
+    current: MasterKey,
+    previous: HashMap<String, MasterKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrappedKey {
+    pub version: u8,
+    pub master_key_id: String,
+    // nonce || ciphertext of the data key under the master key, hex
+    pub wrapped_data_key: String,
+    // nonce || ciphertext of the secret under the data key, hex
+    pub ciphertext: String,
+}
+
+impl MasterKeyRing {
+    pub fn from_env() -> Result<Self, DeckCustomError> {
+        if let Ok(keys) = env::var(MASTER_KEYS_ENV) {
+            return Self::parse(keys.split(','))
+        }
+        match env::var(MASTER_KEY_FILE_ENV) {
+            Ok(path) => match fs::read_to_string(&path) {
+                Ok(keys) => Self::parse(keys.lines()),
+                Err(e) => Err(DeckCustomError::KeyEncryption(format!("cannot read {}: {}", path, e))),
+            },
+            Err(_e) => Err(DeckCustomError::KeyEncryption(format!("neither {} nor {} is set", MASTER_KEYS_ENV, MASTER_KEY_FILE_ENV))),
+        }
+    }
+
+    fn parse<'a>(entries: impl Iterator<Item = &'a str>) -> Result<Self, DeckCustomError> {
+        let mut keys = Vec::new();
+        for entry in entries.map(str::trim).filter(|entry| !entry.is_empty() && !entry.starts_with('#')) {
+            let (id, key_hex) = match entry.split_once(':') {
+                Some(pair) => pair,
+                None => return Err(DeckCustomError::KeyEncryption(String::from("master keys must be given as id:hex"))),
+            };
+            let key = match <[u8; KEY_LEN]>::from_hex(key_hex.trim()) {
+                Ok(key) => key,
+                Err(_e) => return Err(DeckCustomError::KeyEncryption(format!("master key {} must be {} hex encoded bytes", id, KEY_LEN))),
+            };
+            keys.push(MasterKey { id: id.trim().to_string(), key });
+        }
+        let mut keys = keys.into_iter();
+        let current = match keys.next() {
+            Some(current) => current,
+            None => return Err(DeckCustomError::KeyEncryption(String::from("no master key configured"))),
+        };
+        let mut previous = HashMap::new();
+        for key in keys {
+            if key.id == current.id || previous.contains_key(&key.id) {
+                return Err(DeckCustomError::KeyEncryption(format!("master key id {} is used twice", key.id)))
+            }
+            previous.insert(key.id.clone(), key);
+        }
+        Ok(MasterKeyRing { current, previous })
+    }
+
+    pub fn current_id(&self) -> &str {
+        &self.current.id
+    }
+
+    // context is authenticated but not stored, e.g. the owner of the secret, so a blob
+    // copied onto another record fails to open
+    pub fn seal(&self, secret: &[u8], context: &[u8]) -> Result<WrappedKey, DeckCustomError> {
+        let mut data_key = Zeroizing::new([0u8; KEY_LEN]);
+        rand::thread_rng().fill_bytes(&mut *data_key);
+        let ciphertext = encrypt(&*data_key, secret, context)?;
+        let wrapped_data_key = encrypt(&self.current.key, &*data_key, &self.data_key_aad(&self.current.id, context))?;
+        Ok(WrappedKey {
+            version: BLOB_VERSION,
+            master_key_id: self.current.id.clone(),
+            wrapped_data_key: hex::encode(wrapped_data_key),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    pub fn open(&self, blob: &WrappedKey, context: &[u8]) -> Result<Zeroizing<Vec<u8>>, DeckCustomError> {
+        let data_key = self.unwrap_data_key(blob, context)?;
+        decrypt(&data_key, &decode_hex(&blob.ciphertext)?, context)
+    }
+
+    // re-encrypt the data key under the current master key, the secret itself is untouched
+    pub fn rewrap(&self, blob: &WrappedKey, context: &[u8]) -> Result<WrappedKey, DeckCustomError> {
+        if blob.master_key_id == self.current.id {
+            return Ok(blob.clone())
+        }
+        let data_key = self.unwrap_data_key(blob, context)?;
+        let wrapped_data_key = encrypt(&self.current.key, &data_key, &self.data_key_aad(&self.current.id, context))?;
+        Ok(WrappedKey {
+            version: BLOB_VERSION,
+            master_key_id: self.current.id.clone(),
+            wrapped_data_key: hex::encode(wrapped_data_key),
+            ciphertext: blob.ciphertext.clone(),
+        })
+    }
+
+    fn unwrap_data_key(&self, blob: &WrappedKey, context: &[u8]) -> Result<Zeroizing<Vec<u8>>, DeckCustomError> {
+        if blob.version != BLOB_VERSION {
+            return Err(DeckCustomError::KeyEncryption(format!("unsupported key blob version {}", blob.version)))
+        }
+        let master_key = if blob.master_key_id == self.current.id {
+            &self.current
+        } else {
+            match self.previous.get(&blob.master_key_id) {
+                Some(master_key) => master_key,
+                None => return Err(DeckCustomError::KeyEncryption(format!("unknown master key {}", blob.master_key_id))),
+            }
+        };
+        decrypt(&master_key.key, &decode_hex(&blob.wrapped_data_key)?, &self.data_key_aad(&master_key.id, context))
+    }
+
+    fn data_key_aad(&self, master_key_id: &str, context: &[u8]) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(1 + master_key_id.len() + context.len());
+        aad.push(BLOB_VERSION);
+        aad.extend_from_slice(&(master_key_id.len() as u64).to_le_bytes());
+        aad.extend_from_slice(master_key_id.as_bytes());
+        aad.extend_from_slice(context);
+        aad
+    }
+}
+
+pub(crate) fn encrypt(key: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, DeckCustomError> {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    match cipher.encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad }) {
+        Ok(ciphertext) => Ok([nonce.to_vec(), ciphertext].concat()),
+        Err(_e) => Err(DeckCustomError::KeyEncryption(String::from("encryption failed"))),
+    }
+}
+
+// plaintexts here are always key material
+pub(crate) fn decrypt(key: &[u8], sealed: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, DeckCustomError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(DeckCustomError::KeyEncryption(String::from("key blob is truncated")))
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    match cipher.decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad }) {
+        Ok(plaintext) => Ok(Zeroizing::new(plaintext)),
+        Err(_e) => Err(DeckCustomError::KeyEncryption(String::from("key blob does not authenticate"))),
+    }
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, DeckCustomError> {
+    match Vec::from_hex(value) {
+        Ok(bytes) => Ok(bytes),
+        Err(_e) => Err(DeckCustomError::KeyEncryption(String::from("key blob is not hex encoded"))),
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    const OLD_KEY: &str = "old:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const NEW_KEY: &str = "new:1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    #[test]
+    fn test_seal_and_open() {
+        let ring = MasterKeyRing::parse([OLD_KEY].into_iter()).unwrap();
+        let blob = ring.seal(b"secret", b"player1").unwrap();
+        assert_eq!(ring.open(&blob, b"player1").unwrap().as_slice(), b"secret");
+        assert!(matches!(ring.open(&blob, b"player2"), Err(DeckCustomError::KeyEncryption(_))));
+    }
+
+    #[test]
+    fn test_rotation_rewraps_blobs() {
+        let old_ring = MasterKeyRing::parse([OLD_KEY].into_iter()).unwrap();
+        let blob = old_ring.seal(b"secret", b"player1").unwrap();
+
+        let ring = MasterKeyRing::parse([NEW_KEY, OLD_KEY].into_iter()).unwrap();
+        assert_eq!(ring.open(&blob, b"player1").unwrap().as_slice(), b"secret");
+        let rewrapped = ring.rewrap(&blob, b"player1").unwrap();
+        assert_eq!(rewrapped.master_key_id, "new");
+        assert_eq!(rewrapped.ciphertext, blob.ciphertext);
+
+        let new_only = MasterKeyRing::parse([NEW_KEY].into_iter()).unwrap();
+        assert_eq!(new_only.open(&rewrapped, b"player1").unwrap().as_slice(), b"secret");
+        assert!(new_only.open(&blob, b"player1").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ff::PrimeField;
+use barnett_smart_card_protocol::BarnettSmartProtocol;
+use rand::thread_rng;
+use zeroize::Zeroizing;
+use crate::deck::errors::DeckCustomError;
+use crate::keys::secret::SecretScalar;
+use crate::keys::vss::{self, KeyDealing, ShareRecipient};
+
+#[cfg(test)]
+use mockall::automock;
+
+type Curve = starknet_curve::Projective;
+type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
+type Parameters = <CardProtocol as BarnettSmartProtocol>::Parameters;
+type PublicKey = <CardProtocol as BarnettSmartProtocol>::PlayerPublicKey;
+type MaskedCard = barnett_smart_card_protocol::discrete_log_cards::MaskedCard<Curve>;
+type RevealToken = barnett_smart_card_protocol::discrete_log_cards::RevealToken<Curve>;
+type ZKProofKeyOwnership = <CardProtocol as BarnettSmartProtocol>::ZKProofKeyOwnership;
+type RevealProof = <CardProtocol as BarnettSmartProtocol>::ZKProofReveal;
+
+// Every operation that needs a player's private key goes through a KeyStore, the
+// scalar itself only leaves it through export_private_key. Keys are addressed by game_user_id.
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait KeyStore: Sync + Send {
+    // generate and keep the key pair of a new player of the game set up with seed_hex
+    async fn keygen(&self, parameters: &Parameters, game_id: &str, game_user_id: &str, user_id: &str, seed_hex: &str) -> Result<PublicKey, DeckCustomError>;
+
+    async fn prove_key_ownership(&self, parameters: &Parameters, game_user_id: &str, context: &[u8]) -> Result<ZKProofKeyOwnership, DeckCustomError>;
+
+    // one token and proof per masked card, in the same order
+    async fn compute_reveal_tokens(&self, parameters: &Parameters, game_user_id: &str, masked_cards: &[MaskedCard]) -> Result<Vec<(RevealToken, RevealProof)>, DeckCustomError>;
+
+    async fn export_public_key(&self, game_user_id: &str) -> Result<PublicKey, DeckCustomError>;
+
+    // Feldman shares of the player's key, each one encrypted to its recipient
+    async fn deal_key_shares(&self, parameters: &Parameters, game_user_id: &str, game_id: &str, threshold: usize, recipients: &[ShareRecipient]) -> Result<KeyDealing, DeckCustomError>;
+
+    // the key itself, only for disclosure once the game is Finished
+    async fn export_private_key(&self, parameters: &Parameters, game_user_id: &str) -> Result<SecretScalar, DeckCustomError>;
+
+    // private key times point, serialized, used to open shares other players encrypted to us
+    async fn diffie_hellman(&self, parameters: &Parameters, game_user_id: &str, point: &PublicKey) -> Result<Zeroizing<Vec<u8>>, DeckCustomError>;
+}
+
+// What the stores do once they hold the scalar is the same, only where the key
+// comes from differs.
+
+pub(crate) fn prove_key_ownership(parameters: &Parameters, public_key: &PublicKey, private_key: &SecretScalar, context: &[u8]) -> Result<ZKProofKeyOwnership, DeckCustomError> {
+    match CardProtocol::prove_key_ownership(&mut thread_rng(), parameters, public_key, private_key.expose(), &context.to_vec()){
+        Ok(proof) => Ok(proof),
+        Err(_e) => Err(DeckCustomError::InvalidProof)
+    }
+}
+
+pub(crate) fn compute_reveal_tokens(parameters: &Parameters, public_key: &PublicKey, private_key: &SecretScalar, masked_cards: &[MaskedCard]) -> Result<Vec<(RevealToken, RevealProof)>, DeckCustomError> {
+    let rng = &mut thread_rng();
+    let mut reveal_tokens = Vec::with_capacity(masked_cards.len());
+    for masked_card in masked_cards {
+        // the proof nonce must not come from the public seed
+        match CardProtocol::compute_reveal_token(rng, parameters, private_key.expose(), public_key, masked_card){
+            Ok(reveal_token) => reveal_tokens.push(reveal_token),
+            Err(_e) => return Err(DeckCustomError::GenericError(String::from("Internal")))
+        }
+    }
+    Ok(reveal_tokens)
+}
+
+pub(crate) fn deal_key_shares(parameters: &Parameters, private_key: &SecretScalar, game_user_id: &str, game_id: &str, threshold: usize, recipients: &[ShareRecipient]) -> Result<KeyDealing, DeckCustomError> {
+    vss::deal(&mut thread_rng(), parameters.enc_parameters.generator, private_key, game_id, game_user_id, threshold, recipients)
+}
+
+pub(crate) fn diffie_hellman(private_key: &SecretScalar, point: &PublicKey) -> Result<Zeroizing<Vec<u8>>, DeckCustomError> {
+    let shared_point = point.mul(private_key.expose().into_repr()).into_affine();
+    Ok(Zeroizing::new(vss::serialize_point(&shared_point)?))
+}
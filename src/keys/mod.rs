@@ -0,0 +1,6 @@
+pub mod envelope;
+pub mod key_store;
+pub mod derived_key_store;
+pub mod secret;
+pub mod software_key_store;
+pub mod vss;
@@ -0,0 +1,57 @@
+use ark_serialize::{CanonicalSerialize, CanonicalDeserialize};
+use zeroize::{Zeroize, Zeroizing};
+use crate::deck::errors::DeckCustomError;
+
+type PrivateKey = starknet_curve::Fr;
+
+// A player's private scalar. It is wiped when dropped and has no Clone, Debug or
+// Serialize, so the only way to use it is to borrow it for the crypto call at hand.
+pub struct SecretScalar(PrivateKey);
+
+impl SecretScalar {
+    // wrap a freshly generated key right away, the scalar is Copy so the caller's
+    // binding should not outlive the call
+    pub fn new(scalar: PrivateKey) -> Self {
+        SecretScalar(scalar)
+    }
+
+    pub fn expose(&self) -> &PrivateKey {
+        &self.0
+    }
+
+    pub fn to_bytes(&self) -> Result<Zeroizing<Vec<u8>>, DeckCustomError> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(self.0.serialized_size()));
+        match self.0.serialize(&mut *bytes) {
+            Ok(()) => Ok(bytes),
+            Err(e) => Err(DeckCustomError::SerializationError(e.to_string())),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DeckCustomError> {
+        match PrivateKey::deserialize(bytes) {
+            Ok(scalar) => Ok(SecretScalar(scalar)),
+            Err(e) => Err(DeckCustomError::SerializationError(e.to_string())),
+        }
+    }
+}
+
+impl Drop for SecretScalar {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use ark_ff::UniformRand;
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let scalar = PrivateKey::rand(&mut rand::thread_rng());
+        let secret = SecretScalar::new(scalar);
+        let restored = SecretScalar::from_bytes(&secret.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.expose(), &scalar);
+        assert!(SecretScalar::from_bytes(&[1, 2]).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+use barnett_smart_card_protocol::BarnettSmartProtocol;
+use rand::thread_rng;
+use crate::deck::errors::DeckCustomError;
+use crate::game_user::models::game_user::GameUser;
+use crate::game_user::repository::GameUserMemTrait;
+use crate::keys::key_store::{self, KeyStore};
+use crate::keys::secret::SecretScalar;
+use crate::keys::vss::{KeyDealing, ShareRecipient};
+use zeroize::Zeroizing;
+
+type Curve = starknet_curve::Projective;
+type CardProtocol = barnett_smart_card_protocol::discrete_log_cards::DLCards<Curve>;
+type Parameters = <CardProtocol as BarnettSmartProtocol>::Parameters;
+type PublicKey = <CardProtocol as BarnettSmartProtocol>::PlayerPublicKey;
+type MaskedCard = barnett_smart_card_protocol::discrete_log_cards::MaskedCard<Curve>;
+type RevealToken = barnett_smart_card_protocol::discrete_log_cards::RevealToken<Curve>;
+type ZKProofKeyOwnership = <CardProtocol as BarnettSmartProtocol>::ZKProofKeyOwnership;
+type RevealProof = <CardProtocol as BarnettSmartProtocol>::ZKProofReveal;
+
+// In-process key store, keys live in the GameUser records of the repository
+// (envelope encrypted when the repository is persistent).
+pub struct SoftwareKeyStore {
+    game_users: Arc<dyn GameUserMemTrait>,
+}
+
+impl SoftwareKeyStore {
+    pub fn new(game_users: Arc<dyn GameUserMemTrait>) -> Self {
+        SoftwareKeyStore {
+            game_users,
+        }
+    }
+}
+
+fn stored_key(game_user: &GameUser) -> Result<&SecretScalar, DeckCustomError> {
+    match &game_user.private_key {
+        Some(private_key) => Ok(private_key),
+        None => Err(DeckCustomError::KeyEncryption(format!("no private key stored for {}", game_user.game_user_id))),
+    }
+}
+
+#[async_trait]
+impl KeyStore for SoftwareKeyStore {
+    async fn keygen(&self, parameters: &Parameters, game_id: &str, game_user_id: &str, user_id: &str, _seed_hex: &str) -> Result<PublicKey, DeckCustomError> {
+        let (pk, sk) = match CardProtocol::player_keygen(&mut thread_rng(), parameters){
+            Ok((pk, sk)) => (pk, SecretScalar::new(sk)),
+            Err(_e) => return Err(DeckCustomError::InvalidPublicKey)
+        };
+        self.game_users.create(GameUser::new(game_id.to_string(), game_user_id.to_string(), user_id.to_string(), pk, sk)).await?;
+        Ok(pk)
+    }
+
+    async fn prove_key_ownership(&self, parameters: &Parameters, game_user_id: &str, context: &[u8]) -> Result<ZKProofKeyOwnership, DeckCustomError> {
+        let game_user = self.game_users.get_by_id(game_user_id).await?;
+        key_store::prove_key_ownership(parameters, &game_user.public_key, stored_key(&game_user)?, context)
+    }
+
+    async fn compute_reveal_tokens(&self, parameters: &Parameters, game_user_id: &str, masked_cards: &[MaskedCard]) -> Result<Vec<(RevealToken, RevealProof)>, DeckCustomError> {
+        let game_user = self.game_users.get_by_id(game_user_id).await?;
+        key_store::compute_reveal_tokens(parameters, &game_user.public_key, stored_key(&game_user)?, masked_cards)
+    }
+
+    async fn export_public_key(&self, game_user_id: &str) -> Result<PublicKey, DeckCustomError> {
+        Ok(self.game_users.get_by_id(game_user_id).await?.public_key)
+    }
+
+    async fn export_private_key(&self, _parameters: &Parameters, game_user_id: &str) -> Result<SecretScalar, DeckCustomError> {
+        let game_user = self.game_users.get_by_id(game_user_id).await?;
+        Ok(SecretScalar::new(*stored_key(&game_user)?.expose()))
+    }
+
+    async fn deal_key_shares(&self, parameters: &Parameters, game_user_id: &str, game_id: &str, threshold: usize, recipients: &[ShareRecipient]) -> Result<KeyDealing, DeckCustomError> {
+        let game_user = self.game_users.get_by_id(game_user_id).await?;
+        key_store::deal_key_shares(parameters, stored_key(&game_user)?, game_user_id, game_id, threshold, recipients)
+    }
+
+    async fn diffie_hellman(&self, _parameters: &Parameters, game_user_id: &str, point: &PublicKey) -> Result<Zeroizing<Vec<u8>>, DeckCustomError> {
+        let game_user = self.game_users.get_by_id(game_user_id).await?;
+        key_store::diffie_hellman(stored_key(&game_user)?, point)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use ark_ff::UniformRand;
+    use crate::game_user::mem::game_user_mem::GameUserMem;
+
+    #[tokio::test]
+    async fn test_reveal_tokens_verify_against_exported_key() {
+        let rng = &mut thread_rng();
+        let parameters = CardProtocol::setup(rng, 2, 26).unwrap();
+        let key_store = SoftwareKeyStore::new(Arc::new(GameUserMem::new()));
+        let pk = key_store.keygen(&parameters, "game", "player1", "user1", "00").await.unwrap();
+        assert_eq!(key_store.export_public_key("player1").await.unwrap(), pk);
+
+        let card = barnett_smart_card_protocol::discrete_log_cards::Card::<Curve>::rand(rng);
+        let (masked_card, _) = CardProtocol::mask(rng, &parameters, &pk, &card, &starknet_curve::Fr::rand(rng)).unwrap();
+        let tokens = key_store.compute_reveal_tokens(&parameters, "player1", &[masked_card.clone()]).await.unwrap();
+        let (token, proof) = &tokens[0];
+        assert!(CardProtocol::verify_reveal(&parameters, &pk, token, &masked_card, proof).is_ok());
+        assert!(key_store.compute_reveal_tokens(&parameters, "player2", &[masked_card.clone()]).await.is_err());
+    }
+}
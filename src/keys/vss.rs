@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, HashSet};
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ff::{Field, One, PrimeField, UniformRand, Zero};
+use ark_serialize::CanonicalSerialize;
+use proof_essentials::homomorphic_encryption::el_gamal;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroizing;
+use crate::deck::errors::DeckCustomError;
+use crate::keys::envelope;
+use crate::keys::secret::SecretScalar;
+use crate::serialize::fields::{hash_fields, length_prefixed};
+use crate::serialize::serialize::{decode_deck_public_key, encode_public_key};
+
+type Curve = starknet_curve::Projective;
+type Affine = starknet_curve::Affine;
+type Scalar = starknet_curve::Fr;
+type RevealToken = barnett_smart_card_protocol::discrete_log_cards::RevealToken<Curve>;
+
+const SHARE_KEY_DOMAIN: &[u8] = b"deck_agent/key_share/v1";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedShare {
+    pub recipient: String,
+    // evaluation point, the position of the recipient in the game's players plus one
+    pub index: u64,
+    // ephemeral public key of the dealer, the share key is derived from ephemeral * recipient key
+    pub ephemeral_key: String,
+    pub ciphertext: String,
+}
+
+// Feldman dealing of one player's private key. commitments[k] = a_k * G for the coefficients
+// of f(x) = sk + a_1 x + .. + a_{t-1} x^{t-1}, so commitments[0] is the player's public key
+// and every share f(i) can be checked against them without learning anything about sk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyDealing {
+    pub dealer: String,
+    pub threshold: usize,
+    pub commitments: Vec<String>,
+    pub shares: Vec<EncryptedShare>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShareRecipient {
+    pub game_user_id: String,
+    pub index: u64,
+    pub public_key: Affine,
+}
+
+pub fn deal<R: Rng>(rng: &mut R, generator: Affine, secret: &SecretScalar, game_id: &str, dealer: &str, threshold: usize, recipients: &[ShareRecipient]) -> Result<KeyDealing, DeckCustomError> {
+    if threshold == 0 || threshold > recipients.len() {
+        return Err(DeckCustomError::InvalidKeyShare(format!("threshold must be between 1 and {}", recipients.len())))
+    }
+    let mut coefficients = vec![SecretScalar::new(*secret.expose())];
+    for _ in 1..threshold {
+        coefficients.push(SecretScalar::new(Scalar::rand(rng)));
+    }
+    let mut commitments = Vec::with_capacity(threshold);
+    for coefficient in coefficients.iter() {
+        commitments.push(encode_point(generator.mul(coefficient.expose().into_repr()).into_affine())?);
+    }
+
+    let mut shares = Vec::with_capacity(recipients.len());
+    for recipient in recipients {
+        if recipient.index == 0 {
+            return Err(DeckCustomError::InvalidKeyShare(String::from("share index 0 is the secret itself")))
+        }
+        let share = evaluate(&coefficients, recipient.index);
+        let ephemeral = SecretScalar::new(Scalar::rand(rng));
+        let ephemeral_key = generator.mul(ephemeral.expose().into_repr()).into_affine();
+        let shared_point = recipient.public_key.mul(ephemeral.expose().into_repr()).into_affine();
+        let key = share_key(&serialize_point(&shared_point)?, game_id, dealer, &recipient.game_user_id, recipient.index);
+        let ciphertext = envelope::encrypt(&*key, &share.to_bytes()?, &share_aad(game_id, dealer, &recipient.game_user_id, recipient.index))?;
+        shares.push(EncryptedShare {
+            recipient: recipient.game_user_id.clone(),
+            index: recipient.index,
+            ephemeral_key: encode_point(ephemeral_key)?,
+            ciphertext: hex::encode(ciphertext),
+        });
+    }
+    Ok(KeyDealing {
+        dealer: dealer.to_string(),
+        threshold,
+        commitments,
+        shares,
+    })
+}
+
+// public shape of a dealing: committed to the dealer's key, one share per expected recipient
+pub fn check_dealing(dealing: &KeyDealing, dealer_key: &Affine, recipients: &BTreeMap<String, u64>) -> Result<(), DeckCustomError> {
+    if dealing.threshold == 0 || dealing.commitments.len() != dealing.threshold {
+        return Err(DeckCustomError::InvalidKeyShare(format!("dealing of {} must commit to exactly {} coefficients", dealing.dealer, dealing.threshold)))
+    }
+    if decode_deck_public_key(dealing.commitments[0].clone())? != *dealer_key {
+        return Err(DeckCustomError::InvalidKeyShare(format!("dealing of {} does not commit to its public key", dealing.dealer)))
+    }
+    let mut seen = HashSet::new();
+    for share in dealing.shares.iter() {
+        if recipients.get(&share.recipient) != Some(&share.index) || !seen.insert(share.recipient.clone()) {
+            return Err(DeckCustomError::InvalidKeyShare(format!("unexpected share for {} at index {}", share.recipient, share.index)))
+        }
+    }
+    if seen.len() != recipients.len() || dealing.threshold > seen.len() {
+        return Err(DeckCustomError::InvalidKeyShare(format!("dealing of {} does not cover every other player", dealing.dealer)))
+    }
+    Ok(())
+}
+
+// shared_point is the recipient's private key times the share's ephemeral key, serialized
+pub fn open_share(dealing: &KeyDealing, recipient: &str, shared_point: &[u8], game_id: &str) -> Result<(u64, SecretScalar), DeckCustomError> {
+    let share = find_share(dealing, recipient)?;
+    let key = share_key(shared_point, game_id, &dealing.dealer, recipient, share.index);
+    let ciphertext = match hex::decode(&share.ciphertext) {
+        Ok(ciphertext) => ciphertext,
+        Err(_e) => return Err(DeckCustomError::InvalidKeyShare(format!("share of {} is not hex encoded", recipient))),
+    };
+    let plaintext = envelope::decrypt(&*key, &ciphertext, &share_aad(game_id, &dealing.dealer, recipient, share.index))
+        .map_err(|_e| DeckCustomError::InvalidKeyShare(format!("share of {} does not decrypt", recipient)))?;
+    Ok((share.index, SecretScalar::from_bytes(&plaintext)?))
+}
+
+pub fn find_share<'a>(dealing: &'a KeyDealing, recipient: &str) -> Result<&'a EncryptedShare, DeckCustomError> {
+    match dealing.shares.iter().find(|share| share.recipient == recipient) {
+        Some(share) => Ok(share),
+        None => Err(DeckCustomError::InvalidKeyShare(format!("dealing of {} holds no share for {}", dealing.dealer, recipient))),
+    }
+}
+
+pub fn ephemeral_key(share: &EncryptedShare) -> Result<Affine, DeckCustomError> {
+    decode_deck_public_key(share.ephemeral_key.clone())
+}
+
+// f(index) * G, computed from the commitments alone
+pub fn share_public_key(dealing: &KeyDealing, index: u64) -> Result<Affine, DeckCustomError> {
+    let x = Scalar::from(index);
+    let mut power = Scalar::one();
+    let mut sum = Curve::zero();
+    for commitment in dealing.commitments.iter() {
+        sum += decode_deck_public_key(commitment.clone())?.mul(power.into_repr());
+        power *= x;
+    }
+    Ok(sum.into_affine())
+}
+
+pub fn verify_share(generator: Affine, dealing: &KeyDealing, index: u64, share: &SecretScalar) -> Result<bool, DeckCustomError> {
+    Ok(generator.mul(share.expose().into_repr()).into_affine() == share_public_key(dealing, index)?)
+}
+
+// sum of lambda_i * token_i with the Lagrange coefficients at 0, equal to the token the
+// dealer's own key would have produced as long as every partial token is valid
+pub fn combine_partial_tokens(partial_tokens: &[(u64, RevealToken)]) -> Result<RevealToken, DeckCustomError> {
+    let indices: Vec<Scalar> = partial_tokens.iter().map(|(index, _)| Scalar::from(*index)).collect();
+    let mut seen = HashSet::new();
+    if partial_tokens.iter().any(|(index, _)| *index == 0 || !seen.insert(*index)) {
+        return Err(DeckCustomError::InvalidKeyShare(String::from("partial tokens need distinct non zero indices")))
+    }
+    let mut token = Curve::zero();
+    for (i, (_, partial_token)) in partial_tokens.iter().enumerate() {
+        let mut lambda = Scalar::one();
+        for (j, x_j) in indices.iter().enumerate() {
+            if i != j {
+                lambda *= *x_j * (*x_j - indices[i]).inverse().unwrap();
+            }
+        }
+        token += partial_token.0.mul(lambda.into_repr());
+    }
+    Ok(el_gamal::Plaintext(token.into_affine()))
+}
+
+fn evaluate(coefficients: &[SecretScalar], index: u64) -> SecretScalar {
+    let x = Scalar::from(index);
+    let mut value = Scalar::zero();
+    for coefficient in coefficients.iter().rev() {
+        value = value * x + coefficient.expose();
+    }
+    SecretScalar::new(value)
+}
+
+fn share_key(shared_point: &[u8], game_id: &str, dealer: &str, recipient: &str, index: u64) -> Zeroizing<[u8; 32]> {
+    Zeroizing::new(hash_fields(&[SHARE_KEY_DOMAIN, shared_point, &share_aad(game_id, dealer, recipient, index)]))
+}
+
+// binds a share to its game, dealer, recipient and index
+fn share_aad(game_id: &str, dealer: &str, recipient: &str, index: u64) -> Vec<u8> {
+    length_prefixed(&[game_id.as_bytes(), dealer.as_bytes(), recipient.as_bytes(), &index.to_le_bytes()])
+}
+
+pub fn serialize_point(point: &Affine) -> Result<Vec<u8>, DeckCustomError> {
+    let mut bytes = Vec::new();
+    match point.serialize(&mut bytes) {
+        Ok(()) => Ok(bytes),
+        Err(e) => Err(DeckCustomError::SerializationError(e.to_string())),
+    }
+}
+
+fn encode_point(point: Affine) -> Result<String, DeckCustomError> {
+    encode_public_key(point).map_err(|e| DeckCustomError::SerializationError(e.to_string()))
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use rand::thread_rng;
+
+    fn player(rng: &mut impl Rng, generator: Affine, game_user_id: &str, index: u64) -> (ShareRecipient, SecretScalar) {
+        let sk = SecretScalar::new(Scalar::rand(rng));
+        let public_key = generator.mul(sk.expose().into_repr()).into_affine();
+        (ShareRecipient { game_user_id: game_user_id.to_string(), index, public_key }, sk)
+    }
+
+    #[test]
+    fn test_threshold_of_shares_recovers_reveal_token() {
+        let rng = &mut thread_rng();
+        let generator = Curve::prime_subgroup_generator().into_affine();
+        let (dealer, dealer_sk) = player(rng, generator, "dealer", 1);
+        let others: Vec<(ShareRecipient, SecretScalar)> = (2..5).map(|i| player(rng, generator, &format!("player{}", i), i)).collect();
+        let recipients: Vec<ShareRecipient> = others.iter().map(|(recipient, _)| recipient.clone()).collect();
+
+        let dealing = deal(rng, generator, &dealer_sk, "game", "dealer", 2, &recipients).unwrap();
+        let expected: BTreeMap<String, u64> = recipients.iter().map(|r| (r.game_user_id.clone(), r.index)).collect();
+        check_dealing(&dealing, &dealer.public_key, &expected).unwrap();
+
+        let c1 = Curve::rand(rng).into_affine();
+        let mut partial_tokens = Vec::new();
+        for (recipient, sk) in others.iter().take(2) {
+            let share = find_share(&dealing, &recipient.game_user_id).unwrap();
+            let shared_point = ephemeral_key(share).unwrap().mul(sk.expose().into_repr()).into_affine();
+            let (index, share) = open_share(&dealing, &recipient.game_user_id, &serialize_point(&shared_point).unwrap(), "game").unwrap();
+            assert!(verify_share(generator, &dealing, index, &share).unwrap());
+            assert!(!verify_share(generator, &dealing, index + 1, &share).unwrap());
+            partial_tokens.push((index, el_gamal::Plaintext(c1.mul(share.expose().into_repr()).into_affine())));
+        }
+        let token = combine_partial_tokens(&partial_tokens).unwrap();
+        assert_eq!(token.0, c1.mul(dealer_sk.expose().into_repr()).into_affine());
+
+        // a share opened with the wrong key or under another game is rejected
+        let share = find_share(&dealing, "player2").unwrap();
+        let wrong_point = ephemeral_key(share).unwrap().mul(dealer_sk.expose().into_repr()).into_affine();
+        assert!(open_share(&dealing, "player2", &serialize_point(&wrong_point).unwrap(), "game").is_err());
+    }
+}
@@ -0,0 +1,31 @@
+#[macro_use]
+extern crate rocket;
+
+pub mod deck;
+pub mod serialize;
+pub mod card;
+pub mod game_user;
+pub mod game;
+pub mod keys;
+
+pub mod user {
+    pub mod models {
+        pub mod user;
+        pub mod use_case;
+    }
+    pub mod routes;
+    pub mod service;
+    pub mod errors;
+    pub mod repository;
+    pub mod db {
+        pub mod mock;
+        pub mod mongo;
+    }
+    pub mod mem{
+        pub mod user_memory;
+    }
+}
+
+pub mod core {
+    pub mod api_response;
+}
@@ -1,34 +1,8 @@
-mod deck;
-mod serialize;
-mod card;
-mod game_user;
-
 #[macro_use]
 extern crate rocket;
 
-pub mod user {
-    pub mod models {
-        pub mod user;
-        pub mod use_case;
-    }
-    pub mod routes;
-    pub mod service;
-    pub mod errors;
-    pub mod repository;
-    pub mod db {
-        pub mod mock;
-        pub mod mongo;
-    }
-    pub mod mem{
-        pub mod user_memory;
-    }
-}
-
-pub mod core {
-    pub mod api_response;
-}
-
 use std::future::IntoFuture;
+use example_api::{deck, user};
 use user::mem::user_memory::UserMem;
 use user::db::mongo::user_mongo::UserMongo;
 
@@ -41,7 +15,17 @@ use deck::service::{
     DeckService,
     DeckServiceTrait,
 };
-use crate::game_user::mem::game_user_mem::GameUserMem;
+use example_api::game_user::db::mongo::game_user_mongo::GameUserMongo;
+use example_api::game_user::mem::game_user_mem::GameUserMem;
+use example_api::game_user::repository::GameUserMemTrait;
+use example_api::game::db::mongo::game_mongo::GameMongo;
+use example_api::game::mem::game_mem::GameMem;
+use example_api::game::repository::GameDbTrait;
+use example_api::keys::envelope::MasterKeyRing;
+use example_api::keys::derived_key_store::DerivedKeyStore;
+use example_api::keys::key_store::KeyStore;
+use example_api::keys::software_key_store::SoftwareKeyStore;
+use std::sync::Arc;
 
 #[launch]
 async fn rocket() -> _ {
@@ -57,10 +41,30 @@ async fn rocket() -> _ {
     let user_mongo_repo = UserMongo::new(&mongo_uri, &mongo_db_name).await.unwrap();
 
 
-    let game_user_mem_repo = Box::new(GameUserMem::new());
+    // player keys and game state survive a restart in the middle of a hand,
+    // DECK_STORE=memory keeps them in process for local runs
+    let (game_user_repo, game_repo): (Arc<dyn GameUserMemTrait>, Box<dyn GameDbTrait>) = match std::env::var("DECK_STORE").as_deref() {
+        Ok("memory") => (Arc::new(GameUserMem::new()), Box::new(GameMem::new())),
+        _ => {
+            // private keys are stored envelope encrypted, see keys::envelope for the key format
+            let master_keys = Arc::new(MasterKeyRing::from_env().unwrap());
+            let game_user_mongo = GameUserMongo::new(&mongo_uri, &mongo_db_name, master_keys).await.unwrap();
+            game_user_mongo.rewrap_private_keys().await.unwrap();
+            (
+                Arc::new(game_user_mongo),
+                Box::new(GameMongo::new(&mongo_uri, &mongo_db_name).await.unwrap()),
+            )
+        }
+    };
 
     let user_service: Box<dyn UserServiceTrait> = Box::new(UserService::new(Box::new(user_mongo_repo)));
-    let deck_service: Box<dyn DeckServiceTrait> = Box::new(DeckService::new(game_user_mem_repo));
+    // with a master seed player keys are derived instead of stored
+    let key_store: Box<dyn KeyStore> = match DerivedKeyStore::seed_from_env().unwrap() {
+        Some(master_seed) => Box::new(DerivedKeyStore::new(master_seed, game_user_repo.clone()).unwrap()),
+        None => Box::new(SoftwareKeyStore::new(game_user_repo.clone())),
+    };
+    let deck_service: Arc<dyn DeckServiceTrait> = Arc::new(DeckService::new(game_user_repo, game_repo, key_store));
+    deck::sweeper::spawn(deck_service.clone());
 
     rocket::build()
         .manage(user_service)
@@ -69,6 +73,7 @@ async fn rocket() -> _ {
         .mount("/", routes![user::routes::delete])
         .manage(deck_service)
         .mount("/",routes![deck::routes::initialize])
+        .mount("/",routes![deck::routes::initialize_with_seed])
         .mount("/",routes![deck::routes::setup])
         .mount("/",routes![deck::routes::compute_aggregate_key])
         .mount("/",routes![deck::routes::mask])
@@ -76,4 +81,25 @@ async fn rocket() -> _ {
         .mount("/",routes![deck::routes::verify_shuffle])
         .mount("/",routes![deck::routes::reveal_token])
         .mount("/",routes![deck::routes::peek_cards])
+        .mount("/",routes![deck::routes::verify_mask])
+        .mount("/",routes![deck::routes::verify_reveal_tokens])
+        .mount("/",routes![deck::routes::reveal_cards])
+        .mount("/",routes![deck::routes::open_cards])
+        .mount("/",routes![deck::routes::remask])
+        .mount("/",routes![deck::routes::verify_remask])
+        .mount("/",routes![deck::routes::parameters_cache_stats])
+        .mount("/",routes![deck::routes::batch_verify])
+        .mount("/",routes![deck::routes::verify_shuffle_chain])
+        .mount("/",routes![deck::routes::deal])
+        .mount("/",routes![deck::routes::showdown])
+        .mount("/",routes![deck::routes::finish_game])
+        .mount("/",routes![deck::routes::disclose_key])
+        .mount("/",routes![deck::routes::audit_game])
+        .mount("/",routes![deck::routes::transcript])
+        .mount("/",routes![deck::routes::seed_commit])
+        .mount("/",routes![deck::routes::seed_reveal])
+        .mount("/",routes![deck::routes::seed_combine])
+        .mount("/",routes![deck::routes::verify_key_shares])
+        .mount("/",routes![deck::routes::partial_reveal_tokens])
+        .mount("/",routes![deck::routes::recover_reveal_tokens])
 }
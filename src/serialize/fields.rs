@@ -0,0 +1,33 @@
+use blake2::{Blake2s, Digest};
+
+// Every field goes in behind its length as a u64, so two different field lists
+// never encode to the same bytes. Hashed contexts, AEAD data and key derivations
+// all bind their inputs this way.
+pub fn length_prefixed(fields: &[&[u8]]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(fields.iter().map(|field| 8 + field.len()).sum());
+    for field in fields {
+        bytes.extend_from_slice(&(field.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(field);
+    }
+    bytes
+}
+
+// Blake2s over the length prefixed fields
+pub fn hash_fields(fields: &[&[u8]]) -> [u8; 32] {
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&Blake2s::digest(&length_prefixed(fields)));
+    digest
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_field_boundaries_are_kept() {
+        assert_ne!(length_prefixed(&[b"ab", b"c"]), length_prefixed(&[b"a", b"bc"]));
+        assert_ne!(hash_fields(&[b"ab", b"c"]), hash_fields(&[b"a", b"bc"]));
+        assert_ne!(hash_fields(&[b"abc"]), hash_fields(&[b"abc", b""]));
+        assert_eq!(length_prefixed(&[b"ab"]), [2, 0, 0, 0, 0, 0, 0, 0, b'a', b'b']);
+    }
+}
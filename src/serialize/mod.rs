@@ -1,2 +1,3 @@
 pub mod serialize;
 pub mod proof;
+pub mod fields;
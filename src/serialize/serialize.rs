@@ -25,7 +25,6 @@ type ZKProofKeyOwnership = schnorr_identification::proof::Proof<Curve>;
 
 type Parameters = <DLCards<ark_ec::short_weierstrass_jacobian::GroupProjective<StarkwareParameters>> as BarnettSmartProtocol>::Parameters;
 type PublicKey = GroupAffine<StarkwareParameters>;
-type PrivateKey = <ark_ec::short_weierstrass_jacobian::GroupAffine<StarkwareParameters> as AffineCurve>::ScalarField;
 
 
 
@@ -37,6 +36,8 @@ use proof_essentials::zkp::{
 };
 use proof_essentials::zkp::arguments::shuffle::proof::Proof;
 use crate::deck::errors::DeckCustomError;
+use crate::keys::secret::SecretScalar;
+use zeroize::Zeroizing;
 
 // type ZKProofShuffle = shuffle::proof::Proof<Fr, Self::Enc, Self::Comm>;
 type ZKShuffleProof =  Proof<Fr, proof_essentials::homomorphic_encryption::el_gamal::ElGamal<Curve>, proof_essentials::vector_commitment::pedersen::PedersenCommitment<Curve>>;
@@ -160,6 +161,18 @@ pub fn decode_revel_proof(proof_hex :String)->Result<RevealProof, DeckCustomErro
     Ok(restored_result)
 }
 
+pub fn encode_private_key(sk :&SecretScalar)->Result<String, DeckCustomError>{
+    Ok(hex::encode(&*sk.to_bytes()?))
+}
+
+pub fn decode_private_key(sk_hex :String)->Result<SecretScalar, DeckCustomError>{
+    let bytes = match Vec::from_hex(sk_hex){
+        Ok(bytes) => Zeroizing::new(bytes),
+        Err(e)    => return Err(DeckCustomError::SerializationError(e.to_string()))
+    };
+    SecretScalar::from_bytes(&bytes)
+}
+
 pub fn encode_initial_card(card :Card)->Result<String, SerializationError>{
     let mut bytes = Vec::new();
     card.serialize(&mut bytes)?;